@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use crate::intcode::Error;
+use crate::intcode2::{IntcodeComputer, Signal};
+
+/// Everything a program printed between two points where it stopped.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Screen {
+    /// The text, split into lines. Prompts that were answered from the
+    /// queue are included.
+    pub lines: Vec<String>,
+    /// A value that isn't printable ASCII or a newline, such as a puzzle
+    /// answer printed after the text.
+    pub answer: Option<isize>,
+    /// The prompt the program is waiting at, if it is waiting for input.
+    pub prompt: Option<String>,
+    pub halted: bool
+}
+
+/// A line ending in `?` or `:` printed just before a program asks for input,
+/// such as `Command?` or `Input instructions:`.
+pub fn is_prompt(line: &str) -> bool {
+    line.ends_with('?') || line.ends_with(':')
+}
+
+/// Adapter for programs that talk in lines of ASCII. Input is queued a line
+/// at a time, and each line is only sent once the program asks for input.
+pub struct Terminal {
+    comp: IntcodeComputer,
+    queue: VecDeque<String>,
+    // text of the line being printed
+    partial: String
+}
+
+impl Terminal {
+    pub fn new(program: &[isize]) -> Terminal {
+        Terminal { comp: IntcodeComputer::new(program), queue: VecDeque::new(), partial: String::new() }
+    }
+
+    pub fn computer(&self) -> &IntcodeComputer {
+        &self.comp
+    }
+
+    pub fn computer_mut(&mut self) -> &mut IntcodeComputer {
+        &mut self.comp
+    }
+
+    /// Queues `line`, which shouldn't contain a newline.
+    pub fn send_line(&mut self, line: &str) {
+        self.queue.push_back(line.to_string());
+    }
+
+    /// Queues each line of `text`.
+    pub fn send_lines(&mut self, text: &str) {
+        text.lines().for_each(|line| self.send_line(line));
+    }
+
+    fn end_line(&mut self, screen: &mut Screen) {
+        screen.lines.push(std::mem::take(&mut self.partial));
+    }
+
+    /// Runs until the program halts, or asks for input when nothing is
+    /// queued.
+    pub fn read(&mut self) -> Result<Screen, Error> {
+        let mut screen = Screen::default();
+        loop {
+            match self.comp.run()? {
+                Signal::Output(10) => self.end_line(&mut screen),
+                Signal::Output(c @ 32..=126) => self.partial.push(c as u8 as char),
+                Signal::Output(value) => screen.answer = Some(value),
+                Signal::ExpectingInput => {
+                    if !self.partial.is_empty() {
+                        self.end_line(&mut screen);
+                    }
+                    match self.queue.pop_front() {
+                        Some(line) => self.comp.send_ascii(&format!("{}\n", line)),
+                        None => {
+                            screen.prompt = screen.lines.last().filter(|line| is_prompt(line)).cloned();
+                            return Ok(screen);
+                        }
+                    }
+                },
+                _ => {
+                    if !self.partial.is_empty() {
+                        self.end_line(&mut screen);
+                    }
+                    screen.halted = true;
+                    return Ok(screen);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn test_prompts() -> Result<(), Error> {
+        // asks for two characters, echoes the first and prints their sum
+        let program = assemble("
+                out #'H'
+                out #'i'
+                out #10
+                out #'?'
+                out #10
+                in -> [a]
+                in -> [nl]
+                out #'o'
+                out #'k'
+                out #':'
+                out #10
+                in -> [b]
+                in -> [nl]
+                out [a]
+                add [a], [b] -> [a]
+                out [a]
+                hlt
+            a: db 0
+            b: db 0
+            nl: db 0
+        ").unwrap();
+
+        let mut term = Terminal::new(&program);
+        let screen = term.read()?;
+        assert_eq!(screen.lines, vec!["Hi", "?"]);
+        assert_eq!(screen.prompt.as_deref(), Some("?"));
+
+        term.send_lines("A\nB\n");
+        let screen = term.read()?;
+        assert_eq!(screen, Screen {
+            lines: vec!["ok:".to_string(), "A".to_string()],
+            answer: Some(131),
+            prompt: None,
+            halted: true
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_answers() -> Result<(), Error> {
+        // small answers are control characters, they mustn't end up in the text
+        let program = assemble("
+                out #'x'
+                out #10
+                out #7
+                hlt
+        ").unwrap();
+        let screen = Terminal::new(&program).read()?;
+        assert_eq!(screen.lines, vec!["x"]);
+        assert_eq!(screen.answer, Some(7));
+        Ok(())
+    }
+}
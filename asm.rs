@@ -0,0 +1,21 @@
+use std::fs;
+use std::io;
+use std::process;
+use common::assembler;
+
+const USAGE: &str = "usage: asm <source>";
+
+fn main() -> Result<(), io::Error> {
+    let path = std::env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("{}", USAGE);
+        process::exit(1)
+    });
+    match assembler::assemble(&fs::read_to_string(path)?) {
+        Ok(program) => println!("{}", assembler::format(&program)),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::intcode::{Mode, Opcode};
+use crate::disassembler::{Instruction, Operand};
+
+/// Assembler errors, each carrying the (one based) source line.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Syntax(usize, String),
+    UnknownMnemonic(usize, String),
+    WrongOperandCount(usize, usize),
+    ImmediateOutput(usize),
+    UnknownLabel(usize, String),
+    DuplicateLabel(usize, String),
+    AddressMismatch(usize, usize),
+    UnterminatedMacro(usize),
+    MacroRecursion(usize)
+}
+
+impl Error {
+    pub fn line(&self) -> usize {
+        match *self {
+            Error::Syntax(line, _) | Error::UnknownMnemonic(line, _) | Error::WrongOperandCount(line, _) |
+                Error::ImmediateOutput(line) | Error::UnknownLabel(line, _) | Error::DuplicateLabel(line, _) |
+                Error::AddressMismatch(line, _) | Error::UnterminatedMacro(line) | Error::MacroRecursion(line) => line
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "line {}: ", self.line())?;
+        match self {
+            Error::Syntax(_, msg) => write!(f, "{}", msg),
+            Error::UnknownMnemonic(_, name) => write!(f, "unknown instruction {}", name),
+            Error::WrongOperandCount(_, count) => write!(f, "expected {} operands", count),
+            Error::ImmediateOutput(_) => write!(f, "can't write to an immediate"),
+            Error::UnknownLabel(_, label) => write!(f, "unknown label {}", label),
+            Error::DuplicateLabel(_, label) => write!(f, "label {} is already defined", label),
+            Error::AddressMismatch(_, addr) => write!(f, "not at address {:04}", addr),
+            Error::UnterminatedMacro(_) => write!(f, "macro without .endm"),
+            Error::MacroRecursion(_) => write!(f, "macros nested more than {} deep", MAX_MACRO_DEPTH)
+        }
+    }
+}
+
+const MAX_MACRO_DEPTH: usize = 32;
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(isize),
+    Label(String, isize)
+}
+
+#[derive(Debug)]
+enum Item {
+    Instruction(Opcode, Vec<(Mode, Expr)>),
+    Data(Vec<Expr>)
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn is_ident(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(is_ident_char) && !s.starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn strip_comment(line: &str) -> &str {
+    // a ';' inside a string or character literal isn't a comment
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(_), '\\') if !escaped => { escaped = true; continue },
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, ';') => return &line[..i],
+            _ => ()
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// Splits on top level commas, leaving commas in string literals alone.
+fn split_operands(s: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for c in s.chars() {
+        match (quote, c) {
+            (Some(_), '\\') if !escaped => { current.push(c); escaped = true; continue },
+            (Some(q), c) if c == q && !escaped => { quote = None; current.push(c) },
+            (None, '"') | (None, '\'') => { quote = Some(c); current.push(c) },
+            (None, ',') => parts.push(std::mem::take(&mut current)),
+            _ => current.push(c)
+        }
+        escaped = false;
+    }
+    parts.push(current);
+    parts.into_iter().map(|p| p.trim().to_string()).collect()
+}
+
+fn unescape(line: usize, s: &str) -> Result<Vec<isize>, Error> {
+    let mut values = vec![];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(c @ '\\') | Some(c @ '"') | Some(c @ '\'') => c,
+                _ => return Err(Error::Syntax(line, format!("bad escape in {}", s)))
+            }
+        } else {
+            c
+        };
+        values.push(c as isize);
+    }
+    Ok(values)
+}
+
+fn parse_expr(line: usize, s: &str) -> Result<Expr, Error> {
+    let s = s.trim();
+    if let Ok(v) = s.parse::<isize>() {
+        return Ok(Expr::Number(v));
+    }
+    if s.len() >= 3 && s.starts_with('\'') && s.ends_with('\'') {
+        let value = unescape(line, &s[1..s.len() - 1])?;
+        if value.len() == 1 {
+            return Ok(Expr::Number(value[0]));
+        }
+    }
+    let (label, offset) = match s.rfind(['+', '-']) {
+        Some(i) if i > 0 => {
+            let offset = s[i + 1..].trim().parse::<isize>()
+                .map_err(|_| Error::Syntax(line, format!("bad offset in {}", s)))?;
+            (s[..i].trim(), if &s[i..=i] == "-" { -offset } else { offset })
+        },
+        _ => (s, 0)
+    };
+    if is_ident(label) {
+        Ok(Expr::Label(label.to_string(), offset))
+    } else {
+        Err(Error::Syntax(line, format!("bad expression: {}", s)))
+    }
+}
+
+fn parse_operand(line: usize, s: &str) -> Result<(Mode, Expr), Error> {
+    if let Some(rest) = s.strip_prefix('#') {
+        return Ok((Mode::Immediate, parse_expr(line, rest)?));
+    }
+    if s.starts_with('[') && s.ends_with(']') {
+        let inner = s[1..s.len() - 1].trim();
+        if inner == "rb" {
+            return Ok((Mode::Relative, Expr::Number(0)));
+        }
+        if let Some(rest) = inner.strip_prefix("rb") {
+            let rest = rest.trim_start();
+            if let Some(offset) = rest.strip_prefix('+') {
+                return Ok((Mode::Relative, parse_expr(line, offset)?));
+            }
+            if rest.starts_with('-') {
+                return Ok((Mode::Relative, parse_expr(line, rest)?));
+            }
+        }
+        return Ok((Mode::Position, parse_expr(line, inner)?));
+    }
+    Err(Error::Syntax(line, format!("bad operand: {}", s)))
+}
+
+fn mnemonic(name: &str) -> Option<Opcode> {
+    Opcode::ALL.iter().cloned().find(|op| op.mnemonic().eq_ignore_ascii_case(name))
+}
+
+/// Replaces whole identifiers that name macro parameters, and `@` with a
+/// suffix unique to this expansion so that labels in macros don't clash.
+fn substitute(line: &str, args: &HashMap<&str, &str>, expansion: usize) -> String {
+    let mut out = String::new();
+    let mut ident = String::new();
+    let flush = |ident: &mut String, out: &mut String| {
+        out.push_str(args.get(ident.as_str()).cloned().unwrap_or(ident));
+        ident.clear();
+    };
+    for c in line.chars() {
+        if is_ident_char(c) {
+            ident.push(c);
+            continue;
+        }
+        flush(&mut ident, &mut out);
+        if c == '@' {
+            out.push_str(&format!("_{}", expansion));
+        } else {
+            out.push(c);
+        }
+    }
+    flush(&mut ident, &mut out);
+    out
+}
+
+/// Collects `.macro name params ... .endm` definitions, and expands every
+/// use of them. Returns the remaining lines paired with their line numbers.
+fn expand_macros(source: &str) -> Result<Vec<(usize, String)>, Error> {
+    let mut macros = HashMap::new();
+    let mut lines = vec![];
+    let mut source_lines = source.lines().enumerate().map(|(n, l)| (n + 1, strip_comment(l).trim()));
+    while let Some((n, line)) = source_lines.next() {
+        match line.strip_prefix(".macro") {
+            Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => {
+                let mut words = rest.trim().splitn(2, char::is_whitespace);
+                let name = words.next().filter(|name| is_ident(name))
+                    .ok_or_else(|| Error::Syntax(n, "expected a macro name".to_string()))?
+                    .to_lowercase();
+                let params = words.next().map(split_operands).unwrap_or_default()
+                    .into_iter().filter(|p| !p.is_empty()).collect();
+                let mut body = vec![];
+                loop {
+                    match source_lines.next() {
+                        Some((_, ".endm")) => break,
+                        Some((_, line)) => body.push(line.to_string()),
+                        None => return Err(Error::UnterminatedMacro(n))
+                    }
+                }
+                macros.insert(name, Macro { params, body });
+            },
+            _ => lines.push((n, line.to_string()))
+        }
+    }
+
+    let mut expansions = 0;
+    let mut todo = lines.into_iter().rev().map(|(n, l)| (n, l, 0)).collect::<Vec<_>>();
+    let mut expanded = vec![];
+    while let Some((n, line, depth)) = todo.pop() {
+        let (labels, statement) = split_labels(&line);
+        let mut words = statement.splitn(2, char::is_whitespace);
+        let name = words.next().unwrap_or("").to_lowercase();
+        let m = match macros.get(&name) {
+            Some(m) => m,
+            None => {
+                expanded.push((n, line));
+                continue;
+            }
+        };
+        if depth >= MAX_MACRO_DEPTH {
+            return Err(Error::MacroRecursion(n));
+        }
+        let args = words.next().map(split_operands).unwrap_or_default()
+            .into_iter().filter(|a| !a.is_empty()).collect::<Vec<_>>();
+        if args.len() != m.params.len() {
+            return Err(Error::Syntax(n, format!("{} expects {} arguments", name, m.params.len())));
+        }
+        expansions += 1;
+        let args = m.params.iter().map(|p| p.as_str()).zip(args.iter().map(|a| a.as_str())).collect();
+        if !labels.is_empty() {
+            expanded.push((n, labels.to_string()));
+        }
+        for body_line in m.body.iter().rev() {
+            todo.push((n, substitute(body_line, &args, expansions), depth + 1));
+        }
+    }
+    Ok(expanded)
+}
+
+/// Splits leading `label:` definitions off a line.
+fn split_labels(line: &str) -> (&str, &str) {
+    let mut rest = line;
+    while let Some(i) = rest.find(':') {
+        if !is_ident(rest[..i].trim()) && rest[..i].trim().parse::<usize>().is_err() {
+            break;
+        }
+        rest = rest[i + 1..].trim_start();
+    }
+    (line[..line.len() - rest.len()].trim(), rest)
+}
+
+/// Assembles `source` into a program.
+///
+/// ```text
+/// ; comments run to the end of the line
+/// .macro push value
+///     add value, #0 -> [rb]
+///     arb #1
+/// .endm
+/// start:  in -> [rb+1]
+///         push #'a'
+///         jnz [rb-1], #start
+///         hlt
+/// message: db "hi\n", 0, start+1
+/// ```
+///
+/// Operands are `#n` (immediate), `[n]` (position) or `[rb+n]` (relative),
+/// where `n` is a number, a character, or a label with an optional offset.
+/// The written operand can be separated with `->` or `,`. A numeric label
+/// such as `0012:` checks the current address, so disassembler listings
+/// assemble back to the original program.
+pub fn assemble(source: &str) -> Result<Vec<isize>, Error> {
+    let mut labels = HashMap::new();
+    let mut items = vec![];
+    let mut addr = 0;
+    for (n, line) in expand_macros(source)? {
+        let (defs, statement) = split_labels(&line);
+        for label in defs.split(':').map(str::trim).filter(|l| !l.is_empty()) {
+            match label.parse::<usize>() {
+                Ok(expected) if expected != addr => return Err(Error::AddressMismatch(n, expected)),
+                Ok(_) => (),
+                Err(_) => if labels.insert(label.to_string(), addr).is_some() {
+                    return Err(Error::DuplicateLabel(n, label.to_string()));
+                }
+            }
+        }
+        if statement.is_empty() {
+            continue;
+        }
+
+        let mut words = statement.splitn(2, char::is_whitespace);
+        let name = words.next().unwrap_or("");
+        let operands = words.next().unwrap_or("").replace("->", ",");
+        let operands = split_operands(&operands).into_iter()
+            .filter(|o| !o.is_empty())
+            .collect::<Vec<_>>();
+
+        let item = if name.eq_ignore_ascii_case("db") {
+            let mut values = vec![];
+            for operand in operands {
+                if operand.len() >= 2 && operand.starts_with('"') && operand.ends_with('"') {
+                    values.extend(unescape(n, &operand[1..operand.len() - 1])?.into_iter().map(Expr::Number));
+                } else {
+                    values.push(parse_expr(n, &operand)?);
+                }
+            }
+            addr += values.len();
+            Item::Data(values)
+        } else {
+            let opcode = mnemonic(name).ok_or_else(|| Error::UnknownMnemonic(n, name.to_string()))?;
+            if operands.len() != opcode.arity() {
+                return Err(Error::WrongOperandCount(n, opcode.arity()));
+            }
+            let operands = operands.iter()
+                .map(|o| parse_operand(n, o))
+                .collect::<Result<Vec<_>, Error>>()?;
+            if opcode.writes() && operands.last().map(|(mode, _)| *mode) == Some(Mode::Immediate) {
+                return Err(Error::ImmediateOutput(n));
+            }
+            addr += 1 + operands.len();
+            Item::Instruction(opcode, operands)
+        };
+        items.push((n, item));
+    }
+
+    let resolve = |n: usize, expr: &Expr| match expr {
+        Expr::Number(v) => Ok(*v),
+        Expr::Label(label, offset) => labels.get(label)
+            .map(|&addr| addr as isize + offset)
+            .ok_or_else(|| Error::UnknownLabel(n, label.clone()))
+    };
+    let mut program = vec![];
+    for (n, item) in items {
+        match item {
+            Item::Instruction(opcode, operands) => {
+                let operands = operands.iter().map(|(mode, expr)| {
+                    let value = resolve(n, expr)?;
+                    Ok(match mode {
+                        Mode::Position => Operand::Position(value),
+                        Mode::Immediate => Operand::Immediate(value),
+                        Mode::Relative => Operand::Relative(value)
+                    })
+                }).collect::<Result<Vec<_>, Error>>()?;
+                program.extend(Instruction { opcode, operands }.encode());
+            },
+            Item::Data(values) => {
+                for expr in &values {
+                    program.push(resolve(n, expr)?);
+                }
+            }
+        }
+    }
+    Ok(program)
+}
+
+/// Formats a program the way `read_program` expects it.
+pub fn format(program: &[isize]) -> String {
+    program.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disassembler;
+
+    #[test]
+    fn test_day_07_example() -> Result<(), Error> {
+        let source = "
+            in -> [phase]
+            in -> [signal]
+            mul [signal], #10 -> [signal]   ; shift the signal left a digit
+            add [signal], [phase] -> [phase]
+            out [phase]
+            hlt
+        phase: db 0
+        signal: db 0
+        ";
+        assert_eq!(assemble(source)?, vec![3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_macros_and_data() -> Result<(), Error> {
+        let source = r#"
+        .macro print value
+            out value
+        .endm
+        .macro countdown from
+            add #from, #0 -> [counter]
+        loop@:
+            print [counter]
+            add [counter], #-1 -> [counter]
+            jnz [counter], #loop@
+        .endm
+            countdown 2
+            countdown 3
+            print #';'
+            hlt
+        counter: db "a,b", 'c'
+        "#;
+        let program = assemble(source)?;
+        assert_eq!(format(&program[program.len() - 4..]), "97,44,98,99");
+        let mut comp = crate::intcode2::IntcodeComputer::new(&program);
+        let mut outputs = vec![];
+        while let crate::intcode2::Signal::Output(v) = comp.run().unwrap() {
+            outputs.push(v);
+        }
+        assert_eq!(outputs, vec![2, 1, 3, 2, 1, ';' as isize]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_operands() -> Result<(), Error> {
+        assert_eq!(assemble("add [rb], [rb-1], [rb+2]")?, vec![22201,0,-1,2]);
+        assert_eq!(assemble("x: jz #0, [rb+x+3]")?, vec![2106,0,3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(assemble("add #1, #2 -> #3"), Err(Error::ImmediateOutput(1)));
+        assert_eq!(assemble("hlt\nfoo #1"), Err(Error::UnknownMnemonic(2, "foo".to_string())));
+        assert_eq!(assemble("out [nowhere]"), Err(Error::UnknownLabel(1, "nowhere".to_string())));
+        assert_eq!(assemble("a: hlt\na: hlt"), Err(Error::DuplicateLabel(2, "a".to_string())));
+        assert_eq!(assemble("out #1, #2"), Err(Error::WrongOperandCount(1, 1)));
+        assert_eq!(assemble("0001: hlt"), Err(Error::AddressMismatch(1, 1)));
+        assert_eq!(assemble(".macro m\nm\n.endm\nm"), Err(Error::MacroRecursion(4)));
+        assert_eq!(assemble("hlt\nfoo #1").unwrap_err().to_string(), "line 2: unknown instruction foo");
+        assert_eq!(assemble("0001: hlt").unwrap_err().to_string(), "line 1: not at address 0001");
+    }
+
+    #[test]
+    fn test_disassembler_round_trip() -> Result<(), Error> {
+        for day in &["09", "13", "25"] {
+            let program = crate::intcode::read_program(&format!("day_{}_input.txt", day)).unwrap();
+            assert_eq!(assemble(&disassembler::listing(&program))?, program);
+        }
+        Ok(())
+    }
+}
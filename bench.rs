@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+use common::compiler::Compiled;
+use common::intcode::{Callbacks, Error, Io, Step, Vm, read_program};
+
+#[derive(Clone, Copy)]
+enum Engine {
+    Uncached,
+    Cached,
+    Compiled
+}
+
+struct Machine {
+    vm: Vm,
+    compiled: Option<Compiled>
+}
+
+impl Machine {
+    fn new(program: &[isize], engine: Engine) -> Machine {
+        let mut vm = Vm::new(program);
+        vm.set_decode_cache(!matches!(engine, Engine::Uncached));
+        let compiled = match engine {
+            Engine::Compiled => Some(Compiled::new(program)),
+            _ => None
+        };
+        Machine { vm, compiled }
+    }
+
+    fn run<I: Io>(&mut self, io: &mut I) -> Result<Step, Error> {
+        match &self.compiled {
+            Some(compiled) => compiled.run(&mut self.vm, io),
+            None => self.vm.run(io)
+        }
+    }
+}
+
+type Bench = fn(&mut Machine) -> Result<isize, Error>;
+
+// BOOST in sensor boost mode
+fn day_09(machine: &mut Machine) -> Result<isize, Error> {
+    let mut input = Some(2);
+    let mut result = 0;
+    machine.run(&mut Callbacks::new(|| input.take(), |v| result = v))?;
+    Ok(result)
+}
+
+// points affected by the tractor beam, one reset per point
+fn day_19(machine: &mut Machine) -> Result<isize, Error> {
+    let mut count = 0;
+    for y in 0..50 {
+        for x in 0..50 {
+            let mut input = vec![y, x];
+            machine.vm.reset();
+            machine.run(&mut Callbacks::new(|| input.pop(), |v| count += v))?;
+        }
+    }
+    Ok(count)
+}
+
+// the camera image, without any input
+fn day_17(machine: &mut Machine) -> Result<isize, Error> {
+    let mut count = 0;
+    machine.run(&mut Callbacks::new(|| None, |_| count += 1))?;
+    Ok(count)
+}
+
+fn time(program: &[isize], engine: Engine, run: Bench) -> Result<(Duration, isize), Error> {
+    let mut machine = Machine::new(program, engine);
+    let start = Instant::now();
+    let result = run(&mut machine)?;
+    Ok((start.elapsed(), result))
+}
+
+fn main() -> Result<(), Error> {
+    let benches: [(&str, Bench); 3] = [
+        ("day_09", day_09),
+        ("day_17", day_17),
+        ("day_19", day_19)
+    ];
+    println!("{:8} {:>12} {:>12} {:>12}", "program", "uncached", "cached", "compiled");
+    for (name, run) in benches.iter() {
+        let program = read_program(&format!("{}_input.txt", name))?;
+        let (uncached, expected) = time(&program, Engine::Uncached, *run)?;
+        let (cached, result) = time(&program, Engine::Cached, *run)?;
+        assert_eq!(result, expected, "{} gives a different result with the cache", name);
+        let (compiled, result) = time(&program, Engine::Compiled, *run)?;
+        assert_eq!(result, expected, "{} gives a different result compiled", name);
+        println!("{:8} {:>12?} {:>12?} {:>12?}", name, uncached, cached, compiled);
+    }
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use crate::disassembler::{self, Instruction, Operand};
+use crate::intcode::Opcode;
+
+/// Where control can go when a block is done.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    Fall(usize),
+    Jump(usize),
+    /// A jump whose target is read from memory, such as a return through
+    /// the relative base, so it can't be followed statically.
+    Unresolved,
+    Halt
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+    pub instructions: Vec<(usize, Instruction)>,
+    pub exits: Vec<Exit>
+}
+
+impl Block {
+    fn exits(last: &Instruction, end: usize) -> Vec<Exit> {
+        let mut exits = vec![];
+        if last.opcode == Opcode::Hlt {
+            exits.push(Exit::Halt);
+        } else if last.falls_through() {
+            exits.push(Exit::Fall(end));
+        }
+        if last.can_jump() {
+            exits.push(match last.operands.get(1) {
+                Some(Operand::Immediate(_)) => last.jump_target().map_or(Exit::Unresolved, Exit::Jump),
+                _ => Exit::Unresolved
+            });
+        }
+        exits
+    }
+
+    pub fn successors(&self) -> impl Iterator<Item = usize> + '_ {
+        self.exits.iter().filter_map(|exit| match exit {
+            Exit::Fall(addr) | Exit::Jump(addr) => Some(*addr),
+            _ => None
+        })
+    }
+}
+
+/// The basic blocks of the code reachable from address 0, keyed by their
+/// first address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>
+}
+
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(instruction.opcode, Opcode::Jnz | Opcode::Jz | Opcode::Hlt)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Cfg {
+    pub fn new(program: &[isize]) -> Cfg {
+        let instructions = disassembler::reachable(program).into_iter()
+            .filter_map(|addr| Some((addr, Instruction::decode(program, addr)?)))
+            .collect::<Vec<_>>();
+        let mut leaders = instructions.iter()
+            .filter_map(|(_, i)| i.jump_target())
+            .collect::<BTreeSet<_>>();
+        leaders.insert(0);
+
+        let mut blocks = BTreeMap::new();
+        let mut current: Option<Block> = None;
+        for (addr, instruction) in instructions {
+            let block = match current.take() {
+                Some(block) if block.end == addr && !leaders.contains(&addr)
+                    && !block.instructions.last().is_some_and(|(_, last)| ends_block(last)) => block,
+                other => {
+                    if let Some(block) = other {
+                        blocks.insert(block.start, block);
+                    }
+                    Block { start: addr, end: addr, instructions: vec![], exits: vec![] }
+                }
+            };
+            let mut block = Block { end: addr + instruction.size(), ..block };
+            block.instructions.push((addr, instruction));
+            current = Some(block);
+        }
+        blocks.extend(current.map(|block| (block.start, block)));
+
+        for block in blocks.values_mut() {
+            let (_, last) = block.instructions.last().unwrap();
+            block.exits = Block::exits(last, block.end);
+        }
+        Cfg { blocks }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph intcode {\n    node [shape=box, fontname=monospace];\n");
+        let mut unresolved = false;
+        for block in self.blocks.values() {
+            let label = block.instructions.iter()
+                .map(|(addr, i)| format!("{:04}: {}\\l", addr, escape(&i.to_string())))
+                .collect::<String>();
+            writeln!(dot, "    b{} [label=\"{}\"];", block.start, label).unwrap();
+            for exit in &block.exits {
+                match exit {
+                    Exit::Fall(addr) => writeln!(dot, "    b{} -> b{};", block.start, addr),
+                    Exit::Jump(addr) => writeln!(dot, "    b{} -> b{} [color=blue];", block.start, addr),
+                    Exit::Unresolved => {
+                        unresolved = true;
+                        writeln!(dot, "    b{} -> unresolved [style=dashed];", block.start)
+                    },
+                    Exit::Halt => Ok(())
+                }.unwrap();
+            }
+        }
+        if unresolved {
+            dot.push_str("    unresolved [shape=diamond, label=\"?\"];\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+        let blocks = self.blocks.values().map(|block| {
+            let instructions = block.instructions.iter()
+                .map(|(addr, i)| format!("{{\"addr\": {}, \"text\": \"{}\"}}", addr, escape(&i.to_string())))
+                .collect::<Vec<_>>();
+            let exits = block.exits.iter().map(|exit| match exit {
+                Exit::Fall(addr) => format!("{{\"kind\": \"fall\", \"target\": {}}}", addr),
+                Exit::Jump(addr) => format!("{{\"kind\": \"jump\", \"target\": {}}}", addr),
+                Exit::Unresolved => "{\"kind\": \"unresolved\"}".to_string(),
+                Exit::Halt => "{\"kind\": \"halt\"}".to_string()
+            }).collect::<Vec<_>>();
+            format!(
+                "    {{\"start\": {}, \"end\": {}, \"instructions\": [{}], \"exits\": [{}]}}",
+                block.start, block.end, instructions.join(", "), exits.join(", ")
+            )
+        }).collect::<Vec<_>>();
+        format!("{{\"blocks\": [\n{}\n]}}\n", blocks.join(",\n"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // stores the return address 7 on the stack and calls 9, which returns through it
+    const CALL: [isize; 12] = [21101,0,7,0,1106,0,9,99,42,2105,1,0];
+
+    #[test]
+    fn test_blocks() {
+        let cfg = Cfg::new(&CALL);
+        let blocks = cfg.blocks.values()
+            .map(|b| (b.start, b.end, b.exits.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, vec![
+            (0, 7, vec![Exit::Jump(9)]),
+            (7, 8, vec![Exit::Halt]),
+            (9, 12, vec![Exit::Unresolved])
+        ]);
+
+        // a conditional jump back to the start of its own block
+        let cfg = Cfg::new(&[3,9,1005,9,0,104,1,99,0,0]);
+        assert_eq!(cfg.blocks[&0].exits, vec![Exit::Fall(5), Exit::Jump(0)]);
+        assert_eq!(cfg.blocks[&5].successors().collect::<Vec<_>>(), vec![] as Vec<usize>);
+    }
+
+    #[test]
+    fn test_dot() {
+        assert_eq!(Cfg::new(&CALL).to_dot(), r#"digraph intcode {
+    node [shape=box, fontname=monospace];
+    b0 [label="0000: ADD #0, #7 -> [rb+0]\l0004: JZ #0, #9\l"];
+    b0 -> b9 [color=blue];
+    b7 [label="0007: HLT\l"];
+    b9 [label="0009: JNZ #1, [rb+0]\l"];
+    b9 -> unresolved [style=dashed];
+    unresolved [shape=diamond, label="?"];
+}
+"#);
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(Cfg::new(&[104,1,99]).to_json(), r#"{"blocks": [
+    {"start": 0, "end": 3, "instructions": [{"addr": 0, "text": "OUT #1"}, {"addr": 2, "text": "HLT"}], "exits": [{"kind": "halt"}]}
+]}
+"#);
+    }
+}
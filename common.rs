@@ -1,3 +1,23 @@
 pub mod intcode;
 pub mod intcode2;
+pub mod disassembler;
+pub mod decompiler;
+pub mod compiler;
+pub mod cfg;
+pub mod profiler;
+pub mod assembler;
+pub mod debugger;
+pub mod trace;
+pub mod snapshot;
+pub mod transcript;
+pub mod fuzz;
+pub mod extension;
+pub mod symbolic;
+pub mod partial;
+pub mod memory;
+pub mod wide;
+pub mod runtime;
+pub mod pipeline;
+pub mod network;
+pub mod ascii;
 pub mod utils;
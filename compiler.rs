@@ -0,0 +1,293 @@
+use std::convert::TryInto;
+use crate::disassembler::{self, Instruction, Operand};
+use crate::intcode::{Arithmetic, Error, Io, Limits, Opcode, Step, Vm};
+
+type Load = Box<dyn Fn(&Vm) -> Result<isize, Error> + Send + Sync>;
+type Store = Box<dyn Fn(&Vm) -> Result<usize, Error> + Send + Sync>;
+type Exec = Box<dyn Fn(&mut Vm, &mut dyn Io) -> Result<Flow, Error> + Send + Sync>;
+
+enum Flow {
+    Next,
+    // the address written to, which could be code further down the block
+    Wrote(usize),
+    Jump(usize),
+    Wait
+}
+
+struct Op {
+    size: usize,
+    // whether the block ends here
+    last: bool,
+    exec: Exec
+}
+
+fn addr(pc: usize, addr: isize) -> Result<usize, Error> {
+    addr.try_into().map_err(|_| Error::IndexOutOfBounds { pc, addr })
+}
+
+fn relative(pc: usize, vm: &Vm, offset: isize) -> Result<usize, Error> {
+    addr(pc, vm.relbase().checked_add(offset).ok_or(Error::ArithmeticOverflow { pc })?)
+}
+
+fn load(pc: usize, operand: Operand) -> Load {
+    match operand {
+        Operand::Immediate(v) => Box::new(move |_| Ok(v)),
+        Operand::Position(a) => Box::new(move |vm| Ok(vm.read(addr(pc, a)?))),
+        Operand::Relative(offset) => Box::new(move |vm| Ok(vm.read(relative(pc, vm, offset)?)))
+    }
+}
+
+fn store(pc: usize, operand: Operand) -> Store {
+    match operand {
+        Operand::Relative(offset) => Box::new(move |vm| relative(pc, vm, offset)),
+        // immediate outputs are rejected by the disassembler
+        operand => {
+            let pos = operand.value();
+            Box::new(move |_| addr(pc, pos))
+        }
+    }
+}
+
+// `f` returns `None` on overflow, as with the default `Arithmetic`
+fn binary(pc: usize, a: Load, b: Load, out: Store, f: fn(isize, isize) -> Option<isize>) -> Exec {
+    Box::new(move |vm, _| {
+        let pos = out(vm)?;
+        let value = f(a(vm)?, b(vm)?).ok_or(Error::ArithmeticOverflow { pc })?;
+        vm.write(pos, value);
+        Ok(Flow::Wrote(pos))
+    })
+}
+
+fn jump(pc: usize, cond: Load, target: Load, taken: fn(isize) -> bool) -> Exec {
+    Box::new(move |vm, _| {
+        if !taken(cond(vm)?) {
+            return Ok(Flow::Next);
+        }
+        target(vm)?.try_into()
+            .map(Flow::Jump)
+            .map_err(|_| Error::BadValueAtPosition(pc + 2))
+    })
+}
+
+fn compile(pc: usize, instruction: Instruction) -> Option<Op> {
+    let size = instruction.size();
+    let last = instruction.can_jump() || !instruction.falls_through();
+    let mut operands = instruction.operands.into_iter();
+    let mut next = || operands.next().unwrap();
+    let exec = match instruction.opcode {
+        Opcode::Add => binary(pc, load(pc, next()), load(pc, next()), store(pc, next()), |a, b| a.checked_add(b)),
+        Opcode::Mul => binary(pc, load(pc, next()), load(pc, next()), store(pc, next()), |a, b| a.checked_mul(b)),
+        Opcode::Lt => binary(pc, load(pc, next()), load(pc, next()), store(pc, next()), |a, b| Some((a < b) as isize)),
+        Opcode::Eq => binary(pc, load(pc, next()), load(pc, next()), store(pc, next()), |a, b| Some((a == b) as isize)),
+        Opcode::Jnz => jump(pc, load(pc, next()), load(pc, next()), |v| v != 0),
+        Opcode::Jz => jump(pc, load(pc, next()), load(pc, next()), |v| v == 0),
+        Opcode::In => {
+            let out = store(pc, next());
+            Box::new(move |vm: &mut Vm, io: &mut dyn Io| {
+                let pos = out(vm)?;
+                match io.input()? {
+                    Some(value) => {
+                        vm.write(pos, value);
+                        Ok(Flow::Wrote(pos))
+                    },
+                    None => Ok(Flow::Wait)
+                }
+            })
+        },
+        Opcode::Out => {
+            let value = load(pc, next());
+            Box::new(move |vm: &mut Vm, io: &mut dyn Io| {
+                io.output(value(vm)?)?;
+                Ok(Flow::Next)
+            })
+        },
+        Opcode::Arb => {
+            let value = load(pc, next());
+            Box::new(move |vm: &mut Vm, _: &mut dyn Io| {
+                let relbase = vm.relbase().checked_add(value(vm)?).ok_or(Error::ArithmeticOverflow { pc })?;
+                vm.set_relbase(relbase);
+                Ok(Flow::Next)
+            })
+        },
+        // halting is left to the interpreter
+        Opcode::Hlt => return None
+    };
+    Some(Op { size, last, exec })
+}
+
+/// A program compiled ahead of time into closures, one per reachable
+/// instruction, grouped into straight-line blocks ending at a jump.
+///
+/// A block only runs while the machine's memory still holds the words it
+/// was compiled from. Anything else, including code the program has
+/// overwritten, is left to the interpreter. Compiled blocks don't count
+/// steps, so machines with limits, watchpoints, profiling, an undo log or
+/// non-default arithmetic are always interpreted.
+pub struct Compiled {
+    program: Vec<isize>,
+    ops: Vec<Option<Op>>,
+    // where the block starting at each address ends
+    ends: Vec<usize>
+}
+
+impl Compiled {
+    pub fn new(program: &[isize]) -> Compiled {
+        let mut ops = (0..program.len()).map(|_| None).collect::<Vec<_>>();
+        for addr in disassembler::reachable(program) {
+            if let Some(instruction) = Instruction::decode(program, addr) {
+                ops[addr] = compile(addr, instruction);
+            }
+        }
+
+        let mut ends = vec![0; program.len()];
+        for addr in (0..program.len()).rev() {
+            ends[addr] = match &ops[addr] {
+                Some(op) if op.last => addr + op.size,
+                Some(op) => ends.get(addr + op.size).cloned().unwrap_or(addr + op.size),
+                None => addr
+            };
+        }
+        Compiled { program: program.to_vec(), ops, ends }
+    }
+
+    pub fn program(&self) -> &[isize] {
+        &self.program
+    }
+
+    /// The end of the block starting at `pc`, if it can be run compiled.
+    fn block(&self, vm: &Vm) -> Option<usize> {
+        let pc = vm.pc();
+        let end = *self.ends.get(pc)?;
+        if end == pc || vm.mem().get(pc..end)? != &self.program[pc..end] {
+            return None;
+        }
+        Some(end)
+    }
+
+    /// Runs until the machine halts or is waiting for input, like
+    /// `Vm::run`.
+    pub fn run<I: Io>(&self, vm: &mut Vm, io: &mut I) -> Result<Step, Error> {
+        let interpreted = vm.limits() != &Limits::default() || !vm.watchpoints().is_empty()
+            || vm.profile().is_some() || vm.history().is_some() || vm.transcript().is_some()
+            || vm.arithmetic() != Arithmetic::default();
+        if interpreted {
+            return vm.run(io);
+        }
+        loop {
+            let (start, end) = match self.block(vm) {
+                Some(end) => (vm.pc(), end),
+                None => match vm.step(io)? {
+                    Step::Continue | Step::Output(_) => continue,
+                    step => return Ok(step)
+                }
+            };
+            let mut addr = start;
+            let pc = loop {
+                if addr >= end {
+                    break addr;
+                }
+                let op = self.ops[addr].as_ref().unwrap();
+                let flow = (op.exec)(vm, io).map_err(|err| {
+                    vm.set_pc(addr);
+                    vm.fault(err)
+                })?;
+                match flow {
+                    Flow::Next => addr += op.size,
+                    Flow::Wrote(pos) => {
+                        addr += op.size;
+                        // the rest of the block has been overwritten
+                        if (addr..end).contains(&pos) {
+                            break addr;
+                        }
+                    },
+                    Flow::Jump(target) => break target,
+                    Flow::Wait => {
+                        vm.set_pc(addr);
+                        return Ok(Step::ExpectingInput);
+                    }
+                }
+            };
+            vm.set_pc(pc);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::intcode::{Callbacks, IntcodeComputer, Signal};
+
+    fn run_both(program: &[isize], input: &[isize]) -> Result<(Vec<isize>, Vec<isize>), Error> {
+        let mut outputs = vec![];
+        for compiled in [None, Some(Compiled::new(program))] {
+            let mut vm = Vm::new(program);
+            let mut input = input.iter().rev().cloned().collect::<Vec<_>>();
+            let mut output = vec![];
+            let mut io = Callbacks::new(|| input.pop(), |v| output.push(v));
+            let step = match &compiled {
+                Some(compiled) => compiled.run(&mut vm, &mut io)?,
+                None => vm.run(&mut io)?
+            };
+            assert_eq!(step, Step::Halted);
+            outputs.push(output);
+        }
+        Ok((outputs.remove(0), outputs.remove(0)))
+    }
+
+    #[test]
+    fn test_matches_interpreter() -> Result<(), Error> {
+        // outputs a copy of itself, using the relative base
+        let quine = [109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
+        let (interpreted, compiled) = run_both(&quine, &[])?;
+        assert_eq!(interpreted, quine.to_vec());
+        assert_eq!(compiled, interpreted);
+
+        // reads a number, outputs 1 if it is 8 and 0 otherwise
+        for input in 7..10 {
+            let (interpreted, compiled) = run_both(&[3,9,8,9,10,9,4,9,99,-1,8], &[input])?;
+            assert_eq!(compiled, interpreted);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_self_modifying_code() -> Result<(), Error> {
+        // the add patches the operand of the output right after it
+        let program = [1101,0,7,5,104,5,99];
+        let (interpreted, compiled) = run_both(&program, &[])?;
+        assert_eq!(interpreted, vec![7]);
+        assert_eq!(compiled, interpreted);
+        Ok(())
+    }
+
+    #[test]
+    fn test_overflow() {
+        let program = [1102,1 << 32,1 << 32,7,4,7,99,0];
+        let mut vm = Vm::new(&program);
+        let res = Compiled::new(&program).run(&mut vm, &mut Callbacks::new(|| None, |_| ()));
+        assert!(matches!(res.unwrap_err().cause(), Error::ArithmeticOverflow { pc: 0 }));
+        assert_eq!(vm.pc(), 0);
+
+        // as is a jump to a negative address, at the jump's target
+        let program = [104,1,1105,1,-1];
+        let mut vm = Vm::new(&program);
+        let res = Compiled::new(&program).run(&mut vm, &mut Callbacks::new(|| None, |_| ()));
+        assert!(matches!(res.unwrap_err().cause(), Error::BadValueAtPosition(4)));
+        assert_eq!(vm.pc(), 2);
+    }
+
+    #[test]
+    fn test_channel_front_end() -> Result<(), Error> {
+        // adds two inputs, pausing between them
+        let mut comp = IntcodeComputer::new(&[3,11,3,12,1,11,12,13,4,13,99,0,0,0]);
+        comp.compile();
+        for (a, b) in [(1, 2), (30, 40)] {
+            comp.send(Signal::Value(a))?;
+            comp.send(Signal::Value(b))?;
+            comp.run()?;
+            assert_eq!(comp.output_iter().collect::<Vec<_>>(), vec![a + b]);
+            comp.reset();
+        }
+        Ok(())
+    }
+}
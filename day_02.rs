@@ -1,6 +1,7 @@
 use std::fs;
 use std::io;
 use std::num;
+use common::symbolic::{Explorer, Goal};
 
 #[derive(Debug)]
 enum Error {
@@ -70,23 +71,18 @@ fn run_program(memory: &mut Vec<usize>) -> Result<(), Error> {
     }
 }
 
-fn find_inputs_matching(input: &Vec<usize>, expected_output: usize) -> Option<(usize, usize)> {
-    //let input = read_input()?;
-    for a in 0..100 {
-        for b in 0..100 {
-            let mut input = input.clone();
-            assert_eq!(input[0], 1);
-            input[1] = a;
-            input[2] = b;
-            let result = run_program(&mut input);
-            if result.is_ok() && input[0] == expected_output {
-                return Some((a, b));
-            } else if result.is_err() {
-                println!("{:?}", result.err());
-            }
-        }
+// the noun and verb only end up added to multiples of each other, so rather
+// than trying every pair, solve for them
+fn find_inputs_matching(input: &[usize], expected_output: usize) -> Option<(usize, usize)> {
+    let program = input.iter().map(|&x| x as isize).collect::<Vec<_>>();
+    let solution = Explorer::new(&program)
+        .symbolic_cell(1, 0, 99)
+        .symbolic_cell(2, 0, 99)
+        .solve(Goal::Memory { addr: 0, value: expected_output as isize })?;
+    match solution.memory[..] {
+        [(_, noun), (_, verb)] => Some((noun as usize, verb as usize)),
+        _ => None
     }
-    return None;
 }
 
 fn main() -> Result<(), Error> {
@@ -125,4 +121,15 @@ mod test {
         run_program(&mut t4).unwrap();
         assert_eq!(t4, vec![30,1,1,4,2,5,6,0,99]);
     }
+
+    #[test]
+    fn test_find_inputs_matching() -> Result<(), Error> {
+        let mut input = read_input()?;
+        let original = input.clone();
+        input[1] = 12;
+        input[2] = 2;
+        run_program(&mut input)?;
+        assert_eq!(find_inputs_matching(&original, input[0]), Some((12, 2)));
+        Ok(())
+    }
 }
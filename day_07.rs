@@ -1,11 +1,15 @@
 use itertools::Itertools;
-use std::thread;
+use std::collections::HashMap;
 
-use common::intcode::{self, Signal};
+use common::intcode;
+use common::partial;
+use common::pipeline::{self, Network, Status};
 
 #[derive(Debug)]
 enum Error {
     IntCodeError(intcode::Error),
+    PipelineError(pipeline::Error),
+    PartialError(partial::Error),
     NoResults
 }
 
@@ -15,78 +19,63 @@ impl From<intcode::Error> for Error {
     }
 }
 
-fn run_amplifiers(program: &[isize], phases: &[isize]) -> Result<isize, Error> {
-    let stream_a = intcode::DataStream::new();
-    let stream_b = intcode::DataStream::new();
-    let stream_c = intcode::DataStream::new();
-    let stream_d = intcode::DataStream::new();
-    let stream_e = intcode::DataStream::new();
-    let stream_f = intcode::DataStream::new();
-
-    stream_a.send(Signal::Value(phases[0]))?;
-    stream_a.send(Signal::Value(0))?;
-    stream_b.send(Signal::Value(phases[1]))?;
-    stream_c.send(Signal::Value(phases[2]))?;
-    stream_d.send(Signal::Value(phases[3]))?;
-    stream_e.send(Signal::Value(phases[4]))?;
-
-    let mut amp_a = intcode::IntcodeComputer::new_with_streams(&program, stream_a.clone(), stream_b.clone());
-    let mut amp_b = intcode::IntcodeComputer::new_with_streams(&program, stream_b.clone(), stream_c.clone());
-    let mut amp_c = intcode::IntcodeComputer::new_with_streams(&program, stream_c.clone(), stream_d.clone());
-    let mut amp_d = intcode::IntcodeComputer::new_with_streams(&program, stream_d.clone(), stream_e.clone());
-    let mut amp_e = intcode::IntcodeComputer::new_with_streams(&program, stream_e.clone(), stream_f.clone());
-
-    let thread_a: thread::JoinHandle<Result<(), Error>> = thread::spawn(move || Ok(amp_a.run()?));
-    let thread_b: thread::JoinHandle<Result<(), Error>> = thread::spawn(move || Ok(amp_b.run()?));
-    let thread_c: thread::JoinHandle<Result<(), Error>> = thread::spawn(move || Ok(amp_c.run()?));
-    let thread_d: thread::JoinHandle<Result<(), Error>> = thread::spawn(move || Ok(amp_d.run()?));
-    let thread_e: thread::JoinHandle<Result<(), Error>> = thread::spawn(move || Ok(amp_e.run()?));
-    let feedback: thread::JoinHandle<Result<Option<isize>, Error>> = {
-        let stream_a = stream_a.clone();
-        thread::spawn(move || {
-            let mut last = None;
-            loop {
-                let result = match stream_f.recv()? {
-                    Signal::Value(v) => v,
-                    Signal::Exiting => return Ok(last)
-                };
-                last = Some(result);
-                stream_a.send(Signal::Value(result))?;
-            }
-        })
-    };
-
-    thread_a.join().unwrap()?;
-    thread_b.join().unwrap()?;
-    thread_c.join().unwrap()?;
-    thread_d.join().unwrap()?;
-    thread_e.join().unwrap()?;
-
-    let result = feedback.join().unwrap()?;
-    match result {
-        Some(v) => Ok(v),
-        None => Err(Error::NoResults)
+impl From<pipeline::Error> for Error {
+    fn from(err: pipeline::Error) -> Error {
+        Error::PipelineError(err)
     }
 }
 
-fn find_max_thruster_signal(program: &[isize]) -> Result<isize, Error> {
-    (0..5)
-        .permutations(5)
-        .map(|phases| run_amplifiers(&program, &phases))
+impl From<partial::Error> for Error {
+    fn from(err: partial::Error) -> Error {
+        Error::PartialError(err)
+    }
+}
+
+// the phase is only used to pick what the amplifier does, so each phase gets
+// a copy of the program that has already read it
+fn specialize(program: &[isize], phases: &[isize]) -> Result<HashMap<isize, Vec<isize>>, Error> {
+    phases.iter()
+        .map(|&phase| Ok((phase, partial::specialize(program, &[phase])?.program)))
+        .collect()
+}
+
+fn run_specialized(programs: &HashMap<isize, Vec<isize>>, phases: &[isize]) -> Result<isize, Error> {
+    let names = ["a", "b", "c", "d", "e"];
+    let mut network = Network::new();
+    for (i, (&name, phase)) in names.iter().zip(phases).enumerate() {
+        let inputs = if i == 0 { vec![0] } else { vec![] };
+        network.machine(name, &programs[phase], &inputs);
+        network.connect(name, names[(i + 1) % names.len()]);
+    }
+    network.output("e", "thrust");
+
+    let report = network.run()?;
+    let thrust = report.last("thrust");
+    for (_, status) in report.status {
+        if let Status::Failed(err) = status {
+            return Err(err.into());
+        }
+    }
+    thrust.ok_or(Error::NoResults)
+}
+
+fn find_max_signal(program: &[isize], phases: &[isize]) -> Result<isize, Error> {
+    let programs = specialize(program, phases)?;
+    phases.iter().cloned()
+        .permutations(phases.len())
+        .map(|phases| run_specialized(&programs, &phases))
         .collect::<Result<Vec<isize>, Error>>()?
         .into_iter()
         .max()
         .ok_or(Error::NoResults)
 }
 
+fn find_max_thruster_signal(program: &[isize]) -> Result<isize, Error> {
+    find_max_signal(program, &[0, 1, 2, 3, 4])
+}
+
 fn find_max_thruster_signal_with_feedback(program: &[isize]) -> Result<isize, Error> {
-    (5..10)
-        .permutations(5)
-        .map(|phases| run_amplifiers(&program, &phases))
-        .collect::<Result<Vec<isize>, Error>>()?
-        .into_iter()
-        .max()
-        .ok_or(Error::NoResults)
+    find_max_signal(program, &[5, 6, 7, 8, 9])
 }
 
 fn main() -> Result<(), Error> {
@@ -100,6 +89,10 @@ fn main() -> Result<(), Error> {
 mod test {
     use super::*;
 
+    fn run_amplifiers(program: &[isize], phases: &[isize]) -> Result<isize, Error> {
+        run_specialized(&specialize(program, phases)?, phases)
+    }
+
     #[test]
     fn test_one() -> Result<(), Error> {
         let program = [3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0];
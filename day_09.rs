@@ -47,7 +47,7 @@ mod test {
         let input = [1102,34915192,34915192,7,4,7,99,0];
         let mut comp = intcode::IntcodeComputer::new(&input);
         comp.run()?;
-        assert_eq!(format!("{}", comp.recv()?.value()).len(), 16);
+        assert_eq!(format!("{}", comp.recv()?.value()?).len(), 16);
 
         Ok(())
     }
@@ -57,7 +57,7 @@ mod test {
         let input = [104,1125899906842624,99];
         let mut comp = intcode::IntcodeComputer::new(&input);
         comp.run()?;
-        assert_eq!(comp.recv()?.value(), 1125899906842624);
+        assert_eq!(comp.recv()?.value()?, 1125899906842624);
 
         Ok(())
     }
@@ -70,3 +70,19 @@ fn main() -> Result<(), intcode::Error> {
     thread.join().unwrap()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::transcript::{Error, Transcript};
+
+    #[test]
+    fn test_transcript() -> Result<(), Error> {
+        // the start of a game with quarters in: drawing the screen and the
+        // paddle following the ball for 50 moves, recorded with the
+        // interpreter
+        let mut program = intcode::read_program("day_13_input.txt")?;
+        program[0] = 2;
+        Transcript::load("day_13_transcript.txt")?.replay(&program)
+    }
+}
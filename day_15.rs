@@ -221,3 +221,16 @@ fn main() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::transcript::{Error, Transcript};
+
+    #[test]
+    fn test_transcript() -> Result<(), Error> {
+        // the droid exploring the whole maze, recorded with the interpreter
+        let program = read_program("day_15_input.txt")?;
+        Transcript::load("day_15_transcript.txt")?.replay(&program)
+    }
+}
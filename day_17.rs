@@ -1,16 +1,11 @@
-use common::intcode::{IntcodeComputer, Signal, Error, read_program};
+use common::ascii::Terminal;
+use common::intcode::{Error, read_program};
 
 fn main() -> Result<(), Error> {
 
     let program = read_program("day_17_input.txt")?;
-    let mut comp = IntcodeComputer::new(&program);
-
-    comp.run()?;
-
-    let map = comp.output_iter()
-        .map(|i| std::char::from_u32(i as _).unwrap())
-        .collect::<String>()
-        .split("\n")
+    let map = Terminal::new(&program).read()?.lines
+        .iter()
         .map(|v| v.chars().collect::<Vec<_>>())
         .filter(|v| !v.is_empty())
         .collect::<Vec<_>>();
@@ -140,30 +135,17 @@ fn main() -> Result<(), Error> {
     // DONE MANUALLY!
 
     // A = R,12,L,10,R,10,L,8
-    let program_a = "R,12,L,10,R,10,L,8\n".chars().map(|c| c as isize).collect::<Vec<_>>();
     // B = L,8,R,10,R,6
-    let program_b = "L,8,R,10,R,6\n".chars().map(|c| c as isize).collect::<Vec<_>>();
     // C = R,12,L,10,R,12
-    let program_c = "R,12,L,10,R,12\n".chars().map(|c| c as isize).collect::<Vec<_>>();
-
-    // C,B,C,A,B,A,B,A,C,A
-    let program_main = "C,B,C,A,B,A,B,A,C,A\n".chars().map(|c| c as isize).collect::<Vec<_>>();
-
-
+    // main = C,B,C,A,B,A,B,A,C,A
     let mut program = read_program("day_17_input.txt")?;
     program[0] = 2;
-    let mut comp = IntcodeComputer::new(&program);
-    [program_main, program_a, program_b, program_c].iter().for_each(|program| {
-        program.iter().for_each(|&i| {
-            comp.send(Signal::Value(i)).unwrap();
-        });
-    });
-
-    comp.send(Signal::Value('n' as isize))?;
-    comp.send(Signal::Value('\n' as isize))?;
-    comp.run()?;
+    let mut term = Terminal::new(&program);
+    for line in &["C,B,C,A,B,A,B,A,C,A", "R,12,L,10,R,10,L,8", "L,8,R,10,R,6", "R,12,L,10,R,12", "n"] {
+        term.send_line(line);
+    }
 
-    let result = comp.output_iter().last().unwrap();
+    let result = term.read()?.answer.unwrap();
     println!("Part 2: {}", result);
 
     Ok(())
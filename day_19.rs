@@ -8,45 +8,33 @@ fn find_square_of_size(
     input: DataStream,
     output: DataStream,
     size: isize
-) -> (isize, isize) {
+) -> Result<(isize, isize), Error> {
+    let mut probe = |x: isize, y: isize| -> Result<isize, Error> {
+        input.send(Signal::Value(x))?;
+        input.send(Signal::Value(y))?;
+        comp.run()?;
+        let v = output.recv()?.value()?;
+        comp.reset();
+        Ok(v)
+    };
     let mut y = size;
     let mut x = size;
-    'outer: loop {
+    loop {
         y += 1;
-        x = (x..).filter(|&dx| {
-            input.send(Signal::Value(dx)).unwrap();
-            input.send(Signal::Value(y)).unwrap();
-            comp.run().unwrap();
-            let v = output.recv().unwrap().value();
-            comp.reset();
-            v == 1
-        }).next().unwrap();
-        input.send(Signal::Value(x + size - 1)).unwrap();
-        input.send(Signal::Value(y)).unwrap();
-        comp.run().unwrap();
-        let v = output.recv().unwrap().value();
-        comp.reset();
-        if v == 0 {
+        while probe(x, y)? != 1 {
+            x += 1;
+        }
+        if probe(x + size - 1, y)? == 0 {
             continue;
         }
-        input.send(Signal::Value(x)).unwrap();
-        input.send(Signal::Value(y - (size - 1))).unwrap();
-        comp.run().unwrap();
-        let v = output.recv().unwrap().value();
-        comp.reset();
-        if v == 0 {
-            continue 'outer;
+        if probe(x, y - (size - 1))? == 0 {
+            continue;
         }
-        input.send(Signal::Value(x + size - 1)).unwrap();
-        input.send(Signal::Value(y - (size - 1))).unwrap();
-        comp.run().unwrap();
-        let v = output.recv().unwrap().value();
-        comp.reset();
-        if v == 0 {
-            continue 'outer;
+        if probe(x + size - 1, y - (size - 1))? == 0 {
+            continue;
         }
 
-        return (x, y - (size - 1));
+        return Ok((x, y - (size - 1)));
     }
 }
 
@@ -55,6 +43,7 @@ fn main() -> Result<(), Error> {
     let input = DataStream::new();
     let output = DataStream::new();
     let mut comp = IntcodeComputer::new_with_streams(&program, input.clone(), output.clone());
+    comp.compile();
 
     let mut sum = 0;
     for y in 0..150 {
@@ -64,7 +53,7 @@ fn main() -> Result<(), Error> {
             input.send(Signal::Value(y))?;
             comp.run()?;
 
-            let v = output.recv()?.value();
+            let v = output.recv()?.value()?;
             if v == 0 {
                 //print!(".");
             } else {
@@ -78,7 +67,7 @@ fn main() -> Result<(), Error> {
 
     println!("part1: {}", sum);
 
-    let (x, y) = find_square_of_size(comp.clone(), input.clone(), output.clone(), 100);
+    let (x, y) = find_square_of_size(comp.clone(), input.clone(), output.clone(), 100)?;
     //dbg!(x, y);
 
     // for sy in (y-50)..(y+100) {
@@ -88,7 +77,7 @@ fn main() -> Result<(), Error> {
     //         input.send(Signal::Value(sy))?;
     //         comp.run()?;
 
-    //         let v = output.recv()?.value();
+    //         let v = output.recv()?.value()?;
     //         if v == 0 {
     //             print!(".");
     //         } else {
@@ -1,4 +1,5 @@
-use common::intcode::{IntcodeComputer, Error, read_program};
+use common::ascii::{Screen, Terminal};
+use common::intcode::{Error, read_program};
 
 const NOT: &str = "NOT";
 const AND: &str = "AND";
@@ -34,9 +35,15 @@ macro_rules! springcode {
     }}
 }
 
+fn print_screen(screen: &Screen) {
+    screen.lines.iter().for_each(|line| println!("{}", line));
+    if let Some(damage) = screen.answer {
+        println!("{}", damage);
+    }
+}
+
 fn main() -> Result<(), Error> {
     let program = read_program("day_21_input.txt")?;
-    let mut comp = IntcodeComputer::new(&program);
 
     let springcode = springcode!(
         // jump if A is a hole
@@ -52,13 +59,9 @@ fn main() -> Result<(), Error> {
         !WALK
     );
 
-    comp.send_ascii(&springcode)?;
-    comp.run()?;
-
-    comp.print_output_ascii();
-    println!("");
-
-    comp.reset();
+    let mut term = Terminal::new(&program);
+    term.send_lines(&springcode);
+    print_screen(&term.read()?);
 
     let springcode = springcode!(
         // JUMP if there is a hole and the landing is safe
@@ -78,11 +81,9 @@ fn main() -> Result<(), Error> {
         !RUN
     );
 
-    comp.send_ascii(&springcode)?;
-    comp.run()?;
-
-    comp.print_output_ascii();
-    println!("");
+    let mut term = Terminal::new(&program);
+    term.send_lines(&springcode);
+    print_screen(&term.read()?);
 
     Ok(())
 }
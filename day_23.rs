@@ -1,65 +1,33 @@
-use common::intcode2::{IntcodeComputer, Error, Signal, read_program};
+use common::intcode2::{Error, read_program};
+use common::network::{Event, Network, NAT_ADDRESS};
 use std::collections::HashSet;
+use std::ops::ControlFlow;
 
 fn main() -> Result<(), Error> {
     let program = read_program("day_23_input.txt")?;
 
-    let mut nics = (0..50).map(|address| {
-        let mut comp = IntcodeComputer::new(&program);
-        comp.send(address);
-        comp
-    }).collect::<Vec<_>>();
-
-    // router
     let s = std::time::Instant::now();
-    let mut nat_memory = None;
+    let mut network = Network::new(&program, 50);
+    let mut first_y = None;
     let mut delivered_ys = HashSet::new();
-    'outer: loop {
-        let outputs = nics.iter_mut().map(|comp| {
-            match comp.run()? {
-                Signal::Output(address) => {
-                    let x = comp.run()?.output();
-                    let y = comp.run()?.output();
-                    Ok(Some((address, x, y)))
-                },
-                Signal::ExpectingInput => {
-                    Ok(None)
-                },
-                x => panic!("unexpected signal: {:?}", x)
-            }
-        }).collect::<Result<Vec<Option<(isize, isize, isize)>>, Error>>()?
-            .into_iter()
-            .filter_map(|o| o)
-            .collect::<Vec<(isize, isize, isize)>>();
+    let mut repeated_y = None;
+    network.run(|event| match event {
+        Event::Delivered(packet) if packet.to == NAT_ADDRESS => {
+            first_y.get_or_insert(packet.y);
+            ControlFlow::Continue(())
+        },
+        Event::Delivered(packet) if packet.from == NAT_ADDRESS && !delivered_ys.insert(packet.y) => {
+            repeated_y = Some(packet.y);
+            ControlFlow::Break(())
+        },
+        _ => ControlFlow::Continue(())
+    })?;
 
-        if outputs.is_empty() {
-            // all the nics are waiting for input
-            if let Some((x, y)) = &nat_memory {
-                if !delivered_ys.insert(*y) {
-                    println!("Part2: {} ({:?})", y, s.elapsed());
-                    break 'outer;
-                }
-                nics[0].send(*x);
-                nics[0].send(*y);
-            } else {
-                nics.iter_mut().for_each(|comp| {
-                    comp.send(-1);
-                });
-            }
-        } else {
-            for (address, x, y) in outputs {
-                if address == 255 {
-                    if nat_memory.is_none() {
-                        println!("Part1: {} ({:?})", y, s.elapsed());
-                    }
-                    nat_memory = Some((x, y));
-                } else {
-                    nics[address as usize].send(x);
-                    nics[address as usize].send(y);
-                }
-            }
-        }
+    if let Some(y) = first_y {
+        println!("Part1: {}", y);
+    }
+    if let Some(y) = repeated_y {
+        println!("Part2: {} ({:?})", y, s.elapsed());
     }
-
     Ok(())
 }
@@ -1,4 +1,5 @@
-use common::intcode2::{IntcodeComputer, Signal, Error, read_program};
+use common::ascii::Terminal;
+use common::intcode2::{Error, read_program};
 use std::io;
 use std::collections::HashSet;
 use itertools::Itertools;
@@ -13,69 +14,68 @@ enum State {
 fn main() -> Result<(), Error> {
     let code = read_program("day_25_input.txt")?;
 
-    let mut comp = IntcodeComputer::new(&code);
+    let mut term = Terminal::new(&code);
 
-    // comp.send_ascii("north\n");
-    // comp.send_ascii("take giant electromagnet\n"); // can't move
-    // comp.send_ascii("south\n");
+    // term.send_line("north");
+    // term.send_line("take giant electromagnet"); // can't move
+    // term.send_line("south");
 
-    comp.send_ascii("south\n");
-    comp.send_ascii("take astronaut ice cream\n"); // too light
-    comp.send_ascii("north\n");
+    term.send_line("south");
+    term.send_line("take astronaut ice cream"); // too light
+    term.send_line("north");
 
-    comp.send_ascii("east\n");
+    term.send_line("east");
 
-    comp.send_ascii("take mouse\n");  // too light
+    term.send_line("take mouse");  // too light
 
     // get spool
-    comp.send_ascii("north\n");
-    comp.send_ascii("take spool of cat6\n");
+    term.send_line("north");
+    term.send_line("take spool of cat6");
 
     // get infinite loop
-    // comp.send_ascii("west\n");
-    // comp.send_ascii("north\n");
-    // comp.send_ascii("take infinite loop\n"); // infinite loop!
-    // comp.send_ascii("south\n");
-    // comp.send_ascii("east\n");
+    // term.send_line("west");
+    // term.send_line("north");
+    // term.send_line("take infinite loop"); // infinite loop!
+    // term.send_line("south");
+    // term.send_line("east");
 
     // get hypercube
-    comp.send_ascii("north\n");
-    comp.send_ascii("take hypercube\n");
+    term.send_line("north");
+    term.send_line("take hypercube");
     // get sand
-    comp.send_ascii("east\n");
-    comp.send_ascii("take sand\n");
+    term.send_line("east");
+    term.send_line("take sand");
 
     // get antenna
-    comp.send_ascii("south\n");
-    comp.send_ascii("take antenna\n");
-    comp.send_ascii("north\n");
+    term.send_line("south");
+    term.send_line("take antenna");
+    term.send_line("north");
 
     // exit crew quarters
-    comp.send_ascii("west\n");
+    term.send_line("west");
 
     // exit engineering
-    comp.send_ascii("south\n");
+    term.send_line("south");
 
     // exit arcade
-    comp.send_ascii("south\n");
-
-    comp.send_ascii("south\n");
-    comp.send_ascii("take mutex\n"); // too light
-    comp.send_ascii("west\n");
-    comp.send_ascii("take boulder\n"); // too light
-    comp.send_ascii("south\n");
-    //comp.send_ascii("take escape pod\n"); // LAUCHED INTO SPACE
-    comp.send_ascii("south\n");
-    //comp.send_ascii("take photons\n"); // eaten by a Grue!
-    comp.send_ascii("south\n");
-    //comp.send_ascii("take molten lava\n"); // You melt!
-    comp.send_ascii("west\n");
-    comp.send_ascii("south\n");
-    //comp.send_ascii("south\n");
-
-    //comp.send_ascii("inv\n");
-
-    let mut linebuffer = vec![];
+    term.send_line("south");
+
+    term.send_line("south");
+    term.send_line("take mutex"); // too light
+    term.send_line("west");
+    term.send_line("take boulder"); // too light
+    term.send_line("south");
+    //term.send_line("take escape pod"); // LAUCHED INTO SPACE
+    term.send_line("south");
+    //term.send_line("take photons"); // eaten by a Grue!
+    term.send_line("south");
+    //term.send_line("take molten lava"); // You melt!
+    term.send_line("west");
+    term.send_line("south");
+    //term.send_line("south");
+
+    //term.send_line("inv");
+
     let mut room = String::new();
     let mut inv = HashSet::new();
     let mut doors = HashSet::new();
@@ -98,90 +98,91 @@ fn main() -> Result<(), Error> {
     let mut state = State::Other;
 
     loop {
-        let v = comp.run()?;
-
-        match v {
-            Signal::Output(c) => {
-                let c = std::char::from_u32(c as _).unwrap();
-                print!("{}", c);
-                if c == '\n' {
-                    let s: String = linebuffer.iter().collect();
-                    if s.starts_with("==") && s.ends_with("==") {
-                        room = s[3..(s.len() - 3)].to_string();
-                        doors = HashSet::new();
-                        items = HashSet::new();
-                    } else if s.starts_with("You take the ") {
-                        let item = s[13..(s.len() - 1)].to_string();
-                        items.remove(&item);
-                        inv.insert(item);
-                    } else if s.starts_with("You drop the ") {
-                        let item = s[13..(s.len() - 1)].to_string();
-                        inv.remove(&item);
+        let screen = term.read()?;
+        for s in &screen.lines {
+            println!("{}", s);
+            if s.starts_with("==") && s.ends_with("==") {
+                room = s[3..(s.len() - 3)].to_string();
+                doors = HashSet::new();
+                items = HashSet::new();
+            } else if s.starts_with("You take the ") {
+                let item = s[13..(s.len() - 1)].to_string();
+                items.remove(&item);
+                inv.insert(item);
+            } else if s.starts_with("You drop the ") {
+                let item = s[13..(s.len() - 1)].to_string();
+                inv.remove(&item);
+                items.insert(item);
+            } else if s == "Doors here lead:" {
+                state = State::Doors;
+            } else if s == "Items here:" {
+                state = State::Items;
+            } else if s.starts_with("- ") {
+                let item = s[2..].to_string();
+                match state {
+                    State::Doors => {
+                        doors.insert(item);
+                    },
+                    State::Items => {
                         items.insert(item);
-                    } else if s == "Doors here lead:" {
-                        state = State::Doors;
-                    } else if s == "Items here:" {
-                        state = State::Items;
-                    } else if s.starts_with("- ") {
-                        let item = s[2..].to_string();
-                        match state {
-                            State::Doors => {
-                                doors.insert(item);
-                            },
-                            State::Items => {
-                                items.insert(item);
-                            },
-                            _ => {
-
-                            }
-                        }
-                    } else if s == "Command?" {
-                        state = State::Command;
+                    },
+                    _ => {
+
                     }
-                    linebuffer = vec![];
-                } else {
-                    linebuffer.push(c);
                 }
-            },
-            Signal::ExpectingInput => {
-                if room == "Security Checkpoint" {
-                    let items_to_try: HashSet<String> = match perm_iter.next() {
-                        Some(v) => v.into_iter().cloned().collect(),
-                        None => {
-                            perm_num += 1;
-                            if perm_num > all_items.len() {
-                                panic!("got to end without finding a match!");
-                            }
-                            perm_iter = all_items.iter().permutations(perm_num);
-                            perm_iter.next().unwrap().into_iter().cloned().collect()
-                        }
-                    };
-                    print!(">>> Trying: ");
-                    items_to_try.iter().for_each(|i| print!("{}, ", i));
-                    println!("");
-                    for item_to_drop in inv.difference(&items_to_try) {
-                        comp.send_ascii(&format!("drop {}\n", item_to_drop));
-                    }
-                    for item_to_take in items_to_try.difference(&inv) {
-                        comp.send_ascii(&format!("take {}\n", item_to_take));
-                    }
-                    comp.send_ascii("south\n");
+            } else if s == "Command?" {
+                state = State::Command;
+            }
+        }
+        if screen.halted {
+            break;
+        }
 
-                } else {
-                    let mut input = String::new();
-                    io::stdin().read_line(&mut input)
-                        .ok().expect("Couldn't read input");
-                    comp.send_ascii(&input);
+        if room == "Security Checkpoint" {
+            let items_to_try: HashSet<String> = match perm_iter.next() {
+                Some(v) => v.into_iter().cloned().collect(),
+                None => {
+                    perm_num += 1;
+                    if perm_num > all_items.len() {
+                        panic!("got to end without finding a match!");
+                    }
+                    perm_iter = all_items.iter().permutations(perm_num);
+                    perm_iter.next().unwrap().into_iter().cloned().collect()
                 }
-            },
-            Signal::Exiting => {
-                break;
-            },
-            v => {
-                panic!("Unexpected signal: {:?}", v);
+            };
+            print!(">>> Trying: ");
+            items_to_try.iter().for_each(|i| print!("{}, ", i));
+            println!("");
+            for item_to_drop in inv.difference(&items_to_try) {
+                term.send_line(&format!("drop {}", item_to_drop));
+            }
+            for item_to_take in items_to_try.difference(&inv) {
+                term.send_line(&format!("take {}", item_to_take));
             }
+            term.send_line("south");
+
+        } else {
+            let mut input = String::new();
+            io::stdin().read_line(&mut input)
+                .ok().expect("Couldn't read input");
+            term.send_line(input.trim_end());
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::transcript::{Error, Transcript};
+
+    #[test]
+    fn test_transcript() -> Result<(), Error> {
+        // the walk above, then dropping the spool of cat6, hypercube,
+        // antenna and mutex and stepping onto the pressure-sensitive floor,
+        // recorded with the interpreter
+        let program = read_program("day_25_input.txt")?;
+        Transcript::load("day_25_transcript.txt")?.replay(&program)
+    }
+}
@@ -0,0 +1,488 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use crate::intcode::{Error, Opcode};
+use crate::intcode2::{IntcodeComputer, Signal};
+use crate::disassembler::Instruction;
+use crate::trace::{Access, AccessKind};
+
+const HELP: &str = "\
+s, step [n]              execute n instructions (default 1)
+n, next                  execute until the instruction after the current one (steps over calls)
+c, continue              run until a breakpoint, halt, or the program needs input
+b, break <addr>          set a breakpoint
+d, delete <addr>         remove a breakpoint
+breaks                   list breakpoints
+w, watch <addr>[..<end>] trace accesses to memory, and stop continuing on writes
+unwatch                  remove all watchpoints
+l, list [addr] [n]       disassemble n instructions from addr (default: pc, 10)
+p, print pc|rb|<addr>[..<end>]
+rs, rstep [n]            undo n instructions (default 1)
+rw, rwrite <addr>        rewind to just before the last write of addr
+goto <step>              rewind to the given step count
+set pc|rb|<addr> <value> patch a register or memory
+input <text>             send a line of ASCII input
+value <n> [<n> ...]      send numbers as input
+r, regs                  show pc, rb and the step count
+q, quit
+";
+
+/// Why execution stopped.
+#[derive(Debug, PartialEq)]
+pub enum Stop {
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint(usize),
+    ExpectingInput,
+    Halted
+}
+
+fn ascii(value: isize) -> String {
+    match value {
+        10 | 32..=126 => ((value as u8) as char).to_string(),
+        _ => format!("({})", value)
+    }
+}
+
+fn parse_addr(s: &str) -> Option<usize> {
+    s.parse().ok()
+}
+
+pub struct Debugger {
+    comp: IntcodeComputer,
+    breakpoints: BTreeSet<usize>,
+    // scripted input, handed to the program one chunk at a time as it asks
+    pending: VecDeque<Vec<isize>>,
+    halted: bool,
+    steps: usize,
+    // whether the program's output has left the cursor mid-line
+    mid_line: bool,
+    trace: Arc<Mutex<Vec<Access>>>,
+    // the last watched address written by the most recent step
+    written: Option<usize>
+}
+
+impl Debugger {
+    pub fn new(program: &[isize]) -> Debugger {
+        let trace = Arc::new(Mutex::new(vec![]));
+        let mut comp = IntcodeComputer::new(program);
+        comp.vm_mut().set_trace_sink(trace.clone());
+        comp.vm_mut().set_history(true);
+        Debugger {
+            comp,
+            breakpoints: BTreeSet::new(),
+            pending: VecDeque::new(),
+            halted: false,
+            steps: 0,
+            mid_line: false,
+            trace,
+            written: None
+        }
+    }
+
+    pub fn computer(&self) -> &IntcodeComputer {
+        &self.comp
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Queues `line` (plus a newline) to be sent the next time the program
+    /// waits for input.
+    pub fn queue_line(&mut self, line: &str) {
+        self.pending.push_back(line.chars().chain(Some('\n')).map(|c| c as isize).collect());
+    }
+
+    pub fn queue_values(&mut self, values: &[isize]) {
+        self.pending.push_back(values.to_vec());
+    }
+
+    pub fn step<W: Write>(&mut self, out: &mut W) -> Result<Stop, Error> {
+        let stop = self.step_untraced(out);
+        self.written = None;
+        let accesses = std::mem::take(&mut *self.trace.lock().unwrap());
+        for access in accesses {
+            if self.mid_line {
+                writeln!(out)?;
+                self.mid_line = false;
+            }
+            writeln!(out, "{}", access)?;
+            if access.kind == AccessKind::Write {
+                self.written = Some(access.addr);
+            }
+        }
+        stop
+    }
+
+    fn step_untraced<W: Write>(&mut self, out: &mut W) -> Result<Stop, Error> {
+        loop {
+            if self.halted {
+                return Ok(Stop::Halted);
+            }
+            match self.comp.next() {
+                None => {
+                    self.halted = true;
+                    return Ok(Stop::Halted);
+                },
+                Some(Err(e)) => return Err(e),
+                Some(Ok(Signal::ExpectingInput)) => match self.pending.pop_front() {
+                    Some(values) => {
+                        writeln!(out, "<< {}", values.iter().map(|&v| ascii(v)).collect::<String>().trim_end())?;
+                        values.into_iter().for_each(|v| self.comp.send(v));
+                    },
+                    None => return Ok(Stop::ExpectingInput)
+                },
+                Some(Ok(Signal::Output(v))) => {
+                    write!(out, "{}", ascii(v))?;
+                    self.mid_line = v != 10;
+                    self.steps += 1;
+                    return Ok(Stop::Stepped);
+                },
+                Some(Ok(_)) => {
+                    self.steps += 1;
+                    return Ok(Stop::Stepped);
+                }
+            }
+        }
+    }
+
+    /// Undoes steps until the step count is back at `step`. Input the
+    /// undone steps consumed is handed to the program again, but output
+    /// stays printed. Returns false if the undo log doesn't go back that far.
+    pub fn rewind_to(&mut self, step: usize) -> bool {
+        while self.steps > step {
+            match self.comp.step_back() {
+                Some(undo) => self.steps = undo.step,
+                None => return false
+            }
+            self.halted = false;
+        }
+        true
+    }
+
+    /// Rewinds to just before the most recent write of `addr`, so the
+    /// instruction that wrote it is next.
+    pub fn rewind_write(&mut self, addr: usize) -> bool {
+        let history = self.comp.vm().history().unwrap_or(&[]);
+        match history.iter().rev().find(|undo| undo.write.is_some_and(|(a, _)| a == addr)) {
+            Some(undo) => self.rewind_to(undo.step),
+            None => false
+        }
+    }
+
+    /// Runs until `until` returns true for the pc, or execution stops for
+    /// any other reason. Always executes at least one instruction, so that
+    /// continuing from a breakpoint moves past it.
+    fn run_until<W, F>(&mut self, out: &mut W, until: F) -> Result<Stop, Error>
+    where W: Write, F: Fn(usize) -> bool
+    {
+        match self.step(out)? {
+            Stop::Stepped => (),
+            stop => return Ok(stop)
+        }
+        loop {
+            if let Some(addr) = self.written {
+                return Ok(Stop::Watchpoint(addr));
+            }
+            let pc = self.comp.vm().pc();
+            if self.breakpoints.contains(&pc) {
+                return Ok(Stop::Breakpoint(pc));
+            }
+            if until(pc) {
+                return Ok(Stop::Stepped);
+            }
+            match self.step(out)? {
+                Stop::Stepped => (),
+                stop => return Ok(stop)
+            }
+        }
+    }
+
+    pub fn cont<W: Write>(&mut self, out: &mut W) -> Result<Stop, Error> {
+        self.run_until(out, |_| false)
+    }
+
+    /// Steps over jumps, which is how calls are made: stops at the
+    /// instruction following the current one, if control comes back to it.
+    pub fn next<W: Write>(&mut self, out: &mut W) -> Result<Stop, Error> {
+        let pc = self.comp.vm().pc();
+        match Instruction::decode(self.comp.vm().mem(), pc) {
+            Some(i) if i.opcode == Opcode::Jnz || i.opcode == Opcode::Jz => {
+                let after = pc + i.size();
+                self.run_until(out, |pc| pc == after)
+            },
+            _ => self.step(out)
+        }
+    }
+
+    fn list<W: Write>(&self, out: &mut W, mut addr: usize, count: usize) -> io::Result<()> {
+        let vm = self.comp.vm();
+        for _ in 0..count {
+            if addr >= vm.mem().len() {
+                break;
+            }
+            let marker = if addr == vm.pc() { "=>" } else if self.breakpoints.contains(&addr) { " *" } else { "  " };
+            match Instruction::decode(vm.mem(), addr) {
+                Some(i) => {
+                    writeln!(out, "{} {:04}: {}", marker, addr, i)?;
+                    addr += i.size();
+                },
+                None => {
+                    writeln!(out, "{} {:04}: DB {}", marker, addr, vm.read(addr))?;
+                    addr += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn report<W: Write>(&mut self, out: &mut W, stop: Result<Stop, Error>) -> io::Result<()> {
+        if self.mid_line {
+            writeln!(out)?;
+            self.mid_line = false;
+        }
+        match stop {
+            Ok(Stop::Stepped) => (),
+            Ok(Stop::Breakpoint(addr)) => writeln!(out, "breakpoint at {:04}", addr)?,
+            Ok(Stop::Watchpoint(addr)) => writeln!(out, "watchpoint [{:04}] written", addr)?,
+            Ok(Stop::ExpectingInput) => writeln!(out, "waiting for input")?,
+            Ok(Stop::Halted) => {
+                writeln!(out, "halted after {} steps", self.steps)?;
+                return Ok(());
+            },
+            Err(e) => writeln!(out, "error: {}", e)?
+        }
+        self.list(out, self.comp.vm().pc(), 1)
+    }
+
+    /// Executes a single command, returning false once the session should end.
+    pub fn execute<W: Write>(&mut self, line: &str, out: &mut W) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(c) => c,
+            None => return Ok(true)
+        };
+        let args = words.collect::<Vec<_>>();
+        match (command, &args[..]) {
+            ("q", []) | ("quit", []) => return Ok(false),
+            ("help", []) | ("h", []) => write!(out, "{}", HELP)?,
+            ("s", _) | ("step", _) => {
+                let count = args.first().and_then(|n| n.parse().ok()).unwrap_or(1);
+                let mut stop = Ok(Stop::Stepped);
+                for _ in 0..count {
+                    stop = self.step(out);
+                    if stop.as_ref().ok() != Some(&Stop::Stepped) {
+                        break;
+                    }
+                }
+                self.report(out, stop)?;
+            },
+            ("rs", _) | ("rstep", _) => {
+                let count = args.first().and_then(|n| n.parse().ok()).unwrap_or(1);
+                if !self.rewind_to(self.steps.saturating_sub(count)) {
+                    writeln!(out, "can't go back any further")?;
+                }
+                self.report(out, Ok(Stop::Stepped))?;
+            },
+            ("rw", [addr]) | ("rwrite", [addr]) => match parse_addr(addr) {
+                Some(addr) if self.rewind_write(addr) => self.report(out, Ok(Stop::Stepped))?,
+                Some(addr) => writeln!(out, "no write of {} to rewind to", addr)?,
+                None => writeln!(out, "bad address: {}", addr)?
+            },
+            ("goto", [step]) => match step.parse::<usize>() {
+                Ok(step) if step <= self.steps => {
+                    if !self.rewind_to(step) {
+                        writeln!(out, "can't go back any further")?;
+                    }
+                    self.report(out, Ok(Stop::Stepped))?;
+                },
+                _ => writeln!(out, "bad step: {} (can only go back from {})", step, self.steps)?
+            },
+            ("n", []) | ("next", []) => {
+                let stop = self.next(out);
+                self.report(out, stop)?;
+            },
+            ("c", []) | ("continue", []) => {
+                let stop = self.cont(out);
+                self.report(out, stop)?;
+            },
+            ("b", [addr]) | ("break", [addr]) => match parse_addr(addr) {
+                Some(addr) => { self.breakpoints.insert(addr); },
+                None => writeln!(out, "bad address: {}", addr)?
+            },
+            ("d", [addr]) | ("delete", [addr]) => match parse_addr(addr) {
+                Some(addr) => { self.breakpoints.remove(&addr); },
+                None => writeln!(out, "bad address: {}", addr)?
+            },
+            ("w", [range]) | ("watch", [range]) => {
+                let mut bounds = range.splitn(2, "..").map(parse_addr);
+                match (bounds.next().flatten(), bounds.next()) {
+                    (Some(start), None) => self.comp.vm_mut().watch(start..=start),
+                    (Some(start), Some(Some(end))) if end > start => self.comp.vm_mut().watch(start..=end - 1),
+                    _ => writeln!(out, "bad address: {}", range)?
+                }
+            },
+            ("unwatch", []) => for w in self.comp.vm().watchpoints().to_vec() {
+                self.comp.vm_mut().unwatch(&w);
+            },
+            ("breaks", []) => for addr in &self.breakpoints {
+                self.list(out, *addr, 1)?;
+            },
+            ("l", _) | ("list", _) => {
+                let addr = args.first().and_then(|a| parse_addr(a)).unwrap_or_else(|| self.comp.vm().pc());
+                let count = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(10);
+                self.list(out, addr, count)?;
+            },
+            ("r", []) | ("regs", []) => {
+                let vm = self.comp.vm();
+                writeln!(out, "pc={} rb={} steps={}", vm.pc(), vm.relbase(), self.steps)?;
+            },
+            ("p", [what]) | ("print", [what]) => {
+                let vm = self.comp.vm();
+                match *what {
+                    "pc" => writeln!(out, "{}", vm.pc())?,
+                    "rb" => writeln!(out, "{}", vm.relbase())?,
+                    range => {
+                        let mut bounds = range.splitn(2, "..").map(parse_addr);
+                        match (bounds.next().flatten(), bounds.next()) {
+                            (Some(start), None) => writeln!(out, "[{}] = {}", start, vm.read(start))?,
+                            (Some(start), Some(Some(end))) => for addr in start..end {
+                                writeln!(out, "[{}] = {}", addr, vm.read(addr))?;
+                            },
+                            _ => writeln!(out, "bad address: {}", range)?
+                        }
+                    }
+                }
+            },
+            ("set", [what, value]) => {
+                let value = match value.parse::<isize>() {
+                    Ok(v) => v,
+                    Err(_) => {
+                        writeln!(out, "bad value: {}", value)?;
+                        return Ok(true);
+                    }
+                };
+                let vm = self.comp.vm_mut();
+                match (*what, parse_addr(what)) {
+                    ("pc", _) if value >= 0 => vm.set_pc(value as usize),
+                    ("rb", _) => vm.set_relbase(value),
+                    (_, Some(addr)) => vm.write(addr, value),
+                    _ => writeln!(out, "can't set {} to {}", what, value)?
+                }
+                self.halted = false;
+            },
+            ("input", _) => {
+                let text = line.trim_start()[command.len()..].trim_start();
+                self.comp.send_ascii(text);
+                self.comp.send_ascii("\n");
+            },
+            ("value", values) if !values.is_empty() => match values.iter().map(|v| v.parse()).collect::<Result<Vec<isize>, _>>() {
+                Ok(values) => values.into_iter().for_each(|v| self.comp.send(v)),
+                Err(_) => writeln!(out, "bad values: {}", values.join(" "))?
+            },
+            _ => writeln!(out, "unknown command: {} (try help)", line.trim())?
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn run(dbg: &mut Debugger, commands: &str) -> String {
+        let mut out = vec![];
+        for command in commands.lines() {
+            dbg.execute(command, &mut out).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_breakpoints_and_stepping() {
+        let program = assemble("
+                in -> [x]
+            loop:
+                out [x]
+                add [x], #-1 -> [x]
+                jnz [x], #loop
+                hlt
+            x: db 0
+        ").unwrap();
+        let mut dbg = Debugger::new(&program);
+        dbg.queue_values(&[3]);
+        let out = run(&mut dbg, "b 4\nc\nc\nr\ns 3");
+        assert!(out.contains("<< (3)\n(3)\nbreakpoint at 0004"), "{}", out);
+        assert!(out.contains("pc=4 rb=0 steps=5"), "{}", out);
+        assert!(out.ends_with("(1)\n=> 0004: ADD [12], #-1 -> [12]\n"), "{}", out);
+
+        let out = run(&mut dbg, "d 4\nset 12 1\nc");
+        assert!(out.contains("halted after 10 steps"), "{}", out);
+    }
+
+    #[test]
+    fn test_input_and_next() {
+        // calls a function that doubles [arg], returning through [ret]
+        let program = assemble("
+                in -> [arg]
+                add #back, #0 -> [ret]
+                jz #0, #double
+            back:
+                out [arg]
+                hlt
+            double:
+                mul [arg], #2 -> [arg]
+                jz #0, [ret]
+            arg: db 0
+            ret: db 0
+        ").unwrap();
+        let mut dbg = Debugger::new(&program);
+        let out = run(&mut dbg, "c\nvalue 1000\ns 2\nn\nn\np 19\nc");
+        assert!(out.contains("waiting for input\n=> 0000: IN -> [19]"), "{}", out);
+        assert!(out.contains("=> 0006: JZ #0, #12\n=> 0009: OUT [19]\n(2000)\n=> 0011: HLT"), "{}", out);
+        assert!(out.ends_with("[19] = 2000\nhalted after 6 steps\n"), "{}", out);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let program = assemble("
+                add #1, #2 -> [x]
+                out [x]
+                hlt
+            x: db 0
+        ").unwrap();
+        let mut dbg = Debugger::new(&program);
+        let out = run(&mut dbg, "watch 7\nc\nc");
+        assert_eq!(out, "\
+write [0007] 0 -> 3 at 0000 (ADD)
+watchpoint [0007] written
+=> 0004: OUT [7]
+(3)
+read  [0007] 3 at 0004 (OUT)
+halted after 2 steps
+");
+    }
+
+    #[test]
+    fn test_rewind() {
+        // reads two numbers and outputs their product
+        let program = assemble("
+                in -> [a]
+                in -> [b]
+                mul [a], [b] -> [c]
+                out [c]
+                hlt
+            a: db 0
+            b: db 0
+            c: db 0
+        ").unwrap();
+        let mut dbg = Debugger::new(&program);
+        dbg.queue_values(&[60, 70]);
+        let out = run(&mut dbg, "c\nrw 13\np 13\nrs\ngoto 0\nr\nc");
+        assert!(out.contains("halted after 4 steps\n=> 0004: MUL [11], [12] -> [13]\n[13] = 0\n=> 0002: IN -> [12]\n"), "{}", out);
+        // the inputs are given back, so running again gives the same result
+        assert!(out.ends_with("pc=0 rb=0 steps=0\n(4200)\nhalted after 4 steps\n"), "{}", out);
+    }
+}
@@ -0,0 +1,959 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Write};
+use crate::disassembler::{self, Instruction, Operand};
+use crate::intcode::Opcode;
+
+/// A function found through the calls made to it, or the program itself at
+/// address 0.
+///
+/// Calls follow the convention the puzzle inputs use: the caller stores the
+/// arguments at `[rb+1]`, `[rb+2]`... and the return address at `[rb+0]`,
+/// then jumps. The callee starts with `arb #N`, so that its frame holds the
+/// return address and arguments followed by its locals, and returns with
+/// `arb #-N` and a jump through `[rb+0]`, leaving any result in the first
+/// argument's slot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub entry: usize,
+    /// The size of the frame set up on entry, if the relative base is only
+    /// moved by the prologue and before returning, so that relative operands
+    /// can be named after their place in the frame.
+    pub frame: Option<isize>,
+    /// Number of arguments, the most passed at any of the calls.
+    pub arity: usize,
+    /// Whether the function writes the first argument's slot, which is
+    /// where a result is returned.
+    pub returns: bool,
+    /// Addresses of the function's instructions, including code it shares
+    /// with others.
+    pub code: BTreeSet<usize>
+}
+
+impl Function {
+    pub fn name(&self) -> String {
+        if self.entry == 0 {
+            "main".to_string()
+        } else {
+            format!("f{:04}", self.entry)
+        }
+    }
+
+    fn slot(&self, offset: isize) -> Expr {
+        let frame = match self.frame {
+            Some(frame) => frame,
+            None => return Expr::Mem(Box::new(Expr::add(Expr::Var("rb".to_string()), Expr::Const(offset))))
+        };
+        let slot = offset + frame;
+        Expr::Var(match slot {
+            0 => "ret".to_string(),
+            slot if slot >= 1 && slot as usize <= self.arity => format!("a{}", slot),
+            1 if self.returns => "r".to_string(),
+            _ if offset > 0 => format!("t{}", offset),
+            _ if slot > 0 => format!("l{}", -offset),
+            _ => return Expr::Mem(Box::new(Expr::add(Expr::Var("rb".to_string()), Expr::Const(offset))))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Lt,
+    Ge,
+    Eq,
+    Ne
+}
+
+impl Op {
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Lt | Op::Ge | Op::Eq | Op::Ne => 1,
+            Op::Add | Op::Sub => 2,
+            Op::Mul => 3
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Lt => "<",
+            Op::Ge => ">=",
+            Op::Eq => "==",
+            Op::Ne => "!="
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Const(isize),
+    Var(String),
+    Mem(Box<Expr>),
+    Neg(Box<Expr>),
+    Bin(Box<Expr>, Op, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Input
+}
+
+impl Expr {
+    fn bin(a: Expr, op: Op, b: Expr) -> Expr {
+        Expr::Bin(Box::new(a), op, Box::new(b))
+    }
+
+    fn add(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) if a.checked_add(b).is_some() => Expr::Const(a + b),
+            (x, Expr::Const(0)) | (Expr::Const(0), x) => x,
+            (x, Expr::Const(c)) | (Expr::Const(c), x) if c < 0 && c != isize::MIN => Expr::bin(x, Op::Sub, Expr::Const(-c)),
+            (Expr::Const(c), x) => Expr::bin(x, Op::Add, Expr::Const(c)),
+            (a, b) => Expr::bin(a, Op::Add, b)
+        }
+    }
+
+    fn mul(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) if a.checked_mul(b).is_some() => Expr::Const(a * b),
+            (x, Expr::Const(1)) | (Expr::Const(1), x) => x,
+            (_, Expr::Const(0)) | (Expr::Const(0), _) => Expr::Const(0),
+            (x, Expr::Const(-1)) | (Expr::Const(-1), x) => Expr::Neg(Box::new(x)),
+            (x, Expr::Const(c)) => Expr::bin(Expr::Const(c), Op::Mul, x),
+            (a, b) => Expr::bin(a, Op::Mul, b)
+        }
+    }
+
+    /// The condition under which `self` is zero.
+    fn not(self) -> Expr {
+        match self {
+            Expr::Const(c) => Expr::Const((c == 0) as isize),
+            Expr::Bin(a, op, b) if op.precedence() == 1 => {
+                let op = match op {
+                    Op::Lt => Op::Ge,
+                    Op::Ge => Op::Lt,
+                    Op::Eq => Op::Ne,
+                    _ => Op::Eq
+                };
+                Expr::Bin(a, op, b)
+            },
+            x => Expr::bin(x, Op::Eq, Expr::Const(0))
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Bin(_, op, _) => op.precedence(),
+            Expr::Const(c) if *c < 0 => 4,
+            _ => 5
+        }
+    }
+
+    fn child(&self, f: &mut fmt::Formatter, min: u8) -> Result<(), fmt::Error> {
+        if self.precedence() < min {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Expr::Const(c) => write!(f, "{}", c),
+            Expr::Var(name) => write!(f, "{}", name),
+            Expr::Mem(addr) => write!(f, "mem[{}]", addr),
+            Expr::Neg(x) => {
+                write!(f, "-")?;
+                x.child(f, 5)
+            },
+            Expr::Bin(a, op, b) => {
+                let p = op.precedence();
+                a.child(f, p)?;
+                write!(f, " {} ", op.symbol())?;
+                // comparisons and subtraction don't associate to the right
+                b.child(f, if *op == Op::Add || *op == Op::Mul { p } else { p + 1 })
+            },
+            Expr::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { ", " }, arg)?;
+                }
+                write!(f, ")")
+            },
+            Expr::Input => write!(f, "input()")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Addr(usize),
+    Computed(Expr)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Stmt {
+    Nop,
+    Assign(Expr, Expr),
+    Call(Expr),
+    Output(Expr),
+    Halt,
+    Return(Option<Expr>),
+    /// Taken if the condition is nonzero, or always without one.
+    Jump(Option<Expr>, Target),
+    MoveRb(Expr)
+}
+
+impl Stmt {
+    fn visible(&self) -> bool {
+        match self {
+            Stmt::Nop => false,
+            Stmt::Assign(lhs, rhs) => lhs != rhs,
+            _ => true
+        }
+    }
+}
+
+fn label(addr: usize) -> String {
+    format!("L{:04}", addr)
+}
+
+fn goto(target: &Target) -> String {
+    match target {
+        Target::Addr(addr) => format!("goto {};", label(*addr)),
+        Target::Computed(addr) => format!("goto *{};", addr)
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Stmt::Nop => Ok(()),
+            Stmt::Assign(lhs, rhs) => write!(f, "{} = {};", lhs, rhs),
+            Stmt::Call(call) => write!(f, "{};", call),
+            Stmt::Output(value) => write!(f, "output({});", value),
+            Stmt::Halt => write!(f, "halt;"),
+            Stmt::Return(None) => write!(f, "return;"),
+            Stmt::Return(Some(value)) => write!(f, "return {};", value),
+            Stmt::Jump(None, target) => write!(f, "{}", goto(target)),
+            Stmt::Jump(Some(cond), target) => write!(f, "if ({}) {}", cond, goto(target)),
+            Stmt::MoveRb(by) => write!(f, "rb += {};", by)
+        }
+    }
+}
+
+fn writes_relative(instruction: &Instruction) -> Option<isize> {
+    match instruction.operands.last() {
+        Some(Operand::Relative(offset)) if instruction.opcode.writes() => Some(*offset),
+        _ => None
+    }
+}
+
+fn reads(instruction: &Instruction) -> &[Operand] {
+    let writes = instruction.opcode.writes() as usize;
+    &instruction.operands[..instruction.operands.len() - writes]
+}
+
+fn unconditional(instruction: &Instruction) -> bool {
+    instruction.can_jump() && !instruction.falls_through()
+}
+
+struct Program {
+    code: BTreeMap<usize, Instruction>,
+    /// The instruction that falls through into each address.
+    prev: HashMap<usize, usize>,
+    targets: BTreeSet<usize>,
+    /// Each call, with the straight-line run of stores to `[rb+k]` leading up
+    /// to it, nearest first.
+    calls: BTreeMap<usize, Vec<usize>>
+}
+
+impl Program {
+    fn new(program: &[isize]) -> Program {
+        let code = disassembler::reachable(program).into_iter()
+            .filter_map(|addr| Some((addr, Instruction::decode(program, addr)?)))
+            .collect::<BTreeMap<_, _>>();
+        let prev = code.iter()
+            .filter(|(_, i)| i.falls_through())
+            .map(|(addr, i)| (addr + i.size(), *addr))
+            .collect::<HashMap<_, _>>();
+        let targets = code.values()
+            .filter(|i| i.can_jump())
+            .filter_map(Instruction::jump_target)
+            .collect();
+        let mut program = Program { code, prev, targets, calls: BTreeMap::new() };
+        program.calls = program.code.iter()
+            .filter(|(_, i)| unconditional(i))
+            .filter_map(|(&addr, i)| {
+                let ret = (addr + i.size()) as isize;
+                let run = program.run_before(addr);
+                run.iter().any(|a| writes_relative(&program.code[a]) == Some(0) && program.code[a].constant() == Some(ret))
+                    .then_some((addr, run))
+            })
+            .collect();
+        program
+    }
+
+    fn run_before(&self, addr: usize) -> Vec<usize> {
+        let mut run = vec![];
+        let mut cur = addr;
+        while let Some(&prev) = self.prev.get(&cur) {
+            if writes_relative(&self.code[&prev]).is_none_or(|k| k < 0) {
+                break;
+            }
+            run.push(prev);
+            cur = prev;
+        }
+        run
+    }
+
+    // whether the instruction at `addr` compares into a temporary that the
+    // jump right after it tests
+    fn condition(&self, addr: usize) -> bool {
+        let instruction = &self.code[&addr];
+        let jump = match self.code.get(&(addr + instruction.size())) {
+            Some(jump) => jump,
+            None => return false
+        };
+        matches!(instruction.opcode, Opcode::Lt | Opcode::Eq) && matches!(jump.opcode, Opcode::Jnz | Opcode::Jz) &&
+            jump.falls_through() && jump.can_jump() && jump.operands[0] == instruction.operands[2]
+    }
+
+    fn direct_target(&self, call: usize) -> Option<usize> {
+        self.code[&call].jump_target()
+    }
+
+    fn is_return(&self, addr: usize) -> bool {
+        let instruction = &self.code[&addr];
+        unconditional(instruction) && instruction.operands[1] == Operand::Relative(0) && !self.calls.contains_key(&addr)
+    }
+
+    fn body(&self, entry: usize) -> BTreeSet<usize> {
+        let mut body = BTreeSet::new();
+        let mut todo = vec![entry];
+        while let Some(addr) = todo.pop() {
+            let instruction = match self.code.get(&addr) {
+                Some(i) if !body.contains(&addr) => i,
+                _ => continue
+            };
+            body.insert(addr);
+            let next = addr + instruction.size();
+            if self.calls.contains_key(&addr) {
+                todo.push(next);
+                continue;
+            }
+            if instruction.falls_through() {
+                todo.push(next);
+            }
+            if instruction.can_jump() {
+                todo.extend(instruction.jump_target());
+            }
+        }
+        body
+    }
+
+    fn function(&self, entry: usize, arity: usize) -> Function {
+        let code = self.body(entry);
+        let frame = match self.code[&entry].operands.first() {
+            Some(Operand::Immediate(n)) if self.code[&entry].opcode == Opcode::Arb && *n > 0 => *n,
+            _ => 0
+        };
+        // every other `arb` has to be the epilogue
+        let framed = code.iter()
+            .filter(|&&addr| addr != entry || frame == 0)
+            .filter(|&addr| self.code[addr].opcode == Opcode::Arb)
+            .all(|addr| {
+                let instruction = &self.code[addr];
+                let next = addr + instruction.size();
+                instruction.operands[0] == Operand::Immediate(-frame) && code.contains(&next) && self.is_return(next)
+            });
+        let returns = entry != 0 && framed && code.iter()
+            .any(|&addr| writes_relative(&self.code[&addr]) == Some(1 - frame) && !self.condition(addr));
+        Function { entry, frame: framed.then_some(frame), arity, returns, code }
+    }
+
+    fn functions(&self) -> Vec<Function> {
+        let mut arities = BTreeMap::new();
+        arities.insert(0, 0);
+        for (call, run) in &self.calls {
+            if let Some(target) = self.direct_target(*call) {
+                let arity = run.iter().filter_map(|a| writes_relative(&self.code[a])).max().unwrap_or(0) as usize;
+                let entry = arities.entry(target).or_insert(0);
+                *entry = arity.max(*entry);
+            }
+        }
+        arities.into_iter()
+            .filter(|(entry, _)| self.code.contains_key(entry))
+            .map(|(entry, arity)| self.function(entry, arity))
+            .collect()
+    }
+}
+
+/// Finds the functions of a program: the code reachable from address 0, and
+/// each target of a call, as described for `Function`.
+pub fn functions(program: &[isize]) -> Vec<Function> {
+    Program::new(program).functions()
+}
+
+// the instructions merged into one statement: the stores before a call,
+// which become its arguments, the comparison before a jump, which becomes
+// its condition, the `arb` before a return, and writes into the operands of
+// any of them, which become memory accesses
+#[derive(Default)]
+struct Group {
+    start: usize,
+    patches: HashMap<(usize, usize), Expr>,
+    args: BTreeMap<usize, usize>,
+    compare: Option<usize>,
+    read: Vec<Operand>,
+    written: Vec<Operand>
+}
+
+fn substitute(expr: &mut Expr, from: &Expr, to: &Expr) {
+    if expr == from {
+        *expr = to.clone();
+        return;
+    }
+    match expr {
+        Expr::Mem(x) | Expr::Neg(x) => substitute(x, from, to),
+        Expr::Bin(a, _, b) => {
+            substitute(a, from, to);
+            substitute(b, from, to);
+        },
+        Expr::Call(_, args) => args.iter_mut().for_each(|arg| substitute(arg, from, to)),
+        _ => {}
+    }
+}
+
+// turns the instructions of one function into statements
+struct Builder<'a> {
+    program: &'a Program,
+    function: &'a Function,
+    functions: &'a BTreeMap<usize, Function>,
+    absorbed: BTreeSet<usize>
+}
+
+impl<'a> Builder<'a> {
+    fn operand(&self, operand: Operand, patched: Option<&Expr>) -> Expr {
+        match (operand, patched) {
+            (Operand::Immediate(value), None) => Expr::Const(value),
+            (Operand::Position(addr), None) => Expr::Mem(Box::new(Expr::Const(addr))),
+            (Operand::Relative(offset), None) => self.function.slot(offset),
+            (Operand::Immediate(_), Some(value)) => value.clone(),
+            (Operand::Position(_), Some(addr)) => Expr::Mem(Box::new(addr.clone())),
+            (Operand::Relative(_), Some(offset)) => Expr::Mem(Box::new(Expr::add(Expr::Var("rb".to_string()), offset.clone())))
+        }
+    }
+
+    fn operands(&self, addr: usize, patches: &HashMap<(usize, usize), Expr>) -> Vec<Expr> {
+        self.program.code[&addr].operands.iter().enumerate()
+            .map(|(pos, operand)| self.operand(*operand, patches.get(&(addr, pos))))
+            .collect()
+    }
+
+    fn value(&self, addr: usize, patches: &HashMap<(usize, usize), Expr>) -> Expr {
+        let mut operands = self.operands(addr, patches).into_iter();
+        let mut next = || operands.next().unwrap();
+        match self.program.code[&addr].opcode {
+            Opcode::Add => Expr::add(next(), next()),
+            Opcode::Mul => Expr::mul(next(), next()),
+            Opcode::Lt => Expr::bin(next(), Op::Lt, next()),
+            Opcode::Eq => Expr::bin(next(), Op::Eq, next()),
+            _ => Expr::Input
+        }
+    }
+
+    // the instruction falling through into `addr`, if it can be merged into
+    // the statement there
+    fn before(&self, addr: usize) -> Option<(usize, &'a Instruction)> {
+        if self.program.targets.contains(&addr) {
+            return None;
+        }
+        let prev = *self.program.prev.get(&addr)?;
+        if !self.function.code.contains(&prev) || self.absorbed.contains(&prev) {
+            return None;
+        }
+        Some((prev, &self.program.code[&prev]))
+    }
+
+    // whether `operand` is only ever a temporary holding a condition
+    fn temporary(&self, operand: Operand) -> bool {
+        self.function.code.iter()
+            .filter(|addr| reads(&self.program.code[addr]).contains(&operand))
+            .all(|addr| self.program.prev.get(addr).is_some_and(|&prev| self.program.condition(prev)))
+    }
+
+    fn group(&mut self, addr: usize) -> Group {
+        let instruction = &self.program.code[&addr];
+        let call = self.program.calls.contains_key(&addr);
+        let ret = (addr + instruction.size()) as isize;
+        let frame = self.function.frame.unwrap_or(0);
+        let mut group = Group { start: addr, read: reads(instruction).to_vec(), ..Group::default() };
+        let mut members = vec![addr];
+        let mut writers = vec![];
+
+        // nothing is merged if that would reorder it with an instruction
+        // that writes what it reads, or reads or writes what it writes
+        while let Some((prev, p)) = self.before(group.start) {
+            let moves = !reads(p).iter().any(|o| group.written.contains(o));
+            let out = p.operands.last().filter(|_| p.opcode.writes()).cloned();
+            let patch = match out {
+                Some(Operand::Position(target)) => members.iter()
+                    .map(|&m| (m, (target as usize).wrapping_sub(m + 1)))
+                    .find(|&(m, pos)| pos < self.program.code[&m].operands.len()),
+                _ => None
+            };
+            let compares = members.len() == 1 && self.program.condition(prev) && self.temporary(instruction.operands[0]);
+            let epilogue = self.program.is_return(addr) && members.len() == 1 &&
+                p.opcode == Opcode::Arb && p.operands[0] == Operand::Immediate(-frame);
+
+            if let (Some(key), true) = (patch, moves) {
+                writers.push((key, prev));
+            } else if let (true, Some(Operand::Relative(slot))) = (call && moves, out) {
+                let out = out.unwrap();
+                if slot < 0 || slot == 0 && p.constant() != Some(ret) || group.read.contains(&out) || group.written.contains(&out) {
+                    break;
+                }
+                if slot > 0 {
+                    group.args.insert(slot as usize, prev);
+                }
+                group.written.push(out);
+            } else if compares {
+                group.compare = Some(prev);
+                group.written.extend(out);
+            } else if !epilogue {
+                break;
+            }
+            group.read.extend(reads(p));
+            members.push(prev);
+            self.absorbed.insert(prev);
+            group.start = prev;
+        }
+
+        // in the order they run, as writes can patch the writes after them,
+        // or be added to
+        for (key, writer) in writers.into_iter().rev() {
+            let mut value = self.value(writer, &group.patches);
+            if let Some(before) = group.patches.get(&key) {
+                let word = self.operand(*self.program.code[&writer].operands.last().unwrap(), None);
+                substitute(&mut value, &word, before);
+            }
+            group.patches.insert(key, value);
+        }
+        group
+    }
+
+    // builds the statement for the instruction at `addr`, returning it with
+    // the address of the first instruction merged into it
+    fn build(&mut self, addr: usize) -> (usize, Stmt) {
+        let group = self.group(addr);
+        let instruction = &self.program.code[&addr];
+        let patches = &group.patches;
+        if self.program.calls.contains_key(&addr) {
+            return (group.start, self.call(addr, &group));
+        }
+        if self.program.is_return(addr) && !patches.contains_key(&(addr, 1)) {
+            let value = self.function.returns.then(|| self.function.slot(1 - self.function.frame.unwrap_or(0)));
+            return (group.start, Stmt::Return(value));
+        }
+
+        let mut operands = self.operands(addr, patches);
+        let stmt = match instruction.opcode {
+            Opcode::Jnz | Opcode::Jz if !instruction.can_jump() => Stmt::Nop,
+            Opcode::Jnz | Opcode::Jz => {
+                let target = match (instruction.jump_target(), patches.contains_key(&(addr, 1))) {
+                    (Some(target), false) => Target::Addr(target),
+                    _ => Target::Computed(operands.pop().unwrap())
+                };
+                let cond = match group.compare {
+                    Some(compare) => self.value(compare, patches),
+                    None => operands.swap_remove(0)
+                };
+                let cond = match (instruction.falls_through(), instruction.opcode) {
+                    (false, _) => None,
+                    (true, Opcode::Jnz) => Some(cond),
+                    (true, _) => Some(cond.not())
+                };
+                Stmt::Jump(cond, target)
+            },
+            Opcode::Arb if addr == self.function.entry && self.function.frame.is_some_and(|frame| frame > 0) => Stmt::Nop,
+            Opcode::Arb => Stmt::MoveRb(operands.remove(0)),
+            Opcode::Out => Stmt::Output(operands.remove(0)),
+            Opcode::Hlt => Stmt::Halt,
+            _ => Stmt::Assign(operands.pop().unwrap(), self.value(addr, patches))
+        };
+        (group.start, stmt)
+    }
+
+    fn call(&self, addr: usize, group: &Group) -> Stmt {
+        let mut args = group.args.iter()
+            .map(|(&slot, &store)| (slot, self.value(store, &group.patches)))
+            .collect::<BTreeMap<_, _>>();
+        let patched = group.patches.get(&(addr, 1));
+        let callee = self.program.direct_target(addr)
+            .filter(|_| patched.is_none())
+            .and_then(|target| self.functions.get(&target));
+        let (name, arity, returns) = match callee {
+            Some(callee) => (callee.name(), callee.arity, callee.returns),
+            None => {
+                let target = self.operand(self.program.code[&addr].operands[1], patched);
+                (format!("(*{})", target), args.keys().max().cloned().unwrap_or(0), false)
+            }
+        };
+        let args = (1..=arity)
+            .map(|k| args.remove(&k).unwrap_or_else(|| self.function.slot(k as isize)))
+            .collect();
+        let call = Expr::Call(name, args);
+        if returns {
+            Stmt::Assign(self.function.slot(1), call)
+        } else {
+            Stmt::Call(call)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Label(usize),
+    Stmt(Stmt),
+    Goto(Option<Expr>, usize),
+    Break(Option<Expr>),
+    Continue(Option<Expr>),
+    If(Expr, Vec<Node>, Vec<Node>),
+    While(Option<Expr>, Vec<Node>),
+    DoWhile(Vec<Node>, Expr)
+}
+
+#[derive(Clone, Copy, Default)]
+struct Loop {
+    head: Option<usize>,
+    cont: Option<usize>,
+    exit: Option<usize>
+}
+
+// recovers loops and conditionals from the jumps between statements laid
+// out by address, falling back to gotos
+struct Structurer<'a> {
+    items: &'a [(usize, Stmt)],
+    index: HashMap<usize, usize>,
+    end: usize,
+    targets: &'a BTreeSet<usize>
+}
+
+impl<'a> Structurer<'a> {
+    fn addr(&self, k: usize) -> usize {
+        self.items.get(k).map_or(self.end, |(addr, _)| *addr)
+    }
+
+    fn index(&self, addr: usize) -> Option<usize> {
+        self.index.get(&addr).cloned().or_else(|| (addr == self.end).then_some(self.items.len()))
+    }
+
+    fn jump(&self, k: usize) -> Option<(Option<&'a Expr>, usize)> {
+        match &self.items.get(k)?.1 {
+            Stmt::Jump(cond, Target::Addr(target)) => Some((cond.as_ref(), *target)),
+            _ => None
+        }
+    }
+
+    fn returns(&self, addr: usize) -> bool {
+        self.index.get(&addr).is_some_and(|&k| matches!(self.items[k].1, Stmt::Return(_)))
+    }
+
+    fn structure(&self, lo: usize, hi: usize, ctx: Loop) -> Vec<Node> {
+        let mut nodes = vec![];
+        let mut k = lo;
+        while k < hi {
+            let addr = self.addr(k);
+            if self.targets.contains(&addr) && ctx.head != Some(k) {
+                nodes.push(Node::Label(addr));
+            }
+
+            let back = (k..hi).rev().find(|&m| self.jump(m).is_some_and(|(_, target)| target == addr));
+            if let Some(m) = back.filter(|_| ctx.head != Some(k)) {
+                let exit = Some(self.addr(m + 1));
+                let body = Loop { head: Some(k), cont: Some(addr), exit };
+                nodes.push(match self.jump(m).unwrap().0 {
+                    None => match self.jump(k) {
+                        Some((Some(cond), target)) if k < m && Some(target) == exit => {
+                            let body = Loop { head: None, ..body };
+                            Node::While(Some(cond.clone().not()), self.structure(k + 1, m, body))
+                        },
+                        _ => Node::While(None, self.structure(k, m, body))
+                    },
+                    Some(cond) => {
+                        let body = Loop { cont: Some(self.addr(m)), ..body };
+                        Node::DoWhile(self.structure(k, m, body), cond.clone())
+                    }
+                });
+                k = m + 1;
+                continue;
+            }
+
+            match self.jump(k) {
+                Some((cond, target)) if Some(target) == ctx.exit => nodes.push(Node::Break(cond.cloned())),
+                Some((cond, target)) if Some(target) == ctx.cont => nodes.push(Node::Continue(cond.cloned())),
+                Some((_, target)) if target == self.addr(k + 1) => {},
+                Some((cond, target)) if self.returns(target) => {
+                    let stmt = self.items[self.index(target).unwrap()].1.clone();
+                    nodes.push(match cond {
+                        Some(cond) => Node::If(cond.clone(), vec![Node::Stmt(stmt)], vec![]),
+                        None => Node::Stmt(stmt)
+                    });
+                },
+                Some((Some(cond), target)) if self.index(target).is_some_and(|t| t > k && t <= hi) => {
+                    let t = self.index(target).unwrap();
+                    let otherwise = match self.jump(t - 1) {
+                        Some((None, end)) if t - 1 > k && Some(end) != ctx.exit && Some(end) != ctx.cont => {
+                            self.index(end).filter(|&e| e > t && e <= hi)
+                        },
+                        _ => None
+                    };
+                    let node = match otherwise {
+                        Some(e) => {
+                            let node = self.branch(cond.clone().not(), self.structure(k + 1, t - 1, ctx), self.structure(t, e, ctx));
+                            k = e;
+                            node
+                        },
+                        None => {
+                            let node = self.branch(cond.clone().not(), self.structure(k + 1, t, ctx), vec![]);
+                            k = t;
+                            node
+                        }
+                    };
+                    nodes.extend(node);
+                    continue;
+                },
+                Some((cond, target)) => nodes.push(Node::Goto(cond.cloned(), target)),
+                None => {
+                    let stmt = &self.items[k].1;
+                    if stmt.visible() {
+                        nodes.push(Node::Stmt(stmt.clone()));
+                    }
+                }
+            }
+            k += 1;
+        }
+        nodes
+    }
+
+    fn branch(&self, cond: Expr, then: Vec<Node>, otherwise: Vec<Node>) -> Option<Node> {
+        match (then.is_empty(), otherwise.is_empty()) {
+            (true, true) => None,
+            (true, false) => Some(Node::If(cond.not(), otherwise, then)),
+            _ => Some(Node::If(cond, then, otherwise))
+        }
+    }
+}
+
+fn gotos(nodes: &[Node], used: &mut BTreeSet<usize>) {
+    for node in nodes {
+        match node {
+            Node::Goto(_, target) => {
+                used.insert(*target);
+            },
+            Node::If(_, then, otherwise) => {
+                gotos(then, used);
+                gotos(otherwise, used);
+            },
+            Node::While(_, body) | Node::DoWhile(body, _) => gotos(body, used),
+            _ => {}
+        }
+    }
+}
+
+fn render(out: &mut String, nodes: &[Node], depth: usize, labels: &BTreeSet<usize>) {
+    let indent = "    ".repeat(depth);
+    let conditional = |what: &str, cond: &Option<Expr>| match cond {
+        Some(cond) => format!("if ({}) {}", cond, what),
+        None => what.to_string()
+    };
+    for node in nodes {
+        let _ = match node {
+            Node::Label(addr) if labels.contains(addr) => writeln!(out, "{}:", label(*addr)),
+            Node::Label(_) => Ok(()),
+            Node::Stmt(stmt) => writeln!(out, "{}{}", indent, stmt),
+            Node::Goto(cond, target) if labels.contains(target) => writeln!(out, "{}{}", indent, conditional(&goto(&Target::Addr(*target)), cond)),
+            Node::Goto(cond, target) => writeln!(out, "{}{}", indent, conditional(&goto(&Target::Computed(Expr::Const(*target as isize))), cond)),
+            Node::Break(cond) => writeln!(out, "{}{}", indent, conditional("break;", cond)),
+            Node::Continue(cond) => writeln!(out, "{}{}", indent, conditional("continue;", cond)),
+            Node::If(cond, then, otherwise) => {
+                let _ = writeln!(out, "{}if ({}) {{", indent, cond);
+                render(out, then, depth + 1, labels);
+                if otherwise.iter().any(|node| !matches!(node, Node::Label(addr) if !labels.contains(addr))) {
+                    let _ = writeln!(out, "{}}} else {{", indent);
+                    render(out, otherwise, depth + 1, labels);
+                }
+                writeln!(out, "{}}}", indent)
+            },
+            Node::While(cond, body) => {
+                let cond = cond.as_ref().map_or("true".to_string(), Expr::to_string);
+                let _ = writeln!(out, "{}while ({}) {{", indent, cond);
+                render(out, body, depth + 1, labels);
+                writeln!(out, "{}}}", indent)
+            },
+            Node::DoWhile(body, cond) => {
+                let _ = writeln!(out, "{}do {{", indent);
+                render(out, body, depth + 1, labels);
+                writeln!(out, "{}}} while ({});", indent, cond)
+            }
+        };
+    }
+}
+
+fn decompile_function(program: &Program, function: &Function, functions: &BTreeMap<usize, Function>) -> String {
+    let mut builder = Builder { program, function, functions, absorbed: BTreeSet::new() };
+    let mut items = vec![];
+    for &addr in function.code.iter().rev() {
+        if !builder.absorbed.contains(&addr) {
+            items.push(builder.build(addr));
+        }
+    }
+    items.reverse();
+
+    let end = function.code.iter().next_back().map_or(0, |addr| addr + program.code[addr].size());
+    let index = items.iter().enumerate().map(|(k, (addr, _))| (*addr, k)).collect();
+    let structurer = Structurer { items: &items, index, end, targets: &program.targets };
+    let nodes = structurer.structure(0, items.len(), Loop::default());
+
+    let mut labels = BTreeSet::new();
+    gotos(&nodes, &mut labels);
+    labels.retain(|addr| structurer.index.contains_key(addr));
+
+    let params = (1..=function.arity).map(|k| format!("int a{}", k)).collect::<Vec<_>>();
+    let mut out = format!("{} {}({}) {{\n", if function.returns { "int" } else { "void" }, function.name(), params.join(", "));
+    render(&mut out, &nodes, 1, &labels);
+    out.push_str("}\n");
+    out
+}
+
+/// Decompiles a program into C-like pseudo-code, one function per call
+/// target, with loops and conditionals recovered where the jumps nest.
+/// Arguments are named `a1`, `a2`..., locals `l1`, `l2`... by their offset
+/// below the relative base, and the slots a call's arguments are stored to
+/// `t1`, `t2`...; other memory is `mem[addr]`. Jumps that don't nest are
+/// left as gotos to labels named after their address.
+pub fn decompile(program: &[isize]) -> String {
+    let analysis = Program::new(program);
+    let functions = analysis.functions().into_iter()
+        .map(|function| (function.entry, function))
+        .collect::<BTreeMap<_, _>>();
+    functions.values()
+        .map(|function| decompile_function(&analysis, function, &functions))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn test_functions() {
+        // factorial, with the calling convention of days 9 and 25
+        let program = assemble("
+                arb #100
+                add #0, #10 -> [rb+1]
+                add #ret, #0 -> [rb+0]
+                jz #0, #fact
+            ret:
+                out [rb+1]
+                hlt
+            fact:
+                arb #3
+                lt [rb-2], #2 -> [rb-1]
+                jnz [rb-1], #done
+                add [rb-2], #-1 -> [rb+1]
+                mul #back, #1 -> [rb+0]
+                jz #0, #fact
+            back:
+                mul [rb-2], [rb+1] -> [rb-2]
+            done:
+                arb #-3
+                jz #0, [rb+0]
+        ").unwrap();
+        let functions = functions(&program);
+        assert_eq!(functions.iter().map(|f| (f.entry, f.frame, f.arity, f.returns)).collect::<Vec<_>>(),
+            vec![(0, Some(100), 0, false), (16, Some(3), 1, true)]);
+        assert_eq!(decompile(&program), "\
+void main() {
+    t1 = f0016(10);
+    output(t1);
+    halt;
+}
+
+int f0016(int a1) {
+    if (a1 < 2) {
+        return a1;
+    }
+    t1 = f0016(a1 - 1);
+    a1 = a1 * t1;
+    return a1;
+}
+");
+    }
+
+    #[test]
+    fn test_loops() {
+        // sums the numbers below the input, leaving out 3
+        let program = assemble("
+                in -> [n]
+            loop:
+                lt [i], [n] -> [c]
+                jz [c], #end
+                eq [i], #3 -> [c]
+                jnz [c], #skip
+                add [sum], [i] -> [sum]
+            skip:
+                add [i], #1 -> [i]
+                jz #0, #loop
+            end:
+                out [sum]
+                hlt
+            n: db 0
+            i: db 0
+            sum: db 0
+            c: db 0
+        ").unwrap();
+        assert_eq!(decompile(&program), "\
+void main() {
+    mem[30] = input();
+    while (mem[31] < mem[30]) {
+        if (mem[31] != 3) {
+            mem[32] = mem[32] + mem[31];
+        }
+        mem[31] = mem[31] + 1;
+    }
+    output(mem[32]);
+    halt;
+}
+");
+    }
+
+    #[test]
+    fn test_patched_operands() {
+        // indexes a table by patching the address the `out` reads
+        let program = assemble("
+                in -> [i]
+                add [i], #table -> [load+1]
+            load:
+                out [0]
+                hlt
+            table:
+                db 5
+                db 6
+            i: db 0
+        ").unwrap();
+        assert_eq!(decompile(&program), "\
+void main() {
+    mem[11] = input();
+    output(mem[mem[11] + 9]);
+    halt;
+}
+");
+    }
+}
@@ -0,0 +1,20 @@
+use common::intcode::{Error, read_program};
+use common::{decompiler, disassembler};
+use std::process;
+
+const USAGE: &str = "usage: disasm <program> [--decompile]";
+
+fn main() -> Result<(), Error> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let path = args.iter().find(|a| !a.starts_with("--")).unwrap_or_else(|| {
+        eprintln!("{}", USAGE);
+        process::exit(1)
+    });
+    let program = read_program(path)?;
+    if args.iter().any(|a| a == "--decompile") {
+        print!("{}", decompiler::decompile(&program));
+    } else {
+        print!("{}", disassembler::listing(&program));
+    }
+    Ok(())
+}
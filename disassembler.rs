@@ -0,0 +1,312 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use crate::intcode::{Mode, Opcode};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Position(isize),
+    Immediate(isize),
+    Relative(isize)
+}
+
+impl Operand {
+    fn decode(mode: Mode, value: isize) -> Operand {
+        match mode {
+            Mode::Position => Operand::Position(value),
+            Mode::Immediate => Operand::Immediate(value),
+            Mode::Relative => Operand::Relative(value)
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        match self {
+            Operand::Position(_) => Mode::Position,
+            Operand::Immediate(_) => Mode::Immediate,
+            Operand::Relative(_) => Mode::Relative
+        }
+    }
+
+    pub fn value(&self) -> isize {
+        match *self {
+            Operand::Position(v) | Operand::Immediate(v) | Operand::Relative(v) => v
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Operand::Position(v) => write!(f, "[{}]", v),
+            Operand::Immediate(v) => write!(f, "#{}", v),
+            Operand::Relative(v) if v < 0 => write!(f, "[rb{}]", v),
+            Operand::Relative(v) => write!(f, "[rb+{}]", v)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub operands: Vec<Operand>
+}
+
+impl Instruction {
+    /// Decodes the instruction at `addr`, or returns `None` if the words
+    /// there can't be executed. Instructions with mode digits beyond their
+    /// arity are also rejected, so that every decoded instruction encodes
+    /// back to exactly the same words.
+    pub fn decode(program: &[isize], addr: usize) -> Option<Instruction> {
+        let code = *program.get(addr)?;
+        let opcode = Opcode::decode(code).ok()?;
+        let arity = opcode.arity();
+        if code < 0 || code / 10_isize.pow(arity as u32 + 2) != 0 {
+            return None;
+        }
+        let operands = (0..arity).map(|pos| {
+            let mode = Mode::decode(code, pos).ok()?;
+            Some(Operand::decode(mode, *program.get(addr + pos + 1)?))
+        }).collect::<Option<Vec<_>>>()?;
+        if opcode.writes() && operands[arity - 1].mode() == Mode::Immediate {
+            return None;
+        }
+        Some(Instruction { opcode, operands })
+    }
+
+    pub fn encode(&self) -> Vec<isize> {
+        let modes = self.operands.iter().enumerate().map(|(pos, operand)| {
+            10_isize.pow(pos as u32 + 2) * match operand.mode() {
+                Mode::Position => 0,
+                Mode::Immediate => 1,
+                Mode::Relative => 2
+            }
+        }).sum::<isize>();
+        let mut words = vec![self.opcode.code() + modes];
+        words.extend(self.operands.iter().map(Operand::value));
+        words
+    }
+
+    /// Number of words the instruction occupies.
+    pub fn size(&self) -> usize {
+        1 + self.operands.len()
+    }
+
+    /// The address control continues at if the jump is taken, when it is
+    /// known statically.
+    pub fn jump_target(&self) -> Option<usize> {
+        match (self.opcode, self.operands.get(1)) {
+            (Opcode::Jnz, Some(Operand::Immediate(target))) |
+            (Opcode::Jz, Some(Operand::Immediate(target))) if *target >= 0 => Some(*target as usize),
+            _ => None
+        }
+    }
+
+    /// The value written, if it doesn't depend on anything at runtime.
+    pub fn constant(&self) -> Option<isize> {
+        match (self.opcode, self.operands.first(), self.operands.get(1)) {
+            (Opcode::Add, Some(Operand::Immediate(a)), Some(Operand::Immediate(b))) => a.checked_add(*b),
+            (Opcode::Mul, Some(Operand::Immediate(a)), Some(Operand::Immediate(b))) => a.checked_mul(*b),
+            _ => None
+        }
+    }
+
+    /// Whether control can fall through to the next instruction.
+    pub fn falls_through(&self) -> bool {
+        match (self.opcode, self.operands.first()) {
+            (Opcode::Hlt, _) => false,
+            (Opcode::Jnz, Some(Operand::Immediate(cond))) => *cond == 0,
+            (Opcode::Jz, Some(Operand::Immediate(cond))) => *cond != 0,
+            _ => true
+        }
+    }
+
+    /// Whether the jump can be taken, i.e. the condition isn't a constant
+    /// that rules it out.
+    pub fn can_jump(&self) -> bool {
+        match (self.opcode, self.operands.first()) {
+            (Opcode::Jnz, Some(Operand::Immediate(cond))) => *cond != 0,
+            (Opcode::Jz, Some(Operand::Immediate(cond))) => *cond == 0,
+            (Opcode::Jnz, _) | (Opcode::Jz, _) => true,
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.opcode.mnemonic().to_uppercase())?;
+        let (inputs, output) = if self.opcode.writes() {
+            self.operands.split_at(self.operands.len() - 1)
+        } else {
+            (&self.operands[..], &[][..])
+        };
+        for (i, operand) in inputs.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, operand)?;
+        }
+        for operand in output {
+            write!(f, " -> {}", operand)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Code(usize, Instruction),
+    Data(usize, isize)
+}
+
+impl Line {
+    pub fn addr(&self) -> usize {
+        match *self {
+            Line::Code(addr, _) | Line::Data(addr, _) => addr
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Line::Code(addr, instruction) => write!(f, "{:04}: {}", addr, instruction),
+            Line::Data(addr, value) => write!(f, "{:04}: DB {}", addr, value)
+        }
+    }
+}
+
+/// Addresses of every instruction reachable from address 0, following
+/// fall-through and statically known jump targets.
+///
+/// Calls are made by storing the return address and jumping, so the word
+/// after an unconditional jump is also treated as code if the instructions
+/// leading up to the jump store that address as a constant.
+pub fn reachable(program: &[isize]) -> BTreeSet<usize> {
+    reachable_from(program, &[0])
+}
+
+/// Like `reachable`, starting from each of `entries`.
+pub fn reachable_from(program: &[isize], entries: &[usize]) -> BTreeSet<usize> {
+    let mut seen = BTreeSet::new();
+    let mut todo = entries.to_vec();
+    loop {
+        while let Some(addr) = todo.pop() {
+            if seen.contains(&addr) {
+                continue;
+            }
+            let instruction = match Instruction::decode(program, addr) {
+                Some(i) => i,
+                None => continue
+            };
+            seen.insert(addr);
+            if instruction.falls_through() {
+                todo.push(addr + instruction.size());
+            }
+            if instruction.can_jump() {
+                todo.extend(instruction.jump_target());
+            }
+        }
+
+        let instructions = seen.iter()
+            .filter_map(|&addr| Some((addr, Instruction::decode(program, addr)?)))
+            .collect::<Vec<_>>();
+        todo = instructions.iter().enumerate()
+            .filter(|(_, (_, i))| !i.falls_through() && i.can_jump())
+            .map(|(n, (addr, i))| (n, addr + i.size()))
+            .filter(|(n, ret)| !seen.contains(ret) && stores_before(&instructions[..*n], *ret))
+            .map(|(_, ret)| ret)
+            .collect();
+        if todo.is_empty() {
+            return seen;
+        }
+    }
+}
+
+/// Whether the straight-line run of instructions at the end of `preceding`
+/// stores `value` as a constant.
+fn stores_before(preceding: &[(usize, Instruction)], value: usize) -> bool {
+    let mut next = None;
+    for (addr, instruction) in preceding.iter().rev() {
+        if next.is_some_and(|next| addr + instruction.size() != next) || instruction.can_jump() {
+            return false;
+        }
+        if instruction.constant() == Some(value as isize) {
+            return true;
+        }
+        next = Some(*addr);
+    }
+    false
+}
+
+pub fn disassemble(program: &[isize]) -> Vec<Line> {
+    let code = reachable(program);
+    let mut lines = vec![];
+    let mut addr = 0;
+    while addr < program.len() {
+        match Instruction::decode(program, addr).filter(|_| code.contains(&addr)) {
+            Some(instruction) => {
+                let len = instruction.size();
+                lines.push(Line::Code(addr, instruction));
+                addr += len;
+            },
+            None => {
+                lines.push(Line::Data(addr, program[addr]));
+                addr += 1;
+            }
+        }
+    }
+    lines
+}
+
+pub fn listing(program: &[isize]) -> String {
+    disassemble(program).iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_operands() {
+        let lines = disassemble(&[21101,3,5,104,99]);
+        assert_eq!(format!("{}", lines[0]), "0000: ADD #3, #5 -> [rb+104]");
+        let lines = disassemble(&[1201,-3,5,104,99]);
+        assert_eq!(format!("{}", lines[0]), "0000: ADD [rb-3], #5 -> [104]");
+    }
+
+    #[test]
+    fn test_code_and_data() {
+        // reads a number, outputs 1 if it is 8 and 0 otherwise
+        let program = [3,9,8,9,10,9,4,9,99,-1,8];
+        assert_eq!(listing(&program), "\
+0000: IN -> [9]
+0002: EQ [9], [10] -> [9]
+0006: OUT [9]
+0008: HLT
+0009: DB -1
+0010: DB 8
+");
+    }
+
+    #[test]
+    fn test_follows_jumps() {
+        // the words after the unconditional jump are never executed
+        let program = [1105,1,5,1,2,104,7,99];
+        let lines = disassemble(&program);
+        assert_eq!(lines, vec![
+            Line::Code(0, Instruction::decode(&program, 0).unwrap()),
+            Line::Data(3, 1),
+            Line::Data(4, 2),
+            Line::Code(5, Instruction::decode(&program, 5).unwrap()),
+            Line::Code(7, Instruction::decode(&program, 7).unwrap())
+        ]);
+        assert_eq!(Instruction::decode(&program, 0).unwrap().encode(), vec![1105,1,5]);
+    }
+
+    #[test]
+    fn test_follows_returns() {
+        // stores the return address 7 on the stack and calls 9, which returns through it
+        let program = [21101,0,7,0,1106,0,9,99,42,2105,1,0];
+        let code = reachable(&program);
+        assert_eq!(code.into_iter().collect::<Vec<_>>(), vec![0, 4, 7, 9]);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::intcode::{Decoded, Error, Vm};
+
+/// An opcode added to the instruction set. Parameters use the usual mode
+/// digits, which are checked before the instruction is executed; if
+/// `writes` is set the last one is an address written to, and can't be
+/// immediate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spec {
+    /// The two low digits of the instruction, which must not be taken by a
+    /// built-in opcode.
+    pub code: isize,
+    pub mnemonic: &'static str,
+    /// Number of parameters, at most 3.
+    pub arity: usize,
+    pub writes: bool
+}
+
+/// Extra instructions for a `Vm`, registered with `Vm::extend`. Programs
+/// that don't use the added opcodes run exactly as before.
+pub trait Extension {
+    fn opcodes(&self) -> Vec<Spec>;
+
+    /// Executes one of the added instructions. Writes already made stay made
+    /// if this fails, and only the last one is kept for `Vm::step_back`.
+    fn execute(&mut self, call: &mut Call) -> Result<(), Error>;
+}
+
+/// An extension instruction being executed, with access to its parameters
+/// and the machine. Unless `jump` is called the machine carries on with the
+/// next instruction.
+pub struct Call<'a> {
+    vm: &'a mut Vm,
+    d: Decoded,
+    spec: Spec,
+    jump: Option<usize>
+}
+
+impl<'a> Call<'a> {
+    pub(crate) fn new(vm: &'a mut Vm, d: Decoded, spec: Spec) -> Call<'a> {
+        Call { vm, d, spec, jump: None }
+    }
+
+    pub(crate) fn next(&self) -> usize {
+        self.jump.unwrap_or(self.vm.pc() + 1 + self.spec.arity)
+    }
+
+    pub fn spec(&self) -> &Spec {
+        &self.spec
+    }
+
+    pub fn pc(&self) -> usize {
+        self.vm.pc()
+    }
+
+    /// The value of parameter `pos`, zero based.
+    pub fn arg(&self, pos: usize) -> Result<isize, Error> {
+        self.check(pos)?;
+        self.vm.get_param(self.d, pos)
+    }
+
+    /// The address parameter `pos` refers to, which fails for immediate
+    /// parameters.
+    pub fn addr(&self, pos: usize) -> Result<usize, Error> {
+        self.check(pos)?;
+        self.vm.get_pos(self.d, pos)
+    }
+
+    fn check(&self, pos: usize) -> Result<(), Error> {
+        if pos < self.spec.arity {
+            Ok(())
+        } else {
+            Err(Error::BadValueAtPosition(self.vm.pc() + pos + 1))
+        }
+    }
+
+    pub fn read(&self, addr: usize) -> isize {
+        self.vm.read(addr)
+    }
+
+    /// Writes memory the way the built-in instructions do, so the write is
+    /// traced and subject to the memory limit.
+    pub fn write(&mut self, addr: usize, value: isize) -> Result<(), Error> {
+        self.vm.set_mem(addr, value)
+    }
+
+    /// Writes `value` to the address in the last parameter.
+    pub fn set_result(&mut self, value: isize) -> Result<(), Error> {
+        let addr = self.addr(self.spec.arity.saturating_sub(1))?;
+        self.write(addr, value)
+    }
+
+    pub fn relbase(&self) -> isize {
+        self.vm.relbase()
+    }
+
+    pub fn set_relbase(&mut self, relbase: isize) {
+        self.vm.set_relbase(relbase);
+    }
+
+    /// Continues at `target` instead of the next instruction.
+    pub fn jump(&mut self, target: usize) {
+        self.jump = Some(target);
+    }
+}
+
+/// A single opcode that calls into the host: the first `arity - 1`
+/// parameters are passed to `f`, and what it returns is written to the last.
+pub struct HostCall<F> {
+    spec: Spec,
+    f: F
+}
+
+impl<F: FnMut(&[isize]) -> Result<isize, Error>> HostCall<F> {
+    pub fn new(code: isize, mnemonic: &'static str, arity: usize, f: F) -> HostCall<F> {
+        HostCall { spec: Spec { code, mnemonic, arity, writes: true }, f }
+    }
+}
+
+impl<F: FnMut(&[isize]) -> Result<isize, Error>> Extension for HostCall<F> {
+    fn opcodes(&self) -> Vec<Spec> {
+        vec![self.spec]
+    }
+
+    fn execute(&mut self, call: &mut Call) -> Result<(), Error> {
+        let args = (0..self.spec.arity.saturating_sub(1))
+            .map(|pos| call.arg(pos))
+            .collect::<Result<Vec<_>, _>>()?;
+        let value = (self.f)(&args)?;
+        call.set_result(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::intcode::{self, Callbacks, Vm};
+    use crate::intcode2::{IntcodeComputer, Signal};
+
+    // `prt a` records a value, `cpy src, dst, len` copies memory
+    #[derive(Default)]
+    struct Debug {
+        printed: Vec<isize>
+    }
+
+    impl Extension for Debug {
+        fn opcodes(&self) -> Vec<Spec> {
+            vec![
+                Spec { code: 50, mnemonic: "prt", arity: 1, writes: false },
+                Spec { code: 51, mnemonic: "cpy", arity: 3, writes: false }
+            ]
+        }
+
+        fn execute(&mut self, call: &mut Call) -> Result<(), Error> {
+            match call.spec().code {
+                50 => self.printed.push(call.arg(0)?),
+                _ => {
+                    let (src, dst) = (call.arg(0)? as usize, call.arg(1)? as usize);
+                    for i in 0..call.arg(2)? as usize {
+                        call.write(dst + i, call.read(src + i))?;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_extension() -> Result<(), Error> {
+        // prints 7, copies [13..16] over [16..19], prints [17] and outputs [18]
+        let program = [150,7,11151,13,16,3,50,17,4,18,99,0,0,5,6,7,0,0,0];
+        let debug = Arc::new(Mutex::new(Debug::default()));
+        let mut comp = IntcodeComputer::new(&program);
+        comp.vm_mut().extend(debug.clone())?;
+        assert!(matches!(comp.run()?, Signal::Output(7)));
+        assert!(matches!(comp.run()?, Signal::Exiting));
+        assert_eq!(debug.lock().unwrap().printed, vec![7, 6]);
+        assert_eq!(comp.vm().mem()[16..19], [5, 6, 7]);
+
+        // compiled code leaves the added instructions to the interpreter
+        let mut comp = intcode::IntcodeComputer::new(&program);
+        comp.vm_mut().extend(Arc::new(Mutex::new(Debug::default())))?;
+        comp.compile();
+        comp.run()?;
+        assert_eq!(comp.output_iter().collect::<Vec<_>>(), vec![7]);
+
+        // without the extension nothing changes
+        let mut comp = IntcodeComputer::new(&program);
+        assert!(matches!(comp.run().unwrap_err().cause(), Error::InvalidOpcode(50)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_call() -> Result<(), Error> {
+        // out(max(in, 10))
+        let program = [3,9,21042,9,10,0,204,0,99,0];
+        let max = HostCall::new(42, "max", 3, |args: &[isize]| Ok(args[0].max(args[1])));
+        let mut comp = IntcodeComputer::new(&program);
+        comp.vm_mut().set_relbase(11);
+        comp.vm_mut().extend(Arc::new(Mutex::new(max)))?;
+        comp.send(4);
+        assert!(matches!(comp.run()?, Signal::Output(10)));
+        assert_eq!(comp.vm().extensions().map(|spec| spec.mnemonic).collect::<Vec<_>>(), vec!["max"]);
+
+        let clash = HostCall::new(42, "min", 3, |args: &[isize]| Ok(args[0].min(args[1])));
+        assert!(matches!(comp.vm_mut().extend(Arc::new(Mutex::new(clash))), Err(Error::DuplicateOpcode(42))));
+        let builtin = HostCall::new(7, "lt", 3, |_: &[isize]| Ok(0));
+        assert!(matches!(comp.vm_mut().extend(Arc::new(Mutex::new(builtin))), Err(Error::DuplicateOpcode(7))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_modes() {
+        let calls = Arc::new(Mutex::new(0));
+        let mut io = Callbacks::new(|| None, |_| ());
+
+        // the result can't go to an immediate, nor can a mode be made up,
+        // and the handler isn't called for either
+        for (program, mode) in [([11142,1,2,3,99], 1), ([1342,1,2,3,99], 3)] {
+            let counted = calls.clone();
+            let add = HostCall::new(42, "add", 3, move |args: &[isize]| {
+                *counted.lock().unwrap() += 1;
+                Ok(args[0] + args[1])
+            });
+            let mut vm = Vm::new(&program);
+            vm.extend(Arc::new(Mutex::new(add))).unwrap();
+            assert!(matches!(vm.run(&mut io).unwrap_err().cause(), Error::InvalidParameterMode(m) if *m == mode));
+        }
+        assert_eq!(*calls.lock().unwrap(), 0);
+
+        // a bad mode on `cpy`'s length stops it before it copies anything
+        let mut vm = Vm::new(&[31151,5,6,3,99,1,0]);
+        vm.extend(Arc::new(Mutex::new(Debug::default()))).unwrap();
+        assert!(matches!(vm.run(&mut io).unwrap_err().cause(), Error::InvalidParameterMode(3)));
+        assert_eq!(vm.mem()[6], 0);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        // `cpy`'s writes are traced like those of built-in instructions
+        let mut vm = Vm::new(&[11151,9,11,2,4,12,99,0,0,5,6,0,0]);
+        vm.extend(Arc::new(Mutex::new(Debug::default()))).unwrap();
+        let sink = Arc::new(Mutex::new(vec![]));
+        vm.set_trace_sink(sink.clone());
+        vm.watch(11..=12);
+        vm.run(&mut Callbacks::new(|| None, |_| ())).unwrap();
+
+        let trace = sink.lock().unwrap();
+        assert_eq!(trace.iter().map(|a| a.to_string()).collect::<Vec<_>>(), vec![
+            "write [0011] 0 -> 5 at 0000 (CPY)",
+            "write [0012] 0 -> 6 at 0000 (CPY)",
+            "read  [0012] 6 at 0004 (OUT)"
+        ]);
+    }
+}
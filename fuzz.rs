@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::panic::{self, AssertUnwindSafe};
+use crate::intcode::{self, Error, Limits, Opcode, Signal};
+use crate::intcode2;
+
+/// xorshift64*, so runs are reproducible from a seed without a dependency.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // the state must never be zero
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15 | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn range(&mut self, low: isize, high: isize) -> isize {
+        low + self.below((high - low) as usize + 1) as isize
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+/// A program of well formed instructions whose parameters mostly point
+/// inside it. Jumps and self-modification still make anything possible.
+pub fn valid_program(rng: &mut Rng, instructions: usize) -> Vec<isize> {
+    let mut program = vec![];
+    for _ in 0..instructions {
+        let opcode = Opcode::ALL[rng.below(Opcode::ALL.len())];
+        let mut code = opcode.code();
+        let mut params = vec![];
+        for i in 0..opcode.arity() {
+            let writes = opcode.writes() && i + 1 == opcode.arity();
+            let mode = match rng.below(if writes { 2 } else { 3 }) {
+                0 => 0,
+                1 => 2,
+                _ => 1
+            };
+            code += mode * 10isize.pow(i as u32 + 2);
+            params.push(match mode {
+                1 => rng.range(-10, 100),
+                _ => rng.range(0, instructions as isize * 4)
+            });
+        }
+        program.push(code);
+        program.extend(params);
+    }
+    program.push(99);
+    program
+}
+
+/// Words that are as likely to be garbage as instructions, including bad
+/// opcodes and modes and huge or negative addresses.
+pub fn random_program(rng: &mut Rng, len: usize) -> Vec<isize> {
+    const EXTREMES: [isize; 4] = [isize::MIN, isize::MIN + 1, isize::MAX - 1, isize::MAX];
+    (0..len).map(|_| match rng.below(6) {
+        0 => rng.range(-5, 5),
+        1 => rng.range(0, len as isize),
+        2 => EXTREMES[rng.below(EXTREMES.len())],
+        3 => rng.next_u64() as isize,
+        _ => {
+            // mostly valid modes, with the odd 3 thrown in
+            let modes = (0..3).fold(0, |modes, _| modes * 10 + rng.range(0, 3) % 3 + rng.chance(5) as isize);
+            Opcode::ALL[rng.below(Opcode::ALL.len())].code() + 100 * modes
+        }
+    }).collect()
+}
+
+/// How a run ended, in a form that can be compared between front-ends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub outputs: Vec<isize>,
+    pub error: Option<String>
+}
+
+/// Why a run stopped, without the details only the front-ends know about.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    Halted,
+    Waiting,
+    StepLimit,
+    MemoryLimit,
+    InvalidOpcode,
+    InvalidMode,
+    OutOfBounds,
+    BadValue,
+    Overflow,
+    /// An error the reference interpreter never raises.
+    Other
+}
+
+impl Stop {
+    fn new(err: &Error) -> Stop {
+        match err.cause() {
+            Error::InvalidOpcode(_) => Stop::InvalidOpcode,
+            Error::InvalidParameterMode(_) => Stop::InvalidMode,
+            Error::IndexOutOfBounds { .. } => Stop::OutOfBounds,
+            Error::BadValueAtPosition(_) => Stop::BadValue,
+            Error::ArithmeticOverflow { .. } => Stop::Overflow,
+            Error::StepLimitExceeded { .. } => Stop::StepLimit,
+            Error::MemoryLimitExceeded { .. } => Stop::MemoryLimit,
+            _ => Stop::Other
+        }
+    }
+}
+
+/// How a run of the reference interpreter ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub outputs: Vec<isize>,
+    pub stop: Stop
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+    Panic { front_end: &'static str, message: String },
+    Divergence { channel: Outcome, polling: Outcome },
+    /// The front-ends agree with each other but not with the reference.
+    Mismatch { polling: Outcome, stop: Stop, reference: Reference }
+}
+
+fn limits(steps: usize) -> Limits {
+    Limits { steps: Some(steps), memory: Some(1 << 20), ..Limits::default() }
+}
+
+fn catch<T, F: FnOnce() -> T>(front_end: &'static str, f: F) -> Result<T, Finding> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Finding::Panic { front_end, message }
+    })
+}
+
+fn run_channel(program: &[isize], inputs: &[isize], steps: usize) -> Outcome {
+    let mut comp = intcode::IntcodeComputer::new(program);
+    comp.vm_mut().set_limits(limits(steps));
+    // the input stream has to end, or the machine would block on it forever
+    for &value in inputs {
+        comp.send(Signal::Value(value)).unwrap();
+    }
+    comp.send(Signal::Exiting).unwrap();
+    let error = comp.run().err().map(|e| e.to_string());
+    Outcome { outputs: comp.output_iter().collect(), error }
+}
+
+fn run_polling(program: &[isize], inputs: &[isize], steps: usize) -> (Outcome, Stop) {
+    let mut comp = intcode2::IntcodeComputer::new(program);
+    comp.vm_mut().set_limits(limits(steps));
+    inputs.iter().for_each(|&value| comp.send(value));
+    let mut outputs = vec![];
+    loop {
+        match comp.run() {
+            Ok(intcode2::Signal::Output(value)) => outputs.push(value),
+            Ok(intcode2::Signal::ExpectingInput) => return (Outcome { outputs, error: None }, Stop::Waiting),
+            Ok(_) => return (Outcome { outputs, error: None }, Stop::Halted),
+            Err(e) => return (Outcome { outputs, error: Some(e.to_string()) }, Stop::new(&e))
+        }
+    }
+}
+
+// The reference interpreter keeps memory in a map and decodes every word
+// as it goes, so it shares nothing with `Vm` but the instruction set.
+struct Naive {
+    mem: HashMap<usize, isize>,
+    pc: usize,
+    relbase: isize
+}
+
+impl Naive {
+    fn word(&self, addr: usize) -> isize {
+        self.mem.get(&addr).cloned().unwrap_or(0)
+    }
+
+    fn mode(&self, param: usize) -> Result<isize, Stop> {
+        match self.word(self.pc) / 10isize.pow(param as u32 + 2) % 10 {
+            mode @ 0..=2 => Ok(mode),
+            _ => Err(Stop::InvalidMode)
+        }
+    }
+
+    fn addr(&self, param: usize) -> Result<usize, Stop> {
+        let word = self.word(self.pc + param + 1);
+        let addr = match self.mode(param)? {
+            0 => word,
+            1 => return Err(Stop::InvalidMode),
+            _ => self.relbase.checked_add(word).ok_or(Stop::Overflow)?
+        };
+        usize::try_from(addr).map_err(|_| Stop::OutOfBounds)
+    }
+
+    fn value(&self, param: usize) -> Result<isize, Stop> {
+        match self.mode(param)? {
+            1 => Ok(self.word(self.pc + param + 1)),
+            _ => Ok(self.word(self.addr(param)?))
+        }
+    }
+
+    fn jump(&mut self, taken: bool) -> Result<(), Stop> {
+        self.pc = match taken {
+            true => usize::try_from(self.value(1)?).map_err(|_| Stop::BadValue)?,
+            false => self.pc + 3
+        };
+        Ok(())
+    }
+
+    // `Ok(None)` carries on, the order operands are checked in decides
+    // which error an instruction with several bad ones stops with
+    fn step(&mut self, inputs: &mut impl Iterator<Item = isize>, outputs: &mut Vec<isize>) -> Result<Option<Stop>, Stop> {
+        match self.word(self.pc) % 100 {
+            op @ (1 | 2 | 7 | 8) => {
+                let addr = self.addr(2)?;
+                let (a, b) = (self.value(0)?, self.value(1)?);
+                let value = match op {
+                    1 => a.checked_add(b).ok_or(Stop::Overflow)?,
+                    2 => a.checked_mul(b).ok_or(Stop::Overflow)?,
+                    7 => (a < b) as isize,
+                    _ => (a == b) as isize
+                };
+                self.mem.insert(addr, value);
+                self.pc += 4;
+            },
+            3 => {
+                let addr = self.addr(0)?;
+                match inputs.next() {
+                    Some(value) => self.mem.insert(addr, value),
+                    None => return Ok(Some(Stop::Waiting))
+                };
+                self.pc += 2;
+            },
+            4 => {
+                outputs.push(self.value(0)?);
+                self.pc += 2;
+            },
+            5 => {
+                let taken = self.value(0)? != 0;
+                self.jump(taken)?;
+            },
+            6 => {
+                let taken = self.value(0)? == 0;
+                self.jump(taken)?;
+            },
+            9 => {
+                self.relbase = self.relbase.checked_add(self.value(0)?).ok_or(Stop::Overflow)?;
+                self.pc += 2;
+            },
+            99 => return Ok(Some(Stop::Halted)),
+            _ => return Err(Stop::InvalidOpcode)
+        }
+        Ok(None)
+    }
+}
+
+/// Runs `program` on a simple interpreter written from the puzzle text
+/// alone, with unlimited memory and at most `steps` steps.
+pub fn reference(program: &[isize], inputs: &[isize], steps: usize) -> Reference {
+    let mut naive = Naive { mem: program.iter().cloned().enumerate().collect(), pc: 0, relbase: 0 };
+    let mut inputs = inputs.iter().cloned();
+    let mut outputs = vec![];
+    for _ in 0..steps {
+        match naive.step(&mut inputs, &mut outputs) {
+            Ok(None) => continue,
+            Ok(Some(stop)) | Err(stop) => return Reference { outputs, stop }
+        }
+    }
+    Reference { outputs, stop: Stop::StepLimit }
+}
+
+/// Runs `program` through both front-ends and the reference interpreter
+/// with at most `steps` steps each.
+pub fn check(program: &[isize], inputs: &[isize], steps: usize) -> Result<Outcome, Finding> {
+    let channel = catch("intcode", || run_channel(program, inputs, steps))?;
+    let (polling, stop) = catch("intcode2", || run_polling(program, inputs, steps))?;
+    if channel != polling {
+        return Err(Finding::Divergence { channel, polling });
+    }
+    let reference = catch("reference", || reference(program, inputs, steps))?;
+    let agrees = match stop {
+        // the reference has no memory limit, so it only has to get as far
+        Stop::MemoryLimit => reference.outputs.starts_with(&polling.outputs),
+        stop => reference.outputs == polling.outputs && reference.stop == stop
+    };
+    if !agrees {
+        return Err(Finding::Mismatch { polling, stop, reference });
+    }
+    Ok(channel)
+}
+
+fn same_kind(a: &Finding, b: &Finding) -> bool {
+    match (a, b) {
+        (Finding::Panic { front_end: a, .. }, Finding::Panic { front_end: b, .. }) => a == b,
+        (Finding::Divergence { .. }, Finding::Divergence { .. }) => true,
+        (Finding::Mismatch { .. }, Finding::Mismatch { .. }) => true,
+        _ => false
+    }
+}
+
+/// Shrinks a program for which `fails` holds to a smaller one for which it
+/// still does: first by dropping runs of words, then by making the remaining
+/// words smaller.
+pub fn minimize<F: Fn(&[isize]) -> bool>(program: &[isize], fails: F) -> Vec<isize> {
+    let mut program = program.to_vec();
+
+    // every chunk size rather than just halving, since dropping part of
+    // an instruction usually breaks everything after it
+    let mut chunk = program.len();
+    while chunk > 0 {
+        let mut start = 0;
+        while start < program.len() {
+            let candidate = [&program[..start], &program[(start + chunk).min(program.len())..]].concat();
+            if !candidate.is_empty() && fails(&candidate) {
+                program = candidate;
+            } else {
+                start += 1;
+            }
+        }
+        chunk = (chunk - 1).min(program.len());
+    }
+
+    let mut shrunk = true;
+    while shrunk {
+        shrunk = false;
+        for i in 0..program.len() {
+            for simpler in [0, 1, program[i] / 2] {
+                if simpler.abs() < program[i].abs() {
+                    let mut candidate = program.clone();
+                    candidate[i] = simpler;
+                    if fails(&candidate) {
+                        program = candidate;
+                        shrunk = true;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    program
+}
+
+/// A program that made the front-ends misbehave or disagree with the
+/// reference, already minimized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Crash {
+    pub seed: u64,
+    pub program: Vec<isize>,
+    pub inputs: Vec<isize>,
+    pub finding: Finding
+}
+
+/// Checks `count` generated programs, alternating between valid and random
+/// ones. Panics are caught, so a panic hook that stays quiet is worth
+/// installing first.
+pub fn fuzz(seed: u64, count: usize, steps: usize) -> Vec<Crash> {
+    let mut crashes = vec![];
+    for case in 0..count as u64 {
+        let seed = seed.wrapping_add(case);
+        let mut rng = Rng::new(seed);
+        let program = if case % 2 == 0 {
+            let instructions = 1 + rng.below(20);
+            valid_program(&mut rng, instructions)
+        } else {
+            let len = 1 + rng.below(60);
+            random_program(&mut rng, len)
+        };
+        let inputs = (0..rng.below(5)).map(|_| rng.range(-100, 100)).collect::<Vec<_>>();
+        if let Err(finding) = check(&program, &inputs, steps) {
+            let program = minimize(&program, |candidate| match check(candidate, &inputs, steps) {
+                Err(f) => same_kind(&f, &finding),
+                Ok(_) => false
+            });
+            let finding = check(&program, &inputs, steps).unwrap_err();
+            crashes.push(Crash { seed, program, inputs, finding });
+        }
+    }
+    crashes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disassembler::Instruction;
+
+    #[test]
+    fn test_generators() {
+        let mut rng = Rng::new(7);
+        let program = valid_program(&mut rng, 10);
+        let mut addr = 0;
+        for _ in 0..11 {
+            addr += Instruction::decode(&program, addr).unwrap().size();
+        }
+        assert_eq!(addr, program.len());
+        assert_eq!(program.last(), Some(&99));
+        assert_eq!(random_program(&mut Rng::new(7), 30), random_program(&mut Rng::new(7), 30));
+    }
+
+    #[test]
+    fn test_front_ends_agree() {
+        assert_eq!(fuzz(1, 300, 1000), vec![]);
+    }
+
+    #[test]
+    fn test_minimize() {
+        let outputs_seven = |program: &[isize]| check(program, &[], 100)
+            .is_ok_and(|outcome| outcome.outputs.contains(&7));
+        let program = [1101,3,4,20,1,0,0,0,4,20,104,5,99];
+        assert!(outputs_seven(&program));
+        assert_eq!(minimize(&program, outputs_seven), vec![1101,3,4,20,4,20]);
+    }
+
+    #[test]
+    fn test_reproducers() {
+        // the relative base overflowing used to panic
+        let outcome = check(&[109,isize::MAX,204,1,99], &[], 100).unwrap();
+        assert_eq!(outcome.error.as_deref(), Some("\
+arithmetic overflow
+  at 0002: 204,1 (relative), step 1
+  ran 0000: ARB #9223372036854775807"));
+        // as did running code written far beyond the program, by growing the
+        // decode cache to cover it
+        let outcome = check(&[1101,99,0,1 << 40,1105,1,1 << 40], &[], 100).unwrap();
+        assert_eq!(outcome, Outcome { outputs: vec![], error: None });
+    }
+
+    #[test]
+    fn test_reference() {
+        let stop = |program: &[isize], inputs: &[isize]| reference(program, inputs, 100).stop;
+        assert_eq!(reference(&[3,9,1002,9,3,9,4,9,99,0], &[14], 100), Reference { outputs: vec![42], stop: Stop::Halted });
+        assert_eq!(stop(&[3,0,3,0,99], &[1]), Stop::Waiting);
+        assert_eq!(stop(&[1105,1,0], &[]), Stop::StepLimit);
+        assert_eq!(stop(&[1105,1,-1], &[]), Stop::BadValue);
+        assert_eq!(stop(&[1101,1,1,-1,99], &[]), Stop::OutOfBounds);
+        assert_eq!(stop(&[11101,1,1,0,99], &[]), Stop::InvalidMode);
+        assert_eq!(stop(&[109,isize::MAX,209,1,99], &[]), Stop::Overflow);
+        assert_eq!(stop(&[42], &[]), Stop::InvalidOpcode);
+        // the front-ends stop at their memory limit, the reference doesn't
+        let outcome = check(&[104,7,1101,1,1,1 << 30,99], &[], 100).unwrap();
+        assert_eq!(outcome.outputs, vec![7]);
+        assert_eq!(reference(&[104,7,1101,1,1,1 << 30,99], &[], 100).stop, Stop::Halted);
+    }
+}
@@ -0,0 +1,21 @@
+use std::process;
+use common::intcode::{Error, read_program};
+use common::cfg::Cfg;
+
+const USAGE: &str = "usage: intcfg <program> [--json]";
+
+fn main() -> Result<(), Error> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let path = args.first().unwrap_or_else(|| {
+        eprintln!("{}", USAGE);
+        process::exit(1)
+    });
+    let program = read_program(path)?;
+    let cfg = Cfg::new(&program);
+    if args.iter().any(|a| a == "--json") {
+        print!("{}", cfg.to_json());
+    } else {
+        print!("{}", cfg.to_dot());
+    }
+    Ok(())
+}
@@ -1,9 +1,27 @@
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
 use std::fs;
 use std::io;
 use std::num;
 use std::convert::TryInto;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use crossbeam::crossbeam_channel::{Receiver, Sender, RecvError, SendError, TryIter, unbounded as channel};
+use crate::trace::{Access, AccessKind, AccessOp, TraceSink};
+use crate::snapshot::Snapshot;
+use crate::compiler::Compiled;
+use crate::profiler::Profile;
+use crate::memory::Memory;
+use crate::transcript::{Entry, Transcript};
+use crate::extension::{Call, Extension, Spec};
+use crate::disassembler::Instruction;
 
+/// Errors raised by an instruction while running, such as an invalid opcode
+/// or an overflow, come wrapped in a `Fault` that says where the machine
+/// was. Match on `cause()` to get at the error itself, as in
+/// `matches!(err.cause(), Error::InvalidOpcode(_))`.
 #[derive(Debug)]
 pub enum Error {
     IoError(io::Error),
@@ -11,9 +29,127 @@ pub enum Error {
     InvalidOpcode(isize),
     RecvError(RecvError),
     SendError(SendError<Signal>),
+    /// The address of an operand whose value can't be used, such as a
+    /// negative jump target.
     BadValueAtPosition(usize),
     InvalidParameterMode(isize),
-    //IndexOutOfBounds(isize)
+    InvalidSnapshot(String),
+    StepLimitExceeded { pc: usize, steps: usize },
+    DeadlineExceeded { pc: usize, steps: usize },
+    MemoryLimitExceeded { pc: usize, steps: usize, size: usize },
+    ArithmeticOverflow { pc: usize },
+    IndexOutOfBounds { pc: usize, addr: isize },
+    DuplicateOpcode(isize),
+    /// An `Arithmetic` word size outside `1..=64` bits.
+    InvalidWordSize(u32),
+    /// A `Signal` that isn't the value asked for, in its `Debug` form.
+    UnexpectedSignal(String),
+    /// An instruction failed, with where the machine was when it did.
+    Fault(Box<Fault>)
+}
+
+impl Error {
+    /// The error at the root of a fault, or the error itself.
+    pub fn cause(&self) -> &Error {
+        match self {
+            Error::Fault(fault) => fault.error.cause(),
+            err => err
+        }
+    }
+
+    pub fn fault(&self) -> Option<&Fault> {
+        match self {
+            Error::Fault(fault) => Some(fault),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Error::IoError(err) => write!(f, "i/o error: {}", err),
+            Error::ParseIntError(err) => write!(f, "invalid program: {}", err),
+            Error::InvalidOpcode(opcode) => write!(f, "invalid opcode {}", opcode),
+            Error::RecvError(_) => write!(f, "input channel closed"),
+            Error::SendError(_) => write!(f, "output channel closed"),
+            Error::BadValueAtPosition(pos) => write!(f, "bad value at position {}", pos),
+            Error::InvalidParameterMode(mode) => write!(f, "invalid parameter mode {}", mode),
+            Error::InvalidSnapshot(msg) => write!(f, "invalid snapshot: {}", msg),
+            Error::StepLimitExceeded { pc, steps } => write!(f, "step limit exceeded at {:04} after {} steps", pc, steps),
+            Error::DeadlineExceeded { pc, steps } => write!(f, "deadline exceeded at {:04} after {} steps", pc, steps),
+            Error::MemoryLimitExceeded { pc, steps, size } =>
+                write!(f, "memory limit exceeded at {:04} after {} steps, {} words needed", pc, steps, size),
+            Error::ArithmeticOverflow { pc } => write!(f, "arithmetic overflow at {:04}", pc),
+            Error::IndexOutOfBounds { pc, addr } => write!(f, "address {} out of bounds at {:04}", addr, pc),
+            Error::DuplicateOpcode(opcode) => write!(f, "opcode {} is already taken", opcode),
+            Error::InvalidWordSize(bits) => write!(f, "words can't be {} bits", bits),
+            Error::UnexpectedSignal(signal) => write!(f, "unexpected signal {}", signal),
+            Error::Fault(fault) => write!(f, "{}", fault)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::IoError(err) => Some(err),
+            Error::ParseIntError(err) => Some(err),
+            Error::RecvError(err) => Some(err),
+            Error::SendError(err) => Some(err),
+            Error::Fault(fault) => Some(&fault.error),
+            _ => None
+        }
+    }
+}
+
+// how many of the instructions leading up to a fault are kept
+const RECENT: usize = 8;
+
+/// The state of the machine when an instruction failed. Steps aren't
+/// counted and recent instructions not kept while running compiled code.
+#[derive(Debug)]
+pub struct Fault {
+    pub error: Error,
+    /// The same as the `pc` of the error, for those that have one.
+    pub pc: usize,
+    /// The words of the instruction, as many as its opcode takes if it has
+    /// one.
+    pub instruction: Vec<isize>,
+    /// The modes of its parameters, up to the first invalid one.
+    pub modes: Vec<Mode>,
+    pub steps: usize,
+    /// The addresses of the last few instructions executed before it, oldest
+    /// first, with the words there when it failed.
+    pub recent: Vec<(usize, Vec<isize>)>
+}
+
+fn words(words: &[isize]) -> String {
+    words.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(",")
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        // the location comes next, so it isn't repeated
+        match &self.error {
+            Error::ArithmeticOverflow { .. } => writeln!(f, "arithmetic overflow")?,
+            Error::IndexOutOfBounds { addr, .. } => writeln!(f, "address {} out of bounds", addr)?,
+            error => writeln!(f, "{}", error)?
+        }
+        write!(f, "  at {:04}: {}", self.pc, words(&self.instruction))?;
+        if !self.modes.is_empty() {
+            let modes = self.modes.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+            write!(f, " ({})", modes.join(", "))?;
+        }
+        write!(f, ", step {}", self.steps)?;
+        for (pc, code) in &self.recent {
+            match Instruction::decode(code, 0) {
+                Some(instruction) => write!(f, "\n  ran {:04}: {}", pc, instruction)?,
+                None => write!(f, "\n  ran {:04}: {}", pc, words(code))?
+            }
+        }
+        Ok(())
+    }
 }
 
 impl From<io::Error> for Error {
@@ -41,29 +177,873 @@ impl From<SendError<Signal>> for Error {
 }
 
 pub fn read_program(program: &str) -> Result<Vec<isize>, Error> {
-    fs::read_to_string(&program)?.split(",").map(|x| {
+    fs::read_to_string(program)?.split(',').map(|x| {
         Ok(x.trim().parse::<isize>()?)
     }).collect()
 }
 
+/// Parameter addressing modes, taken from the hundreds, thousands and
+/// ten-thousands digits of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Position,
+    Immediate,
+    Relative
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", match self {
+            Mode::Position => "position",
+            Mode::Immediate => "immediate",
+            Mode::Relative => "relative"
+        })
+    }
+}
+
+impl Mode {
+    /// Decodes the mode of parameter `pos` (zero based) of instruction `code`.
+    pub fn decode(code: isize, pos: usize) -> Result<Mode, Error> {
+        Mode::from_digit((code / 10_isize.pow(pos as u32 + 2)) % 10)
+    }
+
+    pub fn from_digit(digit: isize) -> Result<Mode, Error> {
+        match digit {
+            0 => Ok(Mode::Position),
+            1 => Ok(Mode::Immediate),
+            2 => Ok(Mode::Relative),
+            mode => Err(Error::InvalidParameterMode(mode))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opcode {
+    Add,
+    Mul,
+    In,
+    Out,
+    Jnz,
+    Jz,
+    Lt,
+    Eq,
+    Arb,
+    Hlt
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 10] = [
+        Opcode::Add, Opcode::Mul, Opcode::In, Opcode::Out, Opcode::Jnz,
+        Opcode::Jz, Opcode::Lt, Opcode::Eq, Opcode::Arb, Opcode::Hlt
+    ];
+
+    pub fn decode(code: isize) -> Result<Opcode, Error> {
+        match code % 100 {
+            1 => Ok(Opcode::Add),
+            2 => Ok(Opcode::Mul),
+            3 => Ok(Opcode::In),
+            4 => Ok(Opcode::Out),
+            5 => Ok(Opcode::Jnz),
+            6 => Ok(Opcode::Jz),
+            7 => Ok(Opcode::Lt),
+            8 => Ok(Opcode::Eq),
+            9 => Ok(Opcode::Arb),
+            99 => Ok(Opcode::Hlt),
+            opcode => Err(Error::InvalidOpcode(opcode))
+        }
+    }
+
+    pub fn code(self) -> isize {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Mul => 2,
+            Opcode::In => 3,
+            Opcode::Out => 4,
+            Opcode::Jnz => 5,
+            Opcode::Jz => 6,
+            Opcode::Lt => 7,
+            Opcode::Eq => 8,
+            Opcode::Arb => 9,
+            Opcode::Hlt => 99
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Add => "add",
+            Opcode::Mul => "mul",
+            Opcode::In => "in",
+            Opcode::Out => "out",
+            Opcode::Jnz => "jnz",
+            Opcode::Jz => "jz",
+            Opcode::Lt => "lt",
+            Opcode::Eq => "eq",
+            Opcode::Arb => "arb",
+            Opcode::Hlt => "hlt"
+        }
+    }
+
+    /// Number of parameters, including the one written to.
+    pub fn arity(self) -> usize {
+        match self {
+            Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq => 3,
+            Opcode::Jnz | Opcode::Jz => 2,
+            Opcode::In | Opcode::Out | Opcode::Arb => 1,
+            Opcode::Hlt => 0
+        }
+    }
+
+    /// Whether the last parameter is an address that gets written to.
+    pub fn writes(self) -> bool {
+        matches!(self, Opcode::Add | Opcode::Mul | Opcode::In | Opcode::Lt | Opcode::Eq)
+    }
+}
+
+/// The I/O side of a machine. The core calls into this whenever it executes
+/// an `in`, `out` or `hlt` instruction, so each front-end only has to decide
+/// where values come from and go to.
+pub trait Io {
+    /// Returns `Ok(None)` if there is no input available yet. The input
+    /// instruction is not consumed, so it is retried on the next step.
+    fn input(&mut self) -> Result<Option<isize>, Error>;
+    fn output(&mut self, value: isize) -> Result<(), Error>;
+    fn halt(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// What happened during a single call to `Vm::step`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Continue,
+    Output(isize),
+    ExpectingInput,
+    Halted
+}
+
+/// I/O front-end that forwards to a pair of closures. `input` returning
+/// `None` pauses the machine until more input is available.
+pub struct Callbacks<I, O> {
+    input: I,
+    output: O
+}
+
+impl<I, O> Callbacks<I, O>
+where I: FnMut() -> Option<isize>,
+      O: FnMut(isize)
+{
+    pub fn new(input: I, output: O) -> Callbacks<I, O> {
+        Callbacks { input, output }
+    }
+}
+
+impl<I, O> Io for Callbacks<I, O>
+where I: FnMut() -> Option<isize>,
+      O: FnMut(isize)
+{
+    fn input(&mut self) -> Result<Option<isize>, Error> {
+        Ok((self.input)())
+    }
+
+    fn output(&mut self, value: isize) -> Result<(), Error> {
+        (self.output)(value);
+        Ok(())
+    }
+}
+
+/// What a single step changed, kept so that it can be undone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Undo {
+    /// The step count before the step.
+    pub step: usize,
+    pub pc: usize,
+    pub relbase: isize,
+    /// The address written and the value it held before.
+    pub write: Option<(usize, isize)>,
+    /// Input consumed by the step, which the front-end has to give back
+    /// when undoing it.
+    pub input: Option<isize>,
+    pub output: Option<isize>
+}
+
+/// Bounds on how much work a machine may do before it is stopped with an
+/// error. Steps and time are counted from when the limits are set, so
+/// setting them again gives a stopped machine a fresh budget to resume with.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub steps: Option<usize>,
+    pub time: Option<Duration>,
+    /// Maximum number of words of memory.
+    pub memory: Option<usize>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    Error,
+    Wrap
+}
+
+/// How `add` and `mul` treat results that don't fit in a word of `bits`
+/// bits. Memory is made of `isize`, so `bits` must be in `1..=64`; programs
+/// that need `i128` or arbitrary precision words run on a `wide::WideVm`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arithmetic {
+    pub bits: u32,
+    pub overflow: Overflow
+}
+
+impl Default for Arithmetic {
+    fn default() -> Arithmetic {
+        Arithmetic { bits: 64, overflow: Overflow::Error }
+    }
+}
+
+impl Arithmetic {
+    /// Fits `value` into a word, or returns `None` if it overflows and
+    /// overflow is an error. Panics if `bits` isn't in `1..=64`, which
+    /// `Vm::set_arithmetic` checks.
+    pub fn fit(&self, value: i128) -> Option<isize> {
+        let shift = 128 - self.bits;
+        // sign extend from the top bit of the word
+        let wrapped = (value << shift) >> shift;
+        match self.overflow {
+            Overflow::Wrap => Some(wrapped as isize),
+            Overflow::Error if wrapped == value => Some(value as isize),
+            Overflow::Error => None
+        }
+    }
+
+    pub fn add(&self, a: isize, b: isize) -> Option<isize> {
+        self.fit(a as i128 + b as i128)
+    }
+
+    pub fn mul(&self, a: isize, b: isize) -> Option<isize> {
+        self.fit(a as i128 * b as i128)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Op {
+    Builtin(Opcode),
+    // index into the machine's extension opcodes
+    Extension(usize)
+}
+
+/// An instruction word split into its opcode and mode digits. Invalid mode
+/// digits are kept as they are, since they are only an error if the
+/// parameter is actually used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Decoded {
+    op: Op,
+    modes: [u8; 3]
+}
+
+impl Decoded {
+    fn new(code: isize, extensions: &[(Spec, usize)]) -> Result<Decoded, Error> {
+        let digit = |pos: u32| ((code / 10_isize.pow(pos + 2)) % 10) as u8;
+        let op = match Opcode::decode(code) {
+            Ok(opcode) => Op::Builtin(opcode),
+            Err(err) => match extensions.iter().position(|(spec, _)| spec.code == code % 100) {
+                Some(index) => Op::Extension(index),
+                None => return Err(err)
+            }
+        };
+        Ok(Decoded { op, modes: [digit(0), digit(1), digit(2)] })
+    }
+
+    fn mode(&self, pos: usize) -> Result<Mode, Error> {
+        Mode::from_digit(self.modes[pos] as isize)
+    }
+}
+
+// checking the clock every step would dominate the cost of simple instructions
+const DEADLINE_CHECK_INTERVAL: usize = 1024;
+
+/// The execution core shared by every front-end: memory, registers and the
+/// instruction set, with all I/O delegated to an `Io` implementation.
+///
+/// Clones share the same trace sink and extensions.
+#[derive(Clone)]
+pub struct Vm {
+    pc: usize,
+    mem: Memory,
+    program: Vec<isize>,
+    relbase: isize,
+    watchpoints: Vec<RangeInclusive<usize>>,
+    trace: Option<Arc<Mutex<dyn TraceSink + Send>>>,
+    steps: usize,
+    limits: Limits,
+    budget_start: usize,
+    deadline: Option<Instant>,
+    // decoded instructions by address, tagged with the word they were
+    // decoded from so that entries left over from before a reset are ignored
+    cache: Option<Vec<Option<(isize, Decoded)>>>,
+    profile: Option<Profile>,
+    history: Option<Vec<Undo>>,
+    transcript: Option<Transcript>,
+    // extra opcodes, with the index of the extension that runs them
+    extensions: Vec<(Spec, usize)>,
+    handlers: Vec<Arc<Mutex<dyn Extension + Send>>>,
+    arithmetic: Arithmetic,
+    // the write made by the current step, for the history
+    last_write: Option<(usize, isize)>,
+    // addresses of the last few instructions, for faults
+    recent: VecDeque<usize>
+}
+
+impl Vm {
+    pub fn new(mem: &[isize]) -> Vm {
+        Vm {
+            mem: Memory::new(mem),
+            program: mem.to_vec(),
+            pc: 0,
+            relbase: 0,
+            watchpoints: vec![],
+            trace: None,
+            steps: 0,
+            limits: Limits::default(),
+            budget_start: 0,
+            deadline: None,
+            cache: Some(vec![None; mem.len()]),
+            profile: None,
+            history: None,
+            transcript: None,
+            extensions: vec![],
+            handlers: vec![],
+            arithmetic: Arithmetic::default(),
+            last_write: None,
+            recent: VecDeque::with_capacity(RECENT)
+        }
+    }
+
+    /// Starts collecting a fresh execution profile, or stops and drops it.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = if enabled { Some(Profile::default()) } else { None };
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) -> Result<(), Error> {
+        if !(1..=64).contains(&arithmetic.bits) {
+            return Err(Error::InvalidWordSize(arithmetic.bits));
+        }
+        self.arithmetic = arithmetic;
+        Ok(())
+    }
+
+    /// Starts keeping an undo log of every step, or stops and drops it.
+    pub fn set_history(&mut self, enabled: bool) {
+        self.history = if enabled { Some(vec![]) } else { None };
+    }
+
+    pub fn history(&self) -> Option<&[Undo]> {
+        self.history.as_deref()
+    }
+
+    /// Undoes the most recent step in the undo log, returning what it did.
+    /// Output can't be taken back, and any input has to be handed back to
+    /// the front-end by the caller.
+    pub fn step_back(&mut self) -> Option<Undo> {
+        let undo = self.history.as_mut()?.pop()?;
+        if let Some((addr, old)) = undo.write {
+            self.store(addr, old);
+        }
+        self.pc = undo.pc;
+        self.relbase = undo.relbase;
+        self.steps = undo.step;
+        Some(undo)
+    }
+
+    /// Starts recording a fresh transcript of the machine's I/O, or stops
+    /// and drops it.
+    pub fn set_recording(&mut self, enabled: bool) {
+        self.transcript = if enabled { Some(Transcript::default()) } else { None };
+    }
+
+    pub fn transcript(&self) -> Option<&Transcript> {
+        self.transcript.as_ref()
+    }
+
+    fn record(&mut self, entry: Entry) {
+        if let Some(transcript) = &mut self.transcript {
+            transcript.entries.push(entry);
+        }
+    }
+
+    /// Adds the opcodes of `extension` to the instruction set. They can't
+    /// replace a built-in opcode or one added before.
+    pub fn extend<E: Extension + Send + 'static>(&mut self, extension: Arc<Mutex<E>>) -> Result<(), Error> {
+        let specs = extension.lock().unwrap_or_else(|e| e.into_inner()).opcodes();
+        for (i, spec) in specs.iter().enumerate() {
+            if !(0..100).contains(&spec.code) || spec.arity > 3 {
+                return Err(Error::InvalidOpcode(spec.code));
+            }
+            let taken = Opcode::decode(spec.code).is_ok()
+                || self.extensions().chain(&specs[..i]).any(|s| s.code == spec.code);
+            if taken {
+                return Err(Error::DuplicateOpcode(spec.code));
+            }
+        }
+        let index = self.handlers.len();
+        self.extensions.extend(specs.into_iter().map(|spec| (spec, index)));
+        self.handlers.push(extension);
+        Ok(())
+    }
+
+    /// The opcodes added by extensions.
+    pub fn extensions(&self) -> impl Iterator<Item = &Spec> {
+        self.extensions.iter().map(|(spec, _)| spec)
+    }
+
+    /// Turns the decoded instruction cache on or off. It is on by default.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cache = if enabled { Some(vec![None; self.mem.dense().len()]) } else { None };
+    }
+
+    fn decode(&mut self) -> Result<Decoded, Error> {
+        let (pc, code) = (self.pc, self.read(self.pc));
+        let cache = match &mut self.cache {
+            Some(cache) => cache,
+            None => return Decoded::new(code, &self.extensions)
+        };
+        if let Some(Some((tag, decoded))) = cache.get(pc) {
+            if *tag == code {
+                return Ok(*decoded);
+            }
+        }
+        let decoded = Decoded::new(code, &self.extensions)?;
+        // only the program is cached, code can be written anywhere beyond it
+        if let Some(entry) = cache.get_mut(pc) {
+            *entry = Some((code, decoded));
+        }
+        Ok(decoded)
+    }
+
+    /// Number of instructions executed since the machine was created or reset.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget_start = self.steps;
+        self.deadline = limits.time.map(|t| Instant::now() + t);
+        self.limits = limits;
+    }
+
+    fn check_limits(&self) -> Result<(), Error> {
+        let (pc, steps) = (self.pc, self.steps);
+        if self.limits.steps.is_some_and(|max| steps - self.budget_start >= max) {
+            return Err(Error::StepLimitExceeded { pc, steps });
+        }
+        if let Some(deadline) = self.deadline {
+            if steps.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+                return Err(Error::DeadlineExceeded { pc, steps });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_mem(&self, pos: usize) -> Result<(), Error> {
+        match self.limits.memory.map(|max| (max, self.mem.size_after(pos))) {
+            Some((max, size)) if size > max => {
+                Err(Error::MemoryLimitExceeded { pc: self.pc, steps: self.steps, size })
+            },
+            _ => Ok(())
+        }
+    }
+
+    /// Captures the machine state. The I/O queues are left empty, since
+    /// they belong to the front-end.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc,
+            relbase: self.relbase,
+            mem: self.mem.dense().to_vec(),
+            pages: self.mem.pages().into_iter().map(|(addr, words)| (addr, words.to_vec())).collect(),
+            program: self.program.clone(),
+            input: vec![],
+            output: vec![]
+        }
+    }
+
+    pub fn restore(snapshot: &Snapshot) -> Vm {
+        let mut mem = Memory::new(&snapshot.mem);
+        for (addr, words) in &snapshot.pages {
+            for (i, &value) in words.iter().enumerate().filter(|&(_, &v)| v != 0) {
+                mem.set(addr + i, value);
+            }
+        }
+        Vm {
+            pc: snapshot.pc,
+            relbase: snapshot.relbase,
+            mem,
+            ..Vm::new(&snapshot.program)
+        }
+    }
+
+    /// Reports every read and write of `addrs` to the trace sink.
+    pub fn watch(&mut self, addrs: RangeInclusive<usize>) {
+        self.watchpoints.push(addrs);
+    }
+
+    pub fn unwatch(&mut self, addrs: &RangeInclusive<usize>) {
+        self.watchpoints.retain(|w| w != addrs);
+    }
+
+    pub fn watchpoints(&self) -> &[RangeInclusive<usize>] {
+        &self.watchpoints
+    }
+
+    pub fn set_trace_sink<S: TraceSink + Send + 'static>(&mut self, sink: Arc<Mutex<S>>) {
+        self.trace = Some(sink);
+    }
+
+    fn trace(&self, kind: AccessKind, addr: usize, old: isize, new: isize) {
+        if !self.watchpoints.iter().any(|w| w.contains(&addr)) {
+            return;
+        }
+        if let (Some(sink), Ok(d)) = (&self.trace, Decoded::new(self.read(self.pc), &self.extensions)) {
+            let opcode = match d.op {
+                Op::Builtin(opcode) => AccessOp::Builtin(opcode),
+                Op::Extension(index) => AccessOp::Extension(self.extensions[index].0)
+            };
+            let access = Access { kind, addr, old, new, pc: self.pc, opcode };
+            // a poisoned sink only means another holder panicked mid-record
+            sink.lock().unwrap_or_else(|e| e.into_inner()).record(&access);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.relbase = 0;
+        self.mem = Memory::new(&self.program);
+        self.steps = 0;
+        self.recent.clear();
+        self.set_limits(self.limits.clone());
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        if self.transcript.is_some() {
+            self.set_recording(true);
+        }
+    }
+
+    /// The program as loaded, before it changed anything.
+    pub fn program(&self) -> &[isize] {
+        &self.program
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    pub fn relbase(&self) -> isize {
+        self.relbase
+    }
+
+    pub fn set_relbase(&mut self, relbase: isize) {
+        self.relbase = relbase;
+    }
+
+    /// Memory up to the end of the program. Anything written beyond it is
+    /// only available through `read`.
+    pub fn mem(&self) -> &[isize] {
+        self.mem.dense()
+    }
+
+    /// Reads memory the way the program would, so addresses never written
+    /// read as 0.
+    pub fn read(&self, addr: usize) -> isize {
+        self.mem.get(addr)
+    }
+
+    /// The most words of memory the machine has had allocated at once.
+    pub fn peak_memory(&self) -> usize {
+        self.mem.peak()
+    }
+
+    /// Patches memory from outside the program, so it isn't traced.
+    pub fn write(&mut self, addr: usize, value: isize) {
+        self.store(addr, value);
+    }
+
+    /// Runs until the machine halts or is waiting for input.
+    pub fn run<I: Io + ?Sized>(&mut self, io: &mut I) -> Result<Step, Error> {
+        loop {
+            match self.step(io)? {
+                Step::Continue | Step::Output(_) => continue,
+                step => return Ok(step)
+            }
+        }
+    }
+
+    fn addr(&self, addr: isize) -> Result<usize, Error> {
+        addr.try_into().map_err(|_| Error::IndexOutOfBounds { pc: self.pc, addr })
+    }
+
+    fn relative(&self, offset: isize) -> Result<usize, Error> {
+        self.addr(self.relbase.checked_add(offset).ok_or(Error::ArithmeticOverflow { pc: self.pc })?)
+    }
+
+    pub(crate) fn get_pos(&self, d: Decoded, pos: usize) -> Result<usize, Error> {
+        let param = self.read(self.pc + pos + 1);
+        match d.mode(pos)? {
+            Mode::Position => self.addr(param),
+            Mode::Immediate => Err(Error::InvalidParameterMode(1)),
+            Mode::Relative => self.relative(param)
+        }
+    }
+
+    pub(crate) fn get_param(&self, d: Decoded, pos: usize) -> Result<isize, Error> {
+        let param = self.read(self.pc + pos + 1);
+        let pos = match d.mode(pos)? {
+            Mode::Position => self.addr(param)?,
+            Mode::Immediate => return Ok(param),
+            Mode::Relative => self.relative(param)?
+        };
+        let value = self.read(pos);
+        if !self.watchpoints.is_empty() {
+            self.trace(AccessKind::Read, pos, value, value);
+        }
+        Ok(value)
+    }
+
+    pub(crate) fn set_mem(&mut self, pos: usize, value: isize) -> Result<(), Error> {
+        self.check_mem(pos)?;
+        if !self.watchpoints.is_empty() {
+            self.trace(AccessKind::Write, pos, self.read(pos), value);
+        }
+        if self.history.is_some() {
+            self.last_write = Some((pos, self.read(pos)));
+        }
+        self.store(pos, value);
+        Ok(())
+    }
+
+    fn store(&mut self, pos: usize, value: isize) {
+        self.mem.set(pos, value);
+        // self-modifying code, the instruction at `pos` has to be decoded again
+        if let Some(entry) = self.cache.as_mut().and_then(|c| c.get_mut(pos)) {
+            *entry = None;
+        }
+    }
+
+    fn jump_target(&self, d: Decoded) -> Result<usize, Error> {
+        self.get_param(d, 1)?.try_into()
+            .map_err(|_| Error::BadValueAtPosition(self.pc + 2))
+    }
+
+    /// Executes a single instruction. If this fails the instruction has no
+    /// effect, so the machine can carry on once the problem is dealt with.
+    pub fn step<I: Io + ?Sized>(&mut self, io: &mut I) -> Result<Step, Error> {
+        self.check_limits()?;
+        self.last_write = None;
+        let start = self.pc;
+        // the instruction may overwrite itself, so keep the word it started as
+        let before = (self.profile.is_some() || self.history.is_some())
+            .then(|| (self.pc, self.relbase, self.read(self.pc)));
+        let step = self.execute(io).map_err(|err| self.fault(err))?;
+        if let Step::Continue | Step::Output(_) = step {
+            self.steps += 1;
+            if self.recent.len() == RECENT {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(start);
+        }
+        if let Some(transcript) = &mut self.transcript {
+            transcript.steps = self.steps;
+        }
+        if let Some((pc, relbase, code)) = before {
+            if step != Step::ExpectingInput {
+                // extension opcodes aren't profiled
+                let opcode = Opcode::decode(code).ok();
+                if let (Some(profile), Some(opcode)) = (&mut self.profile, opcode) {
+                    profile.record(pc, opcode, self.pc);
+                }
+                let write = self.last_write.take();
+                let input = write.filter(|_| opcode == Some(Opcode::In)).map(|(addr, _)| self.read(addr));
+                if let (Some(history), false) = (&mut self.history, step == Step::Halted) {
+                    history.push(Undo {
+                        step: self.steps - 1,
+                        pc,
+                        relbase,
+                        write,
+                        input,
+                        output: match step {
+                            Step::Output(v) => Some(v),
+                            _ => None
+                        }
+                    });
+                }
+            }
+        }
+        Ok(step)
+    }
+
+    // the words of the instruction at `pc`
+    fn instruction(&self, pc: usize) -> Vec<isize> {
+        let arity = match Decoded::new(self.read(pc), &self.extensions).map(|d| d.op) {
+            Ok(Op::Builtin(opcode)) => opcode.arity(),
+            Ok(Op::Extension(index)) => self.extensions[index].0.arity,
+            Err(_) => 0
+        };
+        (pc..=pc + arity).map(|addr| self.read(addr)).collect()
+    }
+
+    /// Adds where the machine is to an error raised by the instruction at
+    /// the current address. Errors from I/O or limits are returned as they
+    /// are.
+    pub(crate) fn fault(&self, error: Error) -> Error {
+        match error {
+            Error::InvalidOpcode(_) | Error::BadValueAtPosition(_) | Error::InvalidParameterMode(_) |
+                Error::ArithmeticOverflow { .. } | Error::IndexOutOfBounds { .. } => {},
+            error => return error
+        }
+        let instruction = self.instruction(self.pc);
+        let modes = (0..instruction.len() - 1)
+            .map_while(|pos| Mode::decode(instruction[0], pos).ok())
+            .collect();
+        Error::Fault(Box::new(Fault {
+            error,
+            pc: self.pc,
+            instruction,
+            modes,
+            steps: self.steps,
+            recent: self.recent.iter().map(|&pc| (pc, self.instruction(pc))).collect()
+        }))
+    }
+
+    fn execute_extension(&mut self, d: Decoded, index: usize) -> Result<Step, Error> {
+        let (spec, handler) = self.extensions[index];
+        // modes are checked up front so handlers don't stop halfway through
+        for pos in 0..spec.arity {
+            if d.mode(pos)? == Mode::Immediate && spec.writes && pos + 1 == spec.arity {
+                return Err(Error::InvalidParameterMode(1));
+            }
+        }
+        let handler = self.handlers[handler].clone();
+        let mut call = Call::new(self, d, spec);
+        handler.lock().unwrap_or_else(|e| e.into_inner()).execute(&mut call)?;
+        let next = call.next();
+        self.pc = next;
+        Ok(Step::Continue)
+    }
+
+    fn execute<I: Io + ?Sized>(&mut self, io: &mut I) -> Result<Step, Error> {
+        let d = self.decode()?;
+        let opcode = match d.op {
+            Op::Builtin(opcode) => opcode,
+            Op::Extension(index) => return self.execute_extension(d, index)
+        };
+        match opcode {
+            Opcode::Add => {
+                let out_pos = self.get_pos(d, 2)?;
+                let value = self.arithmetic.add(self.get_param(d, 0)?, self.get_param(d, 1)?)
+                    .ok_or(Error::ArithmeticOverflow { pc: self.pc })?;
+                self.set_mem(out_pos, value)?;
+                self.pc += 4;
+            },
+            Opcode::Mul => {
+                let out_pos = self.get_pos(d, 2)?;
+                let value = self.arithmetic.mul(self.get_param(d, 0)?, self.get_param(d, 1)?)
+                    .ok_or(Error::ArithmeticOverflow { pc: self.pc })?;
+                self.set_mem(out_pos, value)?;
+                self.pc += 4;
+            },
+            Opcode::In => {
+                let out_pos = self.get_pos(d, 0)?;
+                // check before taking the input, so it isn't lost on failure
+                self.check_mem(out_pos)?;
+                match io.input()? {
+                    Some(value) => {
+                        self.set_mem(out_pos, value)?;
+                        self.record(Entry::Input { step: self.steps, value });
+                        self.pc += 2;
+                    },
+                    None => return Ok(Step::ExpectingInput)
+                }
+            },
+            Opcode::Out => {
+                let output = self.get_param(d, 0)?;
+                io.output(output)?;
+                self.record(Entry::Output { step: self.steps, value: output });
+                self.pc += 2;
+                return Ok(Step::Output(output));
+            },
+            Opcode::Jnz => {
+                if self.get_param(d, 0)? != 0 {
+                    self.pc = self.jump_target(d)?;
+                } else {
+                    self.pc += 3;
+                }
+            },
+            Opcode::Jz => {
+                if self.get_param(d, 0)? == 0 {
+                    self.pc = self.jump_target(d)?;
+                } else {
+                    self.pc += 3;
+                }
+            },
+            Opcode::Lt => {
+                let out_pos = self.get_pos(d, 2)?;
+                let value = (self.get_param(d, 0)? < self.get_param(d, 1)?) as isize;
+                self.set_mem(out_pos, value)?;
+                self.pc += 4;
+            },
+            Opcode::Eq => {
+                let out_pos = self.get_pos(d, 2)?;
+                let value = (self.get_param(d, 0)? == self.get_param(d, 1)?) as isize;
+                self.set_mem(out_pos, value)?;
+                self.pc += 4;
+            },
+            Opcode::Arb => {
+                self.relbase = self.relbase.checked_add(self.get_param(d, 0)?)
+                    .ok_or(Error::ArithmeticOverflow { pc: self.pc })?;
+                self.pc += 2;
+            },
+            Opcode::Hlt => {
+                io.halt()?;
+                self.record(Entry::Halt { step: self.steps });
+                return Ok(Step::Halted);
+            }
+        }
+
+        Ok(Step::Continue)
+    }
+}
+
 #[derive(Clone)]
 pub struct DataStream(Sender<Signal>, Receiver<Signal>);
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Signal {
     Value(isize),
     Exiting
 }
 
 impl Signal {
-    pub fn value(self) -> isize {
+    pub fn value(self) -> Result<isize, Error> {
         match self {
-            Signal::Value(v) => v,
-            _ => panic!("not a value")
+            Signal::Value(v) => Ok(v),
+            signal => Err(Error::UnexpectedSignal(format!("{:?}", signal)))
         }
     }
 }
 
-
 impl DataStream {
     pub fn new() -> DataStream {
         let (sender, receiver) = channel();
@@ -78,30 +1058,55 @@ impl DataStream {
         Ok(self.1.recv()?)
     }
 
-    pub fn try_iter(&self) -> TryIter<Signal> {
+    pub fn try_iter(&self) -> TryIter<'_, Signal> {
         self.1.try_iter()
     }
 }
 
+impl Default for DataStream {
+    fn default() -> DataStream {
+        DataStream::new()
+    }
+}
+
 #[derive(Clone)]
-pub struct IntcodeComputer {
-    pc: usize,
-    mem: Vec<isize>,
-    program: Vec<isize>,
-    relbase: isize,
+struct Streams {
     input: DataStream,
     output: DataStream
 }
 
+impl Io for Streams {
+    fn input(&mut self) -> Result<Option<isize>, Error> {
+        match self.input.recv()? {
+            Signal::Value(v) => Ok(Some(v)),
+            Signal::Exiting => Ok(None)
+        }
+    }
+
+    fn output(&mut self, value: isize) -> Result<(), Error> {
+        self.output.send(Signal::Value(value))
+    }
+
+    fn halt(&mut self) -> Result<(), Error> {
+        self.output.send(Signal::Exiting)
+    }
+}
+
+/// Front-end that blocks on a channel for input, suitable for running each
+/// machine on its own thread.
+#[derive(Clone)]
+pub struct IntcodeComputer {
+    vm: Vm,
+    streams: Streams,
+    compiled: Option<Arc<Compiled>>
+}
+
 impl IntcodeComputer {
     pub fn new_with_streams(mem: &[isize], input: DataStream, output: DataStream) -> IntcodeComputer {
         IntcodeComputer {
-            mem: mem.to_vec(),
-            program: mem.to_vec(),
-            pc: 0,
-            relbase: 0,
-            input,
-            output
+            vm: Vm::new(mem),
+            streams: Streams { input, output },
+            compiled: None
         }
     }
 
@@ -111,83 +1116,86 @@ impl IntcodeComputer {
         IntcodeComputer::new_with_streams(mem, input, output)
     }
 
-    pub fn run(&mut self) -> Result<(), Error> {
-        loop {
-            match self.next() {
-                Some(res) => res?,
-                None => break
-            }
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
+
+    /// Captures the machine along with everything queued on its streams.
+    /// The queues are drained and refilled in order, so this shouldn't race
+    /// with another thread using the same streams.
+    pub fn snapshot(&self) -> Result<Snapshot, Error> {
+        let input = self.streams.input.try_iter().collect::<Vec<_>>();
+        let output = self.streams.output.try_iter().collect::<Vec<_>>();
+        for signal in &input {
+            self.streams.input.send(*signal)?;
         }
-        Ok(())
+        for signal in &output {
+            self.streams.output.send(*signal)?;
+        }
+        Ok(Snapshot { input, output, ..self.vm.snapshot() })
     }
 
-    pub fn reset(&mut self) {
-        let mut i = self.input.1.try_iter();
-        while let Some(_) = i.next() {}
-        let mut i = self.output.1.try_iter();
-        while let Some(_) = i.next() {}
-        self.pc = 0;
-        self.relbase = 0;
-        self.mem = self.program.clone();
-    }
-
-    fn get_pos(&self, pos: usize) -> Result<usize, Error> {
-        let code = self.mem[self.pc];
-        let mode = (code / 10_isize.pow(pos as u32 + 2)) % 10;
-        if mode == 0 {
-            Ok(self.mem[self.pc + pos + 1] as usize)
-        } else if mode == 1 {
-            Err(Error::InvalidParameterMode(mode))
-        } else if mode == 2 {
-            Ok((self.relbase + self.mem[self.pc + pos + 1]) as usize)
-        } else {
-            Err(Error::InvalidParameterMode(mode))
-        }
-    }
-
-    fn get_param(&self, pos: usize) -> Result<isize, Error> {
-        let code = self.mem[self.pc];
-        let mode = (code / 10_isize.pow(pos as u32 + 2)) % 10;
-        let pos = if mode == 0 {
-            self.mem[self.pc + pos + 1] as usize
-        } else if mode == 1 {
-            return Ok(self.mem[self.pc + pos + 1]);
-        } else if mode == 2 {
-            (self.relbase + self.mem[self.pc + pos + 1]) as usize
-        } else {
-            return Err(Error::InvalidParameterMode(mode));
+    /// Rebuilds a machine from `snapshot`, with new streams holding its
+    /// queued I/O.
+    pub fn restore(snapshot: &Snapshot) -> Result<IntcodeComputer, Error> {
+        let comp = IntcodeComputer {
+            vm: Vm::restore(snapshot),
+            streams: Streams { input: DataStream::new(), output: DataStream::new() },
+            compiled: None
         };
-        if pos >= self.mem.len() {
-            Ok(0)
-        } else {
-            Ok(self.mem[pos])
+        for signal in &snapshot.input {
+            comp.streams.input.send(*signal)?;
+        }
+        for signal in &snapshot.output {
+            comp.streams.output.send(*signal)?;
         }
+        Ok(comp)
     }
 
-    fn set_mem(&mut self, pos: usize, value: isize) {
-        if pos >= self.mem.len() {
-            self.mem.resize(pos + 1, 0);
+    /// Compiles the program so that `run` executes it as closures instead of
+    /// interpreting it. Clones share the compiled code.
+    pub fn compile(&mut self) {
+        self.compiled = Some(Arc::new(Compiled::new(self.vm.program())));
+    }
+
+    pub fn run(&mut self) -> Result<(), Error> {
+        if let Some(compiled) = &self.compiled {
+            compiled.run(&mut self.vm, &mut self.streams)?;
+            return Ok(());
+        }
+        for res in self {
+            res?
         }
-        self.mem[pos] = value;
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        self.streams.input.try_iter().for_each(drop);
+        self.streams.output.try_iter().for_each(drop);
+        self.vm.reset();
     }
 
     pub fn send(&self, input: Signal) -> Result<(), Error> {
-        self.input.send(input)
+        self.streams.input.send(input)
     }
 
     pub fn send_ascii(&self, input: &str) -> Result<(), Error> {
         for c in input.chars().map(|c| c as isize) {
-            self.input.send(Signal::Value(c))?
+            self.streams.input.send(Signal::Value(c))?
         }
         Ok(())
     }
 
     pub fn recv(&self) -> Result<Signal, Error> {
-        self.output.recv()
+        self.streams.output.recv()
     }
 
-    pub fn output_iter<'a>(&'a self) -> impl Iterator<Item = isize> + 'a {
-        self.output.try_iter().filter_map(|x| match x {
+    pub fn output_iter(&self) -> impl Iterator<Item = isize> + '_ {
+        self.streams.output.try_iter().filter_map(|x| match x {
             Signal::Value(v) => Some(v),
             _ => None
         })
@@ -201,100 +1209,271 @@ impl IntcodeComputer {
     }
 }
 
-macro_rules! opt_err {
-    ($param:expr) => {
-        match $param {
-            Ok(v) => v,
-            Err(e) => return Some(Err(e))
+impl Iterator for IntcodeComputer {
+    type Item = Result<(), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.vm.step(&mut self.streams) {
+            Ok(Step::Continue) | Ok(Step::Output(_)) => Some(Ok(())),
+            // the only way the input stream stops blocking is on `Signal::Exiting`
+            Ok(Step::ExpectingInput) | Ok(Step::Halted) => None,
+            Err(e) => Some(Err(e))
         }
     }
 }
 
-impl Iterator for IntcodeComputer {
-    type Item = Result<(), Error>;
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::intcode2;
+    use crate::memory::PAGE_SIZE;
 
-    fn next(&mut self) -> Option<Self::Item> {
+    // outputs 999, 1000 or 1001 depending on whether the input is below, equal to or above 8
+    const COMPARE_TO_8: [isize; 47] = [
+        3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,
+        1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,
+        999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99
+    ];
 
-        // parse opcode
-        let code = self.mem[self.pc];
-        let opcode = ((code / 10_isize.pow(0)) % 10) + 10 * ((code / 10_isize.pow(1)) % 10);
+    #[test]
+    fn test_front_ends_agree() -> Result<(), Error> {
+        for input in 6..11 {
+            let mut comp = IntcodeComputer::new(&COMPARE_TO_8);
+            comp.send(Signal::Value(input))?;
+            comp.run()?;
+            let channel = comp.output_iter().collect::<Vec<_>>();
 
-        match opcode {
-            1 => {
-                let out_pos = opt_err!(self.get_pos(2)) as usize;
-                self.set_mem(out_pos, opt_err!(self.get_param(0)) + opt_err!(self.get_param(1)));
-                self.pc += 4;
-            },
-            2 => {
-                let out_pos = opt_err!(self.get_pos(2)) as usize;
-                self.set_mem(out_pos, opt_err!(self.get_param(0)) * opt_err!(self.get_param(1)));
-                self.pc += 4;
-            },
-            3 => {
-                let out_pos = opt_err!(self.get_pos(0)) as usize;
-                self.set_mem(out_pos, match self.input.recv() {
-                    Ok(v) => match v {
-                        Signal::Value(v) => v,
-                        Signal::Exiting => return None
-                    },
-                    Err(e) => return Some(Err(e))
-                });
-                self.pc += 2;
-            },
-            4 => {
-                let output = opt_err!(self.get_param(0));
-                opt_err!(self.output.send(Signal::Value(output)));
-                self.pc += 2;
-            },
-            5 => {
-                if opt_err!(self.get_param(0)) != 0 {
-                    self.pc = match opt_err!(self.get_param(1)).try_into() {
-                        Ok(v) => v,
-                        Err(_) => return Some(Err(Error::BadValueAtPosition(self.pc)))
-                    };
-                } else {
-                    self.pc += 3;
-                }
-            },
-            6 => {
-                if opt_err!(self.get_param(0)) == 0 {
-                    self.pc = match opt_err!(self.get_param(1)).try_into() {
-                        Ok(v) => v,
-                        Err(_) => return Some(Err(Error::BadValueAtPosition(self.pc)))
-                    };
-                } else {
-                    self.pc += 3;
-                }
-            },
-            7 => {
-                let out_pos = opt_err!(self.get_pos(2)) as usize;
-                self.set_mem(out_pos, if opt_err!(self.get_param(0)) < opt_err!(self.get_param(1)) {
-                    1
-                }  else {
-                    0
-                });
-                self.pc += 4;
-            },
-            8 => {
-                let out_pos = opt_err!(self.get_pos(2)) as usize;
-                self.set_mem(out_pos, if opt_err!(self.get_param(0)) == opt_err!(self.get_param(1)) {
-                    1
-                }  else {
-                    0
-                });
-                self.pc += 4;
-            },
-            9 => {
-                self.relbase += opt_err!(self.get_param(0));
-                self.pc += 2;
-            },
-            99 => {
-                opt_err!(self.output.send(Signal::Exiting));
-                return None;
-            },
-            _ => return Some(Err(Error::InvalidOpcode(opcode)))
+            let mut comp = intcode2::IntcodeComputer::new(&COMPARE_TO_8);
+            comp.send(input);
+            let mut polling = vec![];
+            while let intcode2::Signal::Output(v) = comp.run()? {
+                polling.push(v);
+            }
+
+            let mut vm = Vm::new(&COMPARE_TO_8);
+            let mut pending = Some(input);
+            let mut callback = vec![];
+            let step = vm.run(&mut Callbacks::new(|| pending.take(), |v| callback.push(v)))?;
+
+            assert_eq!(step, Step::Halted);
+            assert_eq!(channel, polling);
+            assert_eq!(channel, callback);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_callbacks_pause_on_missing_input() -> Result<(), Error> {
+        let mut vm = Vm::new(&[3,11,3,12,1,11,12,13,4,13,99,0,0,0]);
+        let mut inputs = vec![5];
+        let mut outputs = vec![];
+        assert_eq!(vm.run(&mut Callbacks::new(|| inputs.pop(), |v| outputs.push(v)))?, Step::ExpectingInput);
+        inputs.push(7);
+        assert_eq!(vm.run(&mut Callbacks::new(|| inputs.pop(), |v| outputs.push(v)))?, Step::Halted);
+        assert_eq!(outputs, vec![12]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_step_limit_and_resume() {
+        // counts up in [7] forever
+        let mut vm = Vm::new(&[101,1,7,7,1105,1,0,0]);
+        let limits = Limits { steps: Some(100), ..Limits::default() };
+        vm.set_limits(limits.clone());
+        let mut io = Callbacks::new(|| None, |_| ());
+        match vm.run(&mut io) {
+            Err(Error::StepLimitExceeded { pc: 0, steps: 100 }) => (),
+            res => panic!("expected the step limit, got {:?}", res)
+        }
+        assert_eq!(vm.read(7), 50);
+
+        vm.set_limits(limits);
+        match vm.run(&mut io) {
+            Err(Error::StepLimitExceeded { pc: 0, steps: 200 }) => (),
+            res => panic!("expected the step limit, got {:?}", res)
+        }
+        assert_eq!(vm.read(7), 100);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut vm = Vm::new(&[1105,1,0]);
+        vm.set_limits(Limits { time: Some(Duration::from_millis(10)), ..Limits::default() });
+        match vm.run(&mut Callbacks::new(|| None, |_| ())) {
+            Err(Error::DeadlineExceeded { pc: 0, steps }) => assert!(steps > 0),
+            res => panic!("expected the deadline, got {:?}", res)
+        }
+    }
+
+    #[test]
+    fn test_memory_limit() -> Result<(), Error> {
+        // reads an address into the next instruction, then writes to it
+        let mut vm = Vm::new(&[3,3,3,0,99]);
+        vm.set_limits(Limits { memory: Some(1000), ..Limits::default() });
+        let mut inputs = vec![1, 1_000_000_000];
+        match vm.run(&mut Callbacks::new(|| inputs.pop(), |_| ())) {
+            Err(Error::MemoryLimitExceeded { pc: 2, steps: 1, size }) => assert_eq!(size, 5 + PAGE_SIZE),
+            res => panic!("expected the memory limit, got {:?}", res)
+        }
+        assert_eq!(vm.peak_memory(), 5);
+
+        // the input wasn't consumed, so the program can carry on with an address inside the program
+        vm.write(3, 1);
+        assert_eq!(vm.run(&mut Callbacks::new(|| inputs.pop(), |_| ()))?, Step::Halted);
+        assert_eq!(vm.read(1), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparse_memory() -> Result<(), Error> {
+        // reads an address into the next instruction, writes the next input there, and repeats
+        let program = [3,3,3,0,1105,1,0];
+        let mut inputs = vec![1_000_000_000_000, 1, 1_000_000_000_001, 2, 20, 3];
+        inputs.reverse();
+        let mut vm = Vm::new(&program);
+        assert_eq!(vm.run(&mut Callbacks::new(|| inputs.pop(), |_| ()))?, Step::ExpectingInput);
+        assert_eq!(vm.read(1_000_000_000_000), 1);
+        assert_eq!(vm.read(1_000_000_000_001), 2);
+        assert_eq!(vm.read(20), 3);
+        assert_eq!(vm.read(2_000_000_000_000), 0);
+        assert_eq!(vm.peak_memory(), program.len() + 2 * PAGE_SIZE);
+
+        let restored = Vm::restore(&vm.snapshot());
+        assert_eq!(restored.read(1_000_000_000_001), 2);
+        assert_eq!(restored.peak_memory(), vm.peak_memory());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fault() {
+        // adds, outputs and then hits an invalid opcode
+        let program = [1101,2,3,7,104,5,77];
+        let mut vm = Vm::new(&program);
+        let err = vm.run(&mut Callbacks::new(|| None, |_| ())).unwrap_err();
+        assert!(matches!(err.cause(), Error::InvalidOpcode(77)));
+        assert!(error::Error::source(&err).is_some());
+        let fault = err.fault().unwrap();
+        assert_eq!((fault.pc, &fault.instruction, fault.steps), (6, &vec![77], 2));
+        assert_eq!(fault.recent, vec![(0, vec![1101,2,3,7]), (4, vec![104,5])]);
+        assert_eq!(err.to_string(), "\
+invalid opcode 77
+  at 0006: 77, step 2
+  ran 0000: ADD #2, #3 -> [7]
+  ran 0004: OUT #5");
+
+        // modes are decoded up to the invalid one
+        let err = Vm::new(&[31001,0,0,0]).run(&mut Callbacks::new(|| None, |_| ())).unwrap_err();
+        assert!(matches!(err.cause(), Error::InvalidParameterMode(3)));
+        assert_eq!(err.fault().unwrap().modes, vec![Mode::Position, Mode::Immediate]);
+        assert_eq!(err.to_string(), "invalid parameter mode 3\n  at 0000: 31001,0,0,0 (position, immediate), step 0");
+
+        // a bad value is reported at its operand
+        let err = Vm::new(&[1105,1,-1]).run(&mut Callbacks::new(|| None, |_| ())).unwrap_err();
+        assert_eq!(err.to_string(), "bad value at position 2\n  at 0000: 1105,1,-1 (immediate, immediate), step 0");
+    }
+
+    #[test]
+    fn test_signals() {
+        assert_eq!(Signal::Value(3).value().unwrap(), 3);
+        assert!(matches!(Signal::Exiting.value(), Err(Error::UnexpectedSignal(s)) if s == "Exiting"));
+        assert_eq!(intcode2::Signal::Output(4).output().unwrap(), 4);
+        assert_eq!(intcode2::Signal::ExpectingInput.output().unwrap_err().to_string(), "unexpected signal ExpectingInput");
+    }
+
+    #[test]
+    fn test_negative_addresses() {
+        let mut io = Callbacks::new(|| None, |_| ());
+        for (program, addr) in [(vec![204,-1,99], -1), (vec![4,-5,99], -5), (vec![109,-10,21101,1,1,3,99], -7)] {
+            let mut vm = Vm::new(&program);
+            match vm.run(&mut io).as_ref().map_err(Error::cause) {
+                Err(Error::IndexOutOfBounds { addr: a, .. }) => assert_eq!(*a, addr),
+                res => panic!("expected a negative address, got {:?}", res)
+            }
+        }
+        // the relative base overflowing, either way it's moved
+        for program in [vec![109,isize::MAX,204,1,99], vec![109,isize::MAX,109,1,99]] {
+            assert!(matches!(Vm::new(&program).run(&mut io).unwrap_err().cause(), Error::ArithmeticOverflow { pc: 2 }));
         }
+        assert_eq!(Vm::new(&[204,-1,99]).run(&mut io).unwrap_err().to_string(), "\
+address -1 out of bounds
+  at 0000: 204,-1 (relative), step 0");
+    }
+
+    #[test]
+    fn test_decode_cache_sees_self_modification() -> Result<(), Error> {
+        // outputs 5, overwrites its first instruction with hlt and jumps back to it
+        let program = [104,5,1101,99,0,0,1105,1,0];
+        for cached in [true, false] {
+            let mut vm = Vm::new(&program);
+            vm.set_decode_cache(cached);
+            vm.set_limits(Limits { steps: Some(100), ..Limits::default() });
+            for _ in 0..2 {
+                let mut output = vec![];
+                assert_eq!(vm.run(&mut Callbacks::new(|| None, |v| output.push(v)))?, Step::Halted);
+                assert_eq!(output, vec![5]);
+                assert_eq!(vm.steps(), 3);
+                vm.reset();
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_step_back() -> Result<(), Error> {
+        let mut vm = Vm::new(&COMPARE_TO_8);
+        vm.set_history(true);
+        let mut input = Some(8);
+        let mut output = vec![];
+        vm.run(&mut Callbacks::new(|| input.take(), |v| output.push(v)))?;
+        assert_eq!(output, vec![1000]);
 
-        Some(Ok(()))
+        let history = vm.history().unwrap();
+        assert_eq!(history.len(), vm.steps());
+        assert_eq!(history[0].input, Some(8));
+        assert_eq!(history.iter().filter_map(|undo| undo.output).collect::<Vec<_>>(), vec![1000]);
+
+        while let Some(undo) = vm.step_back() {
+            input = undo.input.or(input);
+        }
+        assert_eq!((vm.pc(), vm.steps()), (0, 0));
+        assert_eq!(vm.mem(), &COMPARE_TO_8[..]);
+
+        vm.run(&mut Callbacks::new(|| input.take(), |v| output.push(v)))?;
+        assert_eq!(output, vec![1000, 1000]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_arithmetic() -> Result<(), Error> {
+        // multiplies the two immediates and outputs the product
+        let square = |n: isize| [1102,n,n,7,4,7,99,0];
+        let run = |vm: &mut Vm| {
+            let mut output = None;
+            vm.run(&mut Callbacks::new(|| None, |v| output = Some(v))).map(|_| output.unwrap())
+        };
+
+        // day 9's large number check fits in the default 64 bits
+        assert_eq!(run(&mut Vm::new(&square(34915192)))?, 1219070632396864);
+        match run(&mut Vm::new(&square(1 << 32))).as_ref().map_err(Error::cause) {
+            Err(Error::ArithmeticOverflow { pc: 0 }) => (),
+            res => panic!("expected an overflow, got {:?}", res)
+        }
+
+        let mut vm = Vm::new(&square(1 << 16));
+        vm.set_arithmetic(Arithmetic { bits: 32, overflow: Overflow::Wrap })?;
+        assert_eq!(run(&mut vm)?, 0);
+        let mut vm = Vm::new(&square(46341));
+        vm.set_arithmetic(Arithmetic { bits: 32, overflow: Overflow::Wrap })?;
+        assert_eq!(run(&mut vm)?, (46341 * 46341_i64) as i32 as isize);
+        vm.reset();
+        vm.set_arithmetic(Arithmetic { bits: 32, overflow: Overflow::Error })?;
+        assert!(matches!(run(&mut vm).unwrap_err().cause(), Error::ArithmeticOverflow { pc: 0 }));
+
+        // words are at most an isize
+        for bits in [0, 65, 128] {
+            assert!(matches!(vm.set_arithmetic(Arithmetic { bits, overflow: Overflow::Wrap }), Err(Error::InvalidWordSize(b)) if b == bits));
+        }
+        assert_eq!(vm.arithmetic().bits, 32);
+        Ok(())
     }
 }
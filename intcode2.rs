@@ -1,34 +1,6 @@
-use std::fs;
-use std::io;
-use std::num;
-use std::convert::TryInto;
-
-#[derive(Debug)]
-pub enum Error {
-    IoError(io::Error),
-    ParseIntError(num::ParseIntError),
-    InvalidOpcode(isize),
-    BadValueAtPosition(usize),
-    InvalidParameterMode(isize)
-}
-
-impl From<io::Error> for Error {
-    fn from(err: io::Error) -> Error {
-        Error::IoError(err)
-    }
-}
-
-impl From<num::ParseIntError> for Error {
-    fn from(err: num::ParseIntError) -> Error {
-        Error::ParseIntError(err)
-    }
-}
-
-pub fn read_program(program: &str) -> Result<Vec<isize>, Error> {
-    fs::read_to_string(&program)?.split(",").map(|x| {
-        Ok(x.trim().parse::<isize>()?)
-    }).collect()
-}
+pub use crate::intcode::{Error, read_program};
+use crate::intcode::{self, Io, Step, Undo, Vm};
+use crate::snapshot::Snapshot;
 
 #[derive(Debug)]
 pub enum Signal {
@@ -39,113 +11,104 @@ pub enum Signal {
 }
 
 impl Signal {
-    pub fn output(self) -> isize {
+    pub fn output(self) -> Result<isize, Error> {
         match self {
-            Signal::Output(v) => v,
-            _ => panic!("expected an output, got: {:?}", self)
+            Signal::Output(v) => Ok(v),
+            signal => Err(Error::UnexpectedSignal(format!("{:?}", signal)))
         }
     }
 }
 
+/// Pending input, stored in reverse so the next value is at the end.
+#[derive(Clone, Default)]
+struct InputQueue(Vec<isize>);
+
+impl Io for InputQueue {
+    fn input(&mut self) -> Result<Option<isize>, Error> {
+        Ok(self.0.pop())
+    }
+
+    fn output(&mut self, _value: isize) -> Result<(), Error> {
+        // outputs are handed back to the caller as `Signal::Output`
+        Ok(())
+    }
+}
+
+/// Front-end that never blocks: `run` returns whenever the machine produces
+/// an output or needs more input.
 #[derive(Clone)]
 pub struct IntcodeComputer {
-    pc: usize,
-    mem: Vec<isize>,
-    program: Vec<isize>,
-    relbase: isize,
-    input: Vec<isize>
+    vm: Vm,
+    input: InputQueue
 }
 
 impl IntcodeComputer {
     pub fn new(mem: &[isize]) -> IntcodeComputer {
         IntcodeComputer {
-            mem: mem.to_vec(),
-            program: mem.to_vec(),
-            pc: 0,
-            relbase: 0,
-            input: vec![]
+            vm: Vm::new(mem),
+            input: InputQueue::default()
         }
     }
 
-    pub fn run(&mut self) -> Result<Signal, Error> {
-        loop {
-            let signal = match self.next() {
-                Some(res) => res?,
-                None => break
-            };
-            match signal {
-                Signal::Continue => {
-                    continue;
-                },
-                _ => return Ok(signal)
-            }
-        }
-        Ok(Signal::Exiting)
+    pub fn vm(&self) -> &Vm {
+        &self.vm
     }
 
-    pub fn reset(&mut self) {
-        self.pc = 0;
-        self.relbase = 0;
-        self.mem = self.program.clone();
+    pub fn vm_mut(&mut self) -> &mut Vm {
+        &mut self.vm
     }
 
-    fn get_pos(&self, pos: usize) -> Result<usize, Error> {
-        let code = self.mem[self.pc];
-        let mode = (code / 10_isize.pow(pos as u32 + 2)) % 10;
-        if mode == 0 {
-            Ok(self.mem[self.pc + pos + 1] as usize)
-        } else if mode == 1 {
-            Err(Error::InvalidParameterMode(mode))
-        } else if mode == 2 {
-            Ok((self.relbase + self.mem[self.pc + pos + 1]) as usize)
-        } else {
-            Err(Error::InvalidParameterMode(mode))
-        }
+    /// Captures the machine along with its pending input.
+    pub fn snapshot(&self) -> Snapshot {
+        let input = self.input.0.iter().rev().map(|&v| intcode::Signal::Value(v)).collect();
+        Snapshot { input, ..self.vm.snapshot() }
     }
 
-    fn get_param(&self, pos: usize) -> Result<isize, Error> {
-        let code = self.mem[self.pc];
-        let mode = (code / 10_isize.pow(pos as u32 + 2)) % 10;
-        let pos = if mode == 0 {
-            self.mem[self.pc + pos + 1] as usize
-        } else if mode == 1 {
-            return Ok(self.mem[self.pc + pos + 1]);
-        } else if mode == 2 {
-            (self.relbase + self.mem[self.pc + pos + 1]) as usize
-        } else {
-            return Err(Error::InvalidParameterMode(mode));
+    /// Rebuilds a machine from `snapshot`. Queued outputs are ignored, since
+    /// this front-end hands each one back as soon as it is produced.
+    pub fn restore(snapshot: &Snapshot) -> IntcodeComputer {
+        let mut comp = IntcodeComputer {
+            vm: Vm::restore(snapshot),
+            input: InputQueue::default()
         };
-        if pos >= self.mem.len() {
-            Ok(0)
-        } else {
-            Ok(self.mem[pos])
+        for signal in &snapshot.input {
+            if let intcode::Signal::Value(v) = signal {
+                comp.send(*v);
+            }
         }
+        comp
     }
 
-    fn set_mem(&mut self, pos: usize, value: isize) {
-        if pos >= self.mem.len() {
-            self.mem.resize(pos + 1, 0);
+    pub fn run(&mut self) -> Result<Signal, Error> {
+        for signal in self.by_ref() {
+            match signal? {
+                Signal::Continue => continue,
+                signal => return Ok(signal)
+            }
         }
-        self.mem[pos] = value;
+        Ok(Signal::Exiting)
+    }
+
+    pub fn reset(&mut self) {
+        self.vm.reset();
+    }
+
+    /// Undoes the last step in the machine's undo log, putting back any
+    /// input it consumed.
+    pub fn step_back(&mut self) -> Option<Undo> {
+        let undo = self.vm.step_back()?;
+        self.input.0.extend(undo.input);
+        Some(undo)
     }
 
     pub fn send(&mut self, input: isize) {
-        self.input.insert(0, input);
+        self.input.0.insert(0, input);
     }
 
     pub fn send_ascii(&mut self, input: &str) {
         let mut input = input.chars().map(|c| c as isize).rev().collect::<Vec<_>>();
-        input.append(&mut self.input);
-        self.input = input;
-    }
-}
-
-macro_rules! opt_err {
-    ($param:expr) => {
-        match $param {
-            Ok(v) => v,
-            Err(e) => return Some(Err(e))
-        }
+        input.append(&mut self.input.0);
+        self.input.0 = input;
     }
 }
 
@@ -153,84 +116,12 @@ impl Iterator for IntcodeComputer {
     type Item = Result<Signal, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-
-        // parse opcode
-        let code = self.mem[self.pc];
-        let opcode = ((code / 10_isize.pow(0)) % 10) + 10 * ((code / 10_isize.pow(1)) % 10);
-
-        match opcode {
-            1 => {
-                let out_pos = opt_err!(self.get_pos(2)) as usize;
-                self.set_mem(out_pos, opt_err!(self.get_param(0)) + opt_err!(self.get_param(1)));
-                self.pc += 4;
-            },
-            2 => {
-                let out_pos = opt_err!(self.get_pos(2)) as usize;
-                self.set_mem(out_pos, opt_err!(self.get_param(0)) * opt_err!(self.get_param(1)));
-                self.pc += 4;
-            },
-            3 => {
-                let out_pos = opt_err!(self.get_pos(0)) as usize;
-                if let Some(value) = self.input.pop() {
-                    self.set_mem(out_pos, value);
-                    self.pc += 2;
-                } else {
-                    return Some(Ok(Signal::ExpectingInput));
-                }
-            },
-            4 => {
-                let output = opt_err!(self.get_param(0));
-                self.pc += 2;
-                return Some(Ok(Signal::Output(output)));
-            },
-            5 => {
-                if opt_err!(self.get_param(0)) != 0 {
-                    self.pc = match opt_err!(self.get_param(1)).try_into() {
-                        Ok(v) => v,
-                        Err(_) => return Some(Err(Error::BadValueAtPosition(self.pc)))
-                    };
-                } else {
-                    self.pc += 3;
-                }
-            },
-            6 => {
-                if opt_err!(self.get_param(0)) == 0 {
-                    self.pc = match opt_err!(self.get_param(1)).try_into() {
-                        Ok(v) => v,
-                        Err(_) => return Some(Err(Error::BadValueAtPosition(self.pc)))
-                    };
-                } else {
-                    self.pc += 3;
-                }
-            },
-            7 => {
-                let out_pos = opt_err!(self.get_pos(2)) as usize;
-                self.set_mem(out_pos, if opt_err!(self.get_param(0)) < opt_err!(self.get_param(1)) {
-                    1
-                }  else {
-                    0
-                });
-                self.pc += 4;
-            },
-            8 => {
-                let out_pos = opt_err!(self.get_pos(2)) as usize;
-                self.set_mem(out_pos, if opt_err!(self.get_param(0)) == opt_err!(self.get_param(1)) {
-                    1
-                }  else {
-                    0
-                });
-                self.pc += 4;
-            },
-            9 => {
-                self.relbase += opt_err!(self.get_param(0));
-                self.pc += 2;
-            },
-            99 => {
-                return None;
-            },
-            _ => return Some(Err(Error::InvalidOpcode(opcode)))
+        match self.vm.step(&mut self.input) {
+            Ok(Step::Continue) => Some(Ok(Signal::Continue)),
+            Ok(Step::Output(v)) => Some(Ok(Signal::Output(v))),
+            Ok(Step::ExpectingInput) => Some(Ok(Signal::ExpectingInput)),
+            Ok(Step::Halted) => None,
+            Err(e) => Some(Err(e))
         }
-
-        Some(Ok(Signal::Continue))
     }
 }
@@ -0,0 +1,52 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+use common::intcode::read_program;
+use common::debugger::Debugger;
+
+fn fail<T>(message: String) -> T {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+const USAGE: &str = "usage: intdbg <program> [--input <file>] [--numeric]";
+
+fn main() -> io::Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let program = read_program(args.first().unwrap_or_else(|| fail(USAGE.to_string())))
+        .unwrap_or_else(|e| fail(format!("can't read program: {:?}", e)));
+    let input = args.iter().position(|a| a == "--input").map(|i| args.get(i + 1).unwrap_or_else(|| fail(USAGE.to_string())));
+    let numeric = args.iter().any(|a| a == "--numeric");
+
+    let mut dbg = Debugger::new(&program);
+    if let Some(path) = input {
+        // each line of the file is handed over when the program asks for input
+        for line in fs::read_to_string(path)?.lines() {
+            if numeric {
+                let values = line.split(',')
+                    .map(|v| v.trim().parse::<isize>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap_or_else(|_| fail(format!("bad input line: {}", line)));
+                dbg.queue_values(&values);
+            } else {
+                dbg.queue_line(line);
+            }
+        }
+    }
+
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(intdbg) ");
+        stdout.flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break
+        };
+        if !dbg.execute(&line, &mut stdout)? {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use std::panic;
+use std::process;
+use common::fuzz;
+
+const USAGE: &str = "usage: intfuzz [seed] [count] [steps]";
+
+fn main() {
+    let args = std::env::args().skip(1)
+        .map(|a| a.parse::<u64>().unwrap_or_else(|_| {
+            eprintln!("{}", USAGE);
+            process::exit(1)
+        }))
+        .collect::<Vec<_>>();
+    let seed = args.first().cloned().unwrap_or(0);
+    let count = args.get(1).cloned().unwrap_or(10000) as usize;
+    let steps = args.get(2).cloned().unwrap_or(10000) as usize;
+
+    // panics are reported as findings, not printed as they happen
+    panic::set_hook(Box::new(|_| ()));
+    let crashes = fuzz::fuzz(seed, count, steps);
+    for crash in &crashes {
+        println!("seed {}: {:?}", crash.seed, crash.finding);
+        println!("    program {}", crash.program.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(","));
+        println!("    inputs {:?}", crash.inputs);
+    }
+    println!("{} programs, {} findings", count, crashes.len());
+    if !crashes.is_empty() {
+        process::exit(1);
+    }
+}
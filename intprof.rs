@@ -0,0 +1,53 @@
+use std::fs;
+use std::io;
+use std::process;
+use common::intcode::{Callbacks, Vm, read_program};
+
+fn fail<T>(message: String) -> T {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+const USAGE: &str = "usage: intprof <program> [--input <file>] [--numeric] [--listing]";
+
+fn main() -> io::Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let program = read_program(args.first().unwrap_or_else(|| fail(USAGE.to_string())))
+        .unwrap_or_else(|e| fail(format!("can't read program: {:?}", e)));
+    let input = args.iter().position(|a| a == "--input").map(|i| args.get(i + 1).unwrap_or_else(|| fail(USAGE.to_string())));
+    let numeric = args.iter().any(|a| a == "--numeric");
+    let listing = args.iter().any(|a| a == "--listing");
+
+    let mut values = vec![];
+    if let Some(path) = input {
+        for line in fs::read_to_string(path)?.lines() {
+            if numeric {
+                values.extend(line.split(',').map(|v| v.trim().parse::<isize>()
+                    .unwrap_or_else(|_| fail(format!("bad input line: {}", line)))));
+            } else {
+                values.extend(line.chars().chain(Some('\n')).map(|c| c as isize));
+            }
+        }
+    }
+    values.reverse();
+
+    let mut vm = Vm::new(&program);
+    vm.set_profiling(true);
+    let mut io = Callbacks::new(|| values.pop(), |v| match v {
+        10 | 32..=126 => print!("{}", (v as u8) as char),
+        _ => println!("{}", v)
+    });
+    if let Err(e) = vm.run(&mut io) {
+        eprintln!("{}", e);
+    }
+
+    let profile = vm.profile().unwrap_or_else(|| fail("no profile was recorded".to_string()));
+    println!();
+    print!("{}", profile.report(&program));
+    println!("\npeak memory: {} words", vm.peak_memory());
+    if listing {
+        println!();
+        print!("{}", profile.annotate(&program));
+    }
+    Ok(())
+}
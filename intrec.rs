@@ -0,0 +1,76 @@
+use std::io::{self, BufRead, Write};
+use std::process;
+use common::intcode::{Callbacks, Vm, read_program};
+use common::transcript::{Error, Transcript};
+
+fn fail<T>(message: String) -> T {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+const USAGE: &str = "usage: intrec record <program> <transcript> [--numeric]
+       intrec replay <program> <transcript>";
+
+fn parse(line: &str, numeric: bool) -> Vec<isize> {
+    if numeric {
+        line.split(',').map(|v| v.trim().parse::<isize>()
+            .unwrap_or_else(|_| fail(format!("bad input line: {}", line)))).collect()
+    } else {
+        line.chars().chain(Some('\n')).map(|c| c as isize).collect()
+    }
+}
+
+fn record(program: &[isize], path: &str, numeric: bool) -> Result<(), Error> {
+    let mut vm = Vm::new(program);
+    vm.set_recording(true);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut values = vec![];
+    let mut io = Callbacks::new(|| {
+        if values.is_empty() {
+            io::stdout().flush().unwrap();
+            let line = lines.next()?.unwrap_or_else(|e| fail(format!("can't read input: {}", e)));
+            values = parse(&line, numeric);
+            values.reverse();
+        }
+        values.pop()
+    }, |v| match v {
+        10 | 32..=126 if !numeric => print!("{}", (v as u8) as char),
+        _ => println!("{}", v)
+    });
+    if let Err(e) = vm.run(&mut io) {
+        eprintln!("{}", e);
+    }
+    let transcript = vm.transcript().unwrap();
+    transcript.save(path)?;
+    eprintln!("recorded {} entries over {} steps", transcript.entries.len(), transcript.steps);
+    Ok(())
+}
+
+fn replay(program: &[isize], path: &str) -> Result<(), Error> {
+    let transcript = Transcript::load(path)?;
+    match transcript.replay(program) {
+        Ok(()) => println!("ok, {} entries over {} steps", transcript.entries.len(), transcript.steps),
+        Err(Error::Diverged(divergence)) => fail(format!("diverged at {}", divergence)),
+        Err(e) => return Err(e)
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.len() < 3 {
+        fail::<()>(USAGE.to_string());
+    }
+    let program = read_program(&args[1])
+        .unwrap_or_else(|e| fail(format!("can't read program: {:?}", e)));
+    let numeric = args.iter().any(|a| a == "--numeric");
+    let result = match args[0].as_str() {
+        "record" => record(&program, &args[2], numeric),
+        "replay" => replay(&program, &args[2]),
+        _ => fail(USAGE.to_string())
+    };
+    if let Err(e) = result {
+        fail::<()>(format!("{:?}", e));
+    }
+}
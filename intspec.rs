@@ -0,0 +1,21 @@
+use common::intcode::read_program;
+use common::partial::{Error, specialize};
+use std::process;
+
+const USAGE: &str = "usage: intspec <program> [input...]";
+
+fn main() -> Result<(), Error> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let path = args.first().unwrap_or_else(|| {
+        eprintln!("{}", USAGE);
+        process::exit(1)
+    });
+    let program = read_program(path)?;
+    let inputs = args[1..].iter()
+        .map(|arg| arg.parse::<isize>().map_err(|e| Error::Intcode(e.into())))
+        .collect::<Result<Vec<_>, _>>()?;
+    let specialized = specialize(&program, &inputs)?;
+    println!("{}", specialized.program.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(","));
+    eprintln!("{}", specialized.report);
+    Ok(())
+}
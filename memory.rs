@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+/// Number of words in each page of memory beyond the program.
+pub const PAGE_SIZE: usize = 1024;
+
+// pages this close to the program are kept in a table, the rest in a map
+const NEAR_PAGES: usize = 1024;
+
+/// Memory holding the program as a plain vector, and everything written
+/// beyond it in pages that are only allocated when first written. A write to
+/// a huge address costs one page, not everything below it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Memory {
+    dense: Vec<isize>,
+    near: Vec<Option<Box<[isize]>>>,
+    far: HashMap<usize, Box<[isize]>>,
+    pages: usize,
+    peak: usize
+}
+
+impl Memory {
+    pub fn new(words: &[isize]) -> Memory {
+        Memory {
+            dense: words.to_vec(),
+            near: vec![],
+            far: HashMap::new(),
+            pages: 0,
+            peak: words.len()
+        }
+    }
+
+    /// The words memory started out with.
+    pub fn dense(&self) -> &[isize] {
+        &self.dense
+    }
+
+    fn page(&self, addr: usize) -> (usize, usize) {
+        let offset = addr - self.dense.len();
+        (offset / PAGE_SIZE, offset % PAGE_SIZE)
+    }
+
+    fn page_ref(&self, page: usize) -> Option<&[isize]> {
+        if page < NEAR_PAGES {
+            self.near.get(page).and_then(|p| p.as_deref())
+        } else {
+            self.far.get(&page).map(|p| &p[..])
+        }
+    }
+
+    pub fn get(&self, addr: usize) -> isize {
+        if addr < self.dense.len() {
+            return self.dense[addr];
+        }
+        let (page, offset) = self.page(addr);
+        self.page_ref(page).map_or(0, |p| p[offset])
+    }
+
+    pub fn set(&mut self, addr: usize, value: isize) {
+        if addr < self.dense.len() {
+            self.dense[addr] = value;
+            return;
+        }
+        let (page, offset) = self.page(addr);
+        if self.page_ref(page).is_none() {
+            let words = vec![0; PAGE_SIZE].into_boxed_slice();
+            if page < NEAR_PAGES {
+                if page >= self.near.len() {
+                    self.near.resize(page + 1, None);
+                }
+                self.near[page] = Some(words);
+            } else {
+                self.far.insert(page, words);
+            }
+            self.pages += 1;
+            self.peak = self.peak.max(self.size());
+        }
+        let words = match page {
+            page if page < NEAR_PAGES => self.near[page].as_mut().unwrap(),
+            page => self.far.get_mut(&page).unwrap()
+        };
+        words[offset] = value;
+    }
+
+    /// Number of words allocated.
+    pub fn size(&self) -> usize {
+        self.dense.len() + self.pages * PAGE_SIZE
+    }
+
+    /// What `size` would be after writing `addr`.
+    pub fn size_after(&self, addr: usize) -> usize {
+        if addr < self.dense.len() || self.page_ref(self.page(addr).0).is_some() {
+            self.size()
+        } else {
+            self.size() + PAGE_SIZE
+        }
+    }
+
+    /// The largest `size` has been.
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+
+    /// Allocated pages by their first address, in order.
+    pub fn pages(&self) -> Vec<(usize, &[isize])> {
+        let near = self.near.iter().enumerate().filter_map(|(page, p)| Some((page, p.as_deref()?)));
+        let far = self.far.iter().map(|(&page, p)| (page, &p[..]));
+        let mut pages = near.chain(far)
+            .map(|(page, p)| (self.dense.len() + page * PAGE_SIZE, p))
+            .collect::<Vec<_>>();
+        pages.sort_by_key(|&(addr, _)| addr);
+        pages
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pages() {
+        let mut mem = Memory::new(&[1,2,3]);
+        mem.set(1, 5);
+        mem.set(3, 6);
+        mem.set(3 + PAGE_SIZE * NEAR_PAGES * 10, 7);
+        assert_eq!(mem.dense(), &[1,5,3]);
+        assert_eq!((mem.get(3), mem.get(4), mem.get(3 + PAGE_SIZE * NEAR_PAGES * 10)), (6, 0, 7));
+        assert_eq!(mem.size(), 3 + 2 * PAGE_SIZE);
+        assert_eq!(mem.size_after(PAGE_SIZE), mem.size());
+        assert_eq!(mem.size_after(PAGE_SIZE + 3), mem.size() + PAGE_SIZE);
+        assert_eq!(mem.pages().iter().map(|&(addr, _)| addr).collect::<Vec<_>>(), vec![3, 3 + PAGE_SIZE * NEAR_PAGES * 10]);
+    }
+}
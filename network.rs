@@ -0,0 +1,292 @@
+use std::ops::ControlFlow;
+use crate::intcode::Error;
+use crate::intcode2::{IntcodeComputer, Signal};
+
+/// The address day 23's NAT listens on.
+pub const NAT_ADDRESS: isize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Packet {
+    pub from: isize,
+    pub to: isize,
+    pub x: isize,
+    pub y: isize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// A packet reached a NIC or the NAT.
+    Delivered(Packet),
+    /// A packet was sent to an address nobody has.
+    Dropped(Packet),
+    /// Every NIC has been waiting for input for `Config::idle_rounds` rounds,
+    /// and the NAT is about to be asked what to do.
+    Idle { round: usize }
+}
+
+/// Decides what happens to packets sent to the NAT, and how an idle network
+/// is woken up.
+pub trait Nat {
+    fn receive(&mut self, packet: Packet);
+
+    /// The packet to send once the network goes idle, or `None` to give
+    /// every NIC a -1 instead.
+    fn idle(&mut self) -> Option<Packet>;
+}
+
+/// Day 23's NAT: remembers the last packet it got and resends it to address 0
+/// whenever the network is idle.
+#[derive(Debug, Clone, Default)]
+pub struct ResendLast {
+    pub last: Option<Packet>
+}
+
+impl Nat for ResendLast {
+    fn receive(&mut self, packet: Packet) {
+        self.last = Some(packet);
+    }
+
+    fn idle(&mut self) -> Option<Packet> {
+        self.last.map(|packet| Packet { from: NAT_ADDRESS, to: 0, ..packet })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub nat_address: isize,
+    /// Rounds without any packet being sent before the network counts as
+    /// idle. NICs get a -1 after every quiet round until then.
+    pub idle_rounds: usize
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config { nat_address: NAT_ADDRESS, idle_rounds: 1 }
+    }
+}
+
+/// NICs addressed 0 to n - 1, exchanging `(address, x, y)` packets. Each
+/// round, every NIC in turn runs until it has sent a packet or needs input,
+/// then the packets are routed in the same order, so runs are reproducible.
+pub struct Network<N = ResendLast> {
+    nics: Vec<IntcodeComputer>,
+    // output of a NIC's packet that hasn't been completed yet
+    partial: Vec<Vec<isize>>,
+    halted: Vec<bool>,
+    nat: N,
+    config: Config,
+    round: usize,
+    quiet: usize
+}
+
+impl Network<ResendLast> {
+    pub fn new(program: &[isize], size: usize) -> Network<ResendLast> {
+        Network::with_nat(program, size, ResendLast::default())
+    }
+}
+
+impl<N: Nat> Network<N> {
+    pub fn with_nat(program: &[isize], size: usize, nat: N) -> Network<N> {
+        let nics = (0..size).map(|address| {
+            let mut nic = IntcodeComputer::new(program);
+            nic.send(address as isize);
+            nic
+        }).collect();
+        Network {
+            nics,
+            partial: vec![vec![]; size],
+            halted: vec![false; size],
+            nat,
+            config: Config::default(),
+            round: 0,
+            quiet: 0
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    pub fn nat(&self) -> &N {
+        &self.nat
+    }
+
+    pub fn nics(&self) -> &[IntcodeComputer] {
+        &self.nics
+    }
+
+    pub fn rounds(&self) -> usize {
+        self.round
+    }
+
+    fn next_packet(&mut self, address: usize) -> Result<Option<Packet>, Error> {
+        if self.halted[address] {
+            return Ok(None);
+        }
+        loop {
+            match self.nics[address].run()? {
+                Signal::Output(value) => {
+                    let partial = &mut self.partial[address];
+                    partial.push(value);
+                    if let [to, x, y] = partial[..] {
+                        partial.clear();
+                        return Ok(Some(Packet { from: address as isize, to, x, y }));
+                    }
+                },
+                Signal::Exiting => {
+                    self.halted[address] = true;
+                    return Ok(None);
+                },
+                _ => return Ok(None)
+            }
+        }
+    }
+
+    fn route<F>(&mut self, packet: Packet, hook: &mut F) -> ControlFlow<()>
+        where F: FnMut(&Event) -> ControlFlow<()>
+    {
+        if packet.to == self.config.nat_address {
+            self.nat.receive(packet);
+        } else if let Some(nic) = self.nics.get_mut(packet.to as usize).filter(|_| packet.to >= 0) {
+            nic.send(packet.x);
+            nic.send(packet.y);
+        } else {
+            return hook(&Event::Dropped(packet));
+        }
+        hook(&Event::Delivered(packet))
+    }
+
+    /// Runs one round, passing every event to `hook`. The round is always
+    /// finished, so a `Break` from the hook loses no packets.
+    pub fn step<F>(&mut self, mut hook: F) -> Result<ControlFlow<()>, Error>
+        where F: FnMut(&Event) -> ControlFlow<()>
+    {
+        self.round += 1;
+        let packets = (0..self.nics.len())
+            .filter_map(|address| self.next_packet(address).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut flow = ControlFlow::Continue(());
+        if !packets.is_empty() {
+            self.quiet = 0;
+            for packet in packets {
+                if self.route(packet, &mut hook).is_break() {
+                    flow = ControlFlow::Break(());
+                }
+            }
+            return Ok(flow);
+        }
+
+        self.quiet += 1;
+        if self.quiet >= self.config.idle_rounds {
+            flow = hook(&Event::Idle { round: self.round });
+            if let Some(packet) = self.nat.idle() {
+                self.quiet = 0;
+                if self.route(packet, &mut hook).is_break() {
+                    flow = ControlFlow::Break(());
+                }
+                return Ok(flow);
+            }
+        }
+        for nic in &mut self.nics {
+            nic.send(-1);
+        }
+        Ok(flow)
+    }
+
+    /// Runs rounds until `hook` breaks or every NIC has halted.
+    pub fn run<F>(&mut self, mut hook: F) -> Result<(), Error>
+        where F: FnMut(&Event) -> ControlFlow<()>
+    {
+        while !self.halted.iter().all(|&halted| halted) {
+            if self.step(&mut hook)?.is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    // sends (address, address * 10) to the NAT, then sends every packet it
+    // gets back there with y incremented
+    fn echo() -> Vec<isize> {
+        assemble("
+                in -> [x]
+                mul [x], #10 -> [y]
+            send:
+                out #255
+                out [x]
+                out [y]
+            wait:
+                in -> [x]
+                eq [x], #-1 -> [t]
+                jnz [t], #wait
+                in -> [y]
+                add [y], #1 -> [y]
+                jz #0, #send
+            x: db 0
+            y: db 0
+            t: db 0
+        ").unwrap()
+    }
+
+    #[test]
+    fn test_nat() -> Result<(), Error> {
+        let mut network = Network::new(&echo(), 2);
+        let mut events = vec![];
+        network.run(|event| {
+            events.push(*event);
+            let resends = events.iter().filter(|e| matches!(e, Event::Delivered(p) if p.from == NAT_ADDRESS)).count();
+            if resends < 2 { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+        })?;
+        let packet = |from, to, x, y| Event::Delivered(Packet { from, to, x, y });
+        assert_eq!(events, vec![
+            packet(0, 255, 0, 0),
+            packet(1, 255, 1, 10),
+            Event::Idle { round: 2 },
+            packet(255, 0, 1, 10),
+            packet(0, 255, 1, 11),
+            Event::Idle { round: 4 },
+            packet(255, 0, 1, 11)
+        ]);
+        assert_eq!(network.nat().last, Some(Packet { from: 0, to: 255, x: 1, y: 11 }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_idle_rounds_and_drops() -> Result<(), Error> {
+        // reads its address and two -1s, then sends a packet nowhere and halts
+        let program = assemble("
+                in -> [x]
+                in -> [x]
+                in -> [x]
+                out #7
+                out #1
+                out #2
+                hlt
+            x: db 0
+        ").unwrap();
+        let mut network = Network::new(&program, 1);
+        network.set_config(Config { idle_rounds: 2, ..Config::default() });
+        let mut events = vec![];
+        network.run(|event| {
+            events.push(*event);
+            ControlFlow::Continue(())
+        })?;
+        assert_eq!(events, vec![
+            Event::Idle { round: 2 },
+            Event::Dropped(Packet { from: 0, to: 7, x: 1, y: 2 })
+        ]);
+        assert_eq!(network.rounds(), 4);
+        Ok(())
+    }
+}
@@ -0,0 +1,481 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use crate::disassembler::{self, Instruction, Operand};
+use crate::intcode::{self, Callbacks, Limits, Opcode, Step, Vm};
+
+// how long the fixed inputs may keep a program busy
+const MAX_STEPS: usize = 10_000_000;
+const MAX_WORDS: usize = 1 << 20;
+
+#[derive(Debug)]
+pub enum Error {
+    Intcode(intcode::Error),
+    /// No dead words to put the code that restores the state the fixed
+    /// inputs led to, and memory past the end might be read.
+    NoRoom,
+    /// Running on the fixed inputs wrote memory too far out to be saved as
+    /// a program.
+    TooLarge(usize)
+}
+
+impl From<intcode::Error> for Error {
+    fn from(err: intcode::Error) -> Error {
+        Error::Intcode(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Words in the original program.
+    pub before: usize,
+    /// Words in the specialized program, which only gets shorter when the
+    /// words cleared are at the end.
+    pub after: usize,
+    /// Instructions executed ahead of time on the fixed inputs.
+    pub steps: usize,
+    /// Operands replaced by the constant they always read.
+    pub folded: usize,
+    /// Words of unreachable code or unused data set to 0.
+    pub cleared: usize,
+    /// An instruction whose memory accesses or jumps can't be followed, such
+    /// as self-modifying code, which stopped anything more than running
+    /// ahead.
+    pub opaque: Option<usize>
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let change = 100.0 * (self.after as f64 / self.before.max(1) as f64 - 1.0);
+        write!(f, "{} words cleared, length {} -> {} ({:+.0}%), {} steps run ahead, {} operands folded",
+            self.cleared, self.before, self.after, change, self.steps, self.folded)?;
+        if let Some(pc) = self.opaque {
+            write!(f, ", not simplified past {:04}", pc)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Specialized {
+    pub program: Vec<isize>,
+    pub report: Report
+}
+
+fn instructions(mem: &[isize], entry: usize) -> Vec<(usize, Instruction)> {
+    disassembler::reachable_from(mem, &[entry]).into_iter()
+        .filter_map(|addr| Some((addr, Instruction::decode(mem, addr)?)))
+        .collect()
+}
+
+fn position(operand: &Operand) -> Option<usize> {
+    match *operand {
+        Operand::Position(addr) if addr >= 0 => Some(addr as usize),
+        _ => None
+    }
+}
+
+// the operands that are read, leaving out the one written to
+fn reads(instruction: &Instruction) -> &[Operand] {
+    let writes = instruction.opcode.writes() as usize;
+    &instruction.operands[..instruction.operands.len() - writes]
+}
+
+fn written(code: &[(usize, Instruction)]) -> BTreeSet<usize> {
+    code.iter()
+        .filter(|(_, i)| i.opcode.writes())
+        .filter_map(|(_, i)| position(i.operands.last()?))
+        .collect()
+}
+
+fn code_words(code: &[(usize, Instruction)]) -> BTreeSet<usize> {
+    code.iter().flat_map(|(addr, i)| *addr..addr + i.size()).collect()
+}
+
+// the words execution can get to from `entry`: those of the instructions
+// found, and where they go next even if the disassembler stopped there
+fn reached(code: &[(usize, Instruction)], entry: usize) -> BTreeSet<usize> {
+    let mut words = code_words(code);
+    words.insert(entry);
+    for (addr, instruction) in code {
+        if instruction.falls_through() {
+            words.insert(addr + instruction.size());
+        }
+        if instruction.can_jump() {
+            words.extend(instruction.jump_target());
+        }
+    }
+    words
+}
+
+// an instruction that can change memory nothing else accounts for: one that
+// writes where execution can get to, or uses a stack that overlaps the
+// program. Or where the disassembler stopped at something the machine would
+// still run, such as an instruction with extra mode digits.
+fn unfollowable(mem: &[isize], entry: usize, code: &[(usize, Instruction)], relbase: isize, len: usize) -> Option<usize> {
+    let words = reached(code, entry);
+    let stack_overlaps = relbase < len as isize;
+    let followed = code.iter().find(|(_, i)| {
+        let self_modifying = i.opcode.writes() && i.operands.last().and_then(position).is_some_and(|a| words.contains(&a));
+        let relative = i.operands.iter().any(|o| matches!(o, Operand::Relative(_)));
+        self_modifying || (relative && stack_overlaps)
+    }).map(|(addr, _)| *addr);
+    let stops = words.difference(&code_words(code))
+        .find(|&&addr| Opcode::decode(mem.get(addr).cloned().unwrap_or(0)).is_ok())
+        .cloned();
+    followed.or(stops)
+}
+
+fn read(code: &[(usize, Instruction)]) -> BTreeSet<usize> {
+    code.iter().flat_map(|(_, i)| reads(i).iter().filter_map(position)).collect()
+}
+
+// how many instructions each word is part of
+fn owners(code: &[(usize, Instruction)]) -> BTreeMap<usize, usize> {
+    let mut owners = BTreeMap::new();
+    for addr in code.iter().flat_map(|(addr, i)| *addr..addr + i.size()) {
+        *owners.entry(addr).or_insert(0) += 1;
+    }
+    owners
+}
+
+// replaces reads of words in the first `len` that nothing writes with the
+// word's value, until that stops making more code unreachable. Words beyond
+// could be written through the stack. Returns the address, operand and the
+// word read of each one folded.
+fn fold(mem: &mut [isize], entry: usize, len: usize) -> Vec<(usize, usize, usize)> {
+    let mut folded = vec![];
+    loop {
+        let code = instructions(mem, entry);
+        let (written, read, owners) = (written(&code), read(&code), owners(&code));
+        let original = mem.to_vec();
+        let mut changed = false;
+        for (addr, instruction) in &code {
+            // code that is read as data, or overlaps other code, has to stay
+            // as it is
+            if (*addr..addr + instruction.size()).any(|a| read.contains(&a) || owners[&a] > 1) {
+                continue;
+            }
+            for (pos, operand) in reads(instruction).iter().enumerate() {
+                if let Some(from) = position(operand).filter(|a| *a < len && !written.contains(a)) {
+                    mem[*addr] += 10_isize.pow(pos as u32 + 2);
+                    mem[addr + pos + 1] = original.get(from).cloned().unwrap_or(0);
+                    folded.push((*addr, pos, from));
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            return folded;
+        }
+    }
+}
+
+// whether what was folded still holds for the code it revealed: nothing
+// writes the words read, and the instructions changed are still there and
+// don't share their words with other code or data
+fn holds(folded: &[(usize, usize, usize)], code: &[(usize, Instruction)]) -> bool {
+    let (written, read, owners) = (written(code), read(code), owners(code));
+    folded.iter().all(|&(addr, pos, from)| {
+        code.iter().any(|(a, _)| *a == addr) && !written.contains(&from)
+            && [addr, addr + pos + 1].iter().all(|w| owners.get(w) == Some(&1) && !read.contains(w))
+    })
+}
+
+// no-ops filling exactly `len` words, which can't be done for 1
+fn padding(len: usize) -> Option<Vec<isize>> {
+    match len {
+        1 => None,
+        len if len % 2 == 0 => Some([109, 0].repeat(len / 2)),
+        len => Some([[109, 0].repeat((len - 3) / 2), vec![1105, 0, 0]].concat())
+    }
+}
+
+// writes code that recreates the registers and output of running ahead, and
+// then carries on at `pc`, into dead words, or past the end of memory if
+// there aren't enough and nothing reads there
+fn enter(mem: &mut Vec<isize>, dead: &dyn Fn(usize) -> bool, past_end: bool, pc: usize, relbase: isize, outputs: &[isize])
+    -> Result<(), Error>
+{
+    let mut prefix = vec![];
+    if relbase != 0 {
+        prefix.extend([109, relbase]);
+    }
+    for &value in outputs {
+        prefix.extend([104, value]);
+    }
+    if prefix.is_empty() && pc == 0 {
+        return Ok(());
+    }
+
+    // falling through into `pc` if everything before it is dead
+    if pc >= prefix.len() && (0..pc).all(dead) {
+        if let Some(padding) = padding(pc - prefix.len()) {
+            prefix.extend(padding);
+            mem[..pc].copy_from_slice(&prefix);
+            return Ok(());
+        }
+    }
+
+    // or jumping to the prefix somewhere else, which puts back whatever the
+    // jump replaced before going on to `pc`
+    if mem.len() < 3 && !past_end {
+        return Err(Error::NoRoom);
+    }
+    mem.resize(mem.len().max(3), 0);
+    for addr in (0..3).filter(|&addr| !dead(addr)) {
+        prefix.extend([1101, mem[addr], 0, addr as isize]);
+    }
+    prefix.extend([1106, 0, pc as isize]);
+    let start = (3..mem.len().saturating_sub(prefix.len() - 1))
+        .find(|&start| (start..start + prefix.len()).all(dead))
+        .or(Some(mem.len()).filter(|_| past_end))
+        .ok_or(Error::NoRoom)?;
+    if mem.len() < start + prefix.len() {
+        mem.resize(start + prefix.len(), 0);
+    }
+    mem[start..start + prefix.len()].copy_from_slice(&prefix);
+    mem[..3].copy_from_slice(&[1106, 0, start as isize]);
+    Ok(())
+}
+
+/// Specializes `program` for the first few inputs it reads. The program is
+/// run on `inputs` up to where it needs another, then reads of memory that
+/// nothing writes are folded into constants, and words that are no longer
+/// reachable code or used data are cleared. The result starts out by
+/// restoring the relative base and repeating any output from running ahead,
+/// in words nothing else uses, or fails with `NoRoom`.
+///
+/// Code is found the same way as the disassembler does, and the relative
+/// base is assumed to point at a stack beyond the program.
+pub fn specialize(program: &[isize], inputs: &[isize]) -> Result<Specialized, Error> {
+    let mut vm = Vm::new(program);
+    vm.set_limits(Limits { steps: Some(MAX_STEPS), ..Limits::default() });
+    let mut inputs = inputs.iter().cloned();
+    let mut outputs = vec![];
+    let step = vm.run(&mut Callbacks::new(|| inputs.next(), |value| outputs.push(value)))?;
+    let mut report = Report { before: program.len(), after: 0, steps: vm.steps(), folded: 0, cleared: 0, opaque: None };
+
+    if step == Step::Halted {
+        let program = outputs.iter().flat_map(|&value| [104, value]).chain([99]).collect::<Vec<_>>();
+        report.after = program.len();
+        return Ok(Specialized { program, report });
+    }
+
+    let snapshot = vm.snapshot();
+    let len = snapshot.pages.iter().map(|(addr, words)| addr + words.len()).fold(snapshot.mem.len(), usize::max);
+    if len > MAX_WORDS {
+        return Err(Error::TooLarge(len));
+    }
+    let mut mem = snapshot.mem.clone();
+    mem.resize(len, 0);
+    for (addr, words) in &snapshot.pages {
+        mem[*addr..addr + words.len()].copy_from_slice(words);
+    }
+
+    let (pc, relbase) = (vm.pc(), vm.relbase());
+    report.opaque = unfollowable(&mem, pc, &instructions(&mem, pc), relbase, program.len());
+    if report.opaque.is_none() {
+        let unfolded = mem.clone();
+        let folded = fold(&mut mem, pc, program.len());
+        let mut code = instructions(&mem, pc);
+        if !holds(&folded, &code) {
+            mem = unfolded.clone();
+            code = instructions(&mem, pc);
+        } else {
+            report.folded = folded.len();
+        }
+        // folding can reveal more code, and a jump to an address read from
+        // memory, even a return, could go anywhere
+        report.opaque = unfollowable(&mem, pc, &code, relbase, program.len()).or_else(|| code.iter()
+            .find(|(_, i)| i.can_jump() && i.jump_target().is_none())
+            .map(|(addr, _)| *addr));
+        if report.opaque.is_some() {
+            // what was folded may be written by the code that turned up
+            mem = unfolded;
+            report.folded = 0;
+        } else {
+            let mut live = reached(&code, pc);
+            live.extend(code.iter().flat_map(|(_, i)| i.operands.iter().filter_map(position)));
+            // anything past the program could be on the stack
+            let stack = code.iter().flat_map(|(_, i)| &i.operands).any(|o| matches!(o, Operand::Relative(_)));
+            let dead = |addr: usize| !live.contains(&addr) && (addr < program.len() || !stack);
+            for (addr, word) in mem.iter_mut().enumerate() {
+                if dead(addr) && *word != 0 {
+                    *word = 0;
+                    report.cleared += 1;
+                }
+            }
+            // nothing could read past the end either
+            let past_end = !stack && live.iter().all(|&addr| addr < mem.len());
+            enter(&mut mem, &dead, past_end, pc, relbase, &outputs)?;
+        }
+    }
+    if report.opaque.is_some() {
+        enter(&mut mem, &|_| false, false, pc, relbase, &outputs)?;
+    }
+
+    while mem.last() == Some(&0) {
+        mem.pop();
+    }
+    report.after = mem.len();
+    Ok(Specialized { program: mem, report })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn run(program: &[isize], inputs: &[isize]) -> Vec<isize> {
+        let mut inputs = inputs.iter().cloned();
+        let mut outputs = vec![];
+        Vm::new(program).run(&mut Callbacks::new(|| inputs.next(), |v| outputs.push(v))).unwrap();
+        outputs
+    }
+
+    // the outputs, and the error if it stopped with one
+    fn outcome(program: &[isize], inputs: &[isize]) -> (Vec<isize>, Option<String>) {
+        let mut inputs = inputs.iter().cloned();
+        let mut outputs = vec![];
+        let res = Vm::new(program).run(&mut Callbacks::new(|| inputs.next(), |v| outputs.push(v)));
+        (outputs, res.err().map(|e| format!("{:?}", e.cause())))
+    }
+
+    #[test]
+    fn test_fold() -> Result<(), Error> {
+        let program = assemble("
+                in -> [x]
+                jz [debug], #skip
+                out #999
+            skip:
+                out [x]
+                hlt
+            x: db 0
+            debug: db 0
+        ").unwrap();
+        let specialized = specialize(&program, &[])?;
+        // the check of `debug` became a plain jump over the dead `out`
+        assert_eq!(specialized.program, vec![3,10,1106,0,7,0,0,4,10,99]);
+        assert_eq!(specialized.report, Report { before: 12, after: 10, steps: 0, folded: 1, cleared: 2, opaque: None });
+        assert_eq!(run(&specialized.program, &[5]), vec![5]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_phases() -> Result<(), Error> {
+        // day 7 style: the first input picks a routine through a jump table,
+        // by patching the address the jump reads its target from
+        let program = assemble("
+                in -> [jump+2]
+                add [jump+2], #table -> [jump+2]
+            jump:
+                jnz #1, [0]
+            table:
+                db double
+                db shift
+            double:
+                in -> [x]
+                mul [x], #2 -> [x]
+                out [x]
+                hlt
+            shift:
+                arb #100
+                out #1
+                in -> [rb+0]
+                add [rb+0], #100 -> [rb+0]
+                out [rb+0]
+                hlt
+            x: db 0
+        ").unwrap();
+
+        let double = specialize(&program, &[0])?;
+        assert_eq!(run(&double.program, &[21]), run(&program, &[0, 21]));
+        assert!(double.report.cleared > 10, "{}", double.report);
+        assert!(double.program.len() < program.len());
+
+        // the relative base and the output made while running ahead are
+        // set up again
+        let shift = specialize(&program, &[1])?;
+        assert_eq!(run(&shift.program, &[21]), vec![1, 121]);
+        assert_eq!(run(&program, &[1, 21]), vec![1, 121]);
+
+        let halted = specialize(&program, &[1, 5])?;
+        assert_eq!(halted.program, vec![104,1,104,105,99]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_opaque() -> Result<(), Error> {
+        // day 5 patches the word after its first `add`, which doesn't decode
+        // until it has, so nothing can be folded or cleared
+        let program = intcode::read_program("day_05_input.txt")?;
+        let diagnostic = specialize(&program, &[])?;
+        assert_eq!(diagnostic.report.opaque, Some(2));
+        assert_eq!(diagnostic.program, program);
+        for input in [1, 5] {
+            assert_eq!(run(&diagnostic.program, &[input]), run(&program, &[input]));
+        }
+
+        // a jump folded into a constant reveals code that writes into it
+        let program = [3,8,105,5,11,204,7,106,38,10,20208,5,14,15,99];
+        let specialized = specialize(&program, &[])?;
+        assert!(specialized.report.opaque.is_some(), "{}", specialized.report);
+        assert_eq!(run(&specialized.program, &[5]), run(&program, &[5]));
+
+        // day 23's NIC patches its own code too, so all it could do is run
+        // ahead past reading its address, but then there's nowhere to put
+        // its entry
+        let program = intcode::read_program("day_23_input.txt")?;
+        assert!(matches!(specialize(&program, &[3]), Err(Error::NoRoom)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_past_end() -> Result<(), Error> {
+        // reads [15], past the end, so the entry can't go there
+        let program = [203,3,3,15,106,100,1,4,10,99];
+        assert!(matches!(specialize(&program, &[6]), Err(Error::NoRoom)));
+        assert_eq!(run(&program, &[6, 1]), vec![0]);
+
+        // but it can when nothing does, which is where the entry goes when
+        // the program loops back to the start
+        let program = assemble("
+            start:
+                in -> [x]
+                out [x]
+                jz [x], #start
+                out [y]
+                hlt
+            x: db 0
+            y: db 7
+        ").unwrap();
+        let specialized = specialize(&program, &[0])?;
+        assert!(specialized.program.len() > program.len());
+        assert_eq!(run(&specialized.program, &[0, 4]), run(&program, &[0, 0, 4]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknowns() -> Result<(), Error> {
+        // the stack past the program holds what was stored on it while
+        // running ahead
+        let program = [209,39,1108,50,38,0,209,10,203,9,203,27,4,27,1002,35,14,36,9,35,104,14,1206,9,86,99];
+        let specialized = specialize(&program, &[14])?;
+        assert_eq!(run(&specialized.program, &[20]), vec![0, 14]);
+        assert_eq!(run(&program, &[14, 20]), vec![0, 14]);
+
+        // folding `add`'s operand makes a jump land on it, which wasn't
+        // code when it was folded
+        let program = [3,20,1001,15,31,29,5,3,0,9,17,3,22,99,99,99,99];
+        let specialized = specialize(&program, &[])?;
+        assert_eq!(specialized.report.folded, 0);
+        assert_eq!(outcome(&specialized.program, &[0]), outcome(&program, &[0]));
+
+        // code the disassembler doesn't take, but the machine runs
+        let program = [3,3,10004,0,99,0,77];
+        let specialized = specialize(&program, &[])?;
+        assert_eq!(specialized.report.opaque, Some(2));
+        assert_eq!(outcome(&specialized.program, &[6]), (vec![77], None));
+        Ok(())
+    }
+}
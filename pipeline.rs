@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use crate::intcode::{self, Step};
+use crate::runtime::{self, Executor, Machine, Sender};
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    UnknownMachine(String),
+    DuplicateMachine(String),
+    DuplicatePort(String)
+}
+
+/// How a machine in the network ended up.
+#[derive(Debug)]
+pub enum Status {
+    Halted,
+    /// Waiting for input after everything feeding it had stopped.
+    Starved,
+    Failed(intcode::Error),
+    /// Still waiting for input when no machine could make progress.
+    Deadlocked
+}
+
+struct Node {
+    name: String,
+    program: Vec<isize>,
+    inputs: Vec<isize>
+}
+
+enum Target {
+    Machine(String),
+    Port(String)
+}
+
+/// A network of Intcode machines wired output to input. Any shape works:
+/// chains, rings, and machines feeding or fed by several others. Values from
+/// several producers are interleaved in the order they're sent, and every
+/// consumer of a machine gets all of its output.
+#[derive(Default)]
+pub struct Network {
+    nodes: Vec<Node>,
+    links: Vec<(String, Target)>
+}
+
+/// What a network produced once it stopped.
+#[derive(Debug)]
+pub struct Report {
+    /// Everything sent to each output port, in order.
+    pub outputs: BTreeMap<String, Vec<isize>>,
+    pub status: BTreeMap<String, Status>
+}
+
+impl Report {
+    /// Machines that didn't halt.
+    pub fn errors(&self) -> impl Iterator<Item = (&str, &Status)> {
+        self.status.iter()
+            .filter(|(_, status)| !matches!(status, Status::Halted))
+            .map(|(name, status)| (name.as_str(), status))
+    }
+
+    pub fn last(&self, port: &str) -> Option<isize> {
+        self.outputs.get(port)?.last().cloned()
+    }
+}
+
+impl Network {
+    pub fn new() -> Network {
+        Network::default()
+    }
+
+    /// Adds a machine running `program`, with `inputs` (a phase setting,
+    /// say) queued before anything it's connected to.
+    pub fn machine(&mut self, name: &str, program: &[isize], inputs: &[isize]) -> &mut Network {
+        self.nodes.push(Node { name: name.to_string(), program: program.to_vec(), inputs: inputs.to_vec() });
+        self
+    }
+
+    /// Feeds the output of `from` into the input of `to`.
+    pub fn connect(&mut self, from: &str, to: &str) -> &mut Network {
+        self.links.push((from.to_string(), Target::Machine(to.to_string())));
+        self
+    }
+
+    /// Collects the output of `from` in the port `port` of the report.
+    /// Several machines may share a port.
+    pub fn output(&mut self, from: &str, port: &str) -> &mut Network {
+        self.links.push((from.to_string(), Target::Port(port.to_string())));
+        self
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        let mut names = BTreeMap::new();
+        for node in &self.nodes {
+            if names.insert(node.name.as_str(), ()).is_some() {
+                return Err(Error::DuplicateMachine(node.name.clone()));
+            }
+        }
+        for (from, to) in &self.links {
+            let to = match to {
+                Target::Machine(to) => Some(to),
+                Target::Port(port) if names.contains_key(port.as_str()) => return Err(Error::DuplicatePort(port.clone())),
+                Target::Port(_) => None
+            };
+            for name in std::iter::once(from).chain(to) {
+                if !names.contains_key(name.as_str()) {
+                    return Err(Error::UnknownMachine(name.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs fresh machines for the network on the current thread until all
+    /// of them have stopped or the rest are deadlocked.
+    pub fn run(&self) -> Result<Report, Error> {
+        self.check()?;
+
+        let mut inputs = BTreeMap::new();
+        let mut receivers = vec![];
+        for node in &self.nodes {
+            let (sender, receiver) = runtime::channel();
+            for &value in &node.inputs {
+                sender.send(value);
+            }
+            inputs.insert(node.name.as_str(), sender);
+            receivers.push(receiver);
+        }
+        let mut ports = BTreeMap::new();
+        for (_, to) in &self.links {
+            if let Target::Port(port) = to {
+                ports.entry(port.as_str()).or_insert_with(runtime::channel);
+            }
+        }
+
+        let mut executor = Executor::new();
+        let mut handles = vec![];
+        for (node, input) in self.nodes.iter().zip(receivers) {
+            let targets = self.links.iter()
+                .filter(|(from, _)| *from == node.name)
+                .map(|(_, to)| match to {
+                    Target::Machine(name) => inputs[name.as_str()].clone(),
+                    Target::Port(port) => ports[port.as_str()].0.clone()
+                })
+                .collect::<Vec<Sender>>();
+            // a relay, since a machine only has the one output
+            let (output, relayed) = runtime::channel();
+            executor.spawn(async move {
+                while let Some(value) = relayed.recv().await {
+                    targets.iter().for_each(|target| target.send(value));
+                }
+            });
+            let mut machine = Machine::new(&node.program, input, output);
+            handles.push((node.name.clone(), executor.spawn(async move { machine.run().await })));
+        }
+        // otherwise no input would ever be closed
+        drop(inputs);
+        let ports = ports.into_iter()
+            .map(|(port, (_, receiver))| (port.to_string(), receiver))
+            .collect::<Vec<_>>();
+        executor.run();
+
+        let status = handles.into_iter().map(|(name, handle)| {
+            let status = match handle.take() {
+                Some(Ok(Step::Halted)) => Status::Halted,
+                Some(Ok(_)) => Status::Starved,
+                Some(Err(err)) => Status::Failed(err),
+                None => Status::Deadlocked
+            };
+            (name, status)
+        }).collect();
+        let outputs = ports.into_iter()
+            .map(|(port, receiver)| (port, std::iter::from_fn(|| receiver.try_recv()).collect()))
+            .collect();
+        Ok(Report { outputs, status })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // reads a number and outputs it plus one
+    const INCREMENT: [isize; 9] = [3,0,1001,0,1,0,4,0,99];
+    // the same, twice
+    const INCREMENT_TWICE: [isize; 19] = [3,17,1001,17,1,17,4,17,3,18,1001,18,1,18,4,18,99,0,0];
+    // reads two numbers and outputs their sum
+    const SUM: [isize; 13] = [3,11,3,12,1,11,12,11,4,11,99,0,0];
+
+    #[test]
+    fn test_fan_out_and_in() -> Result<(), Error> {
+        let report = Network::new()
+            .machine("a", &INCREMENT[..], &[1])
+            .machine("b", &INCREMENT[..], &[])
+            .machine("c", &INCREMENT[..], &[])
+            .machine("sum", &SUM[..], &[])
+            .connect("a", "b")
+            .connect("a", "c")
+            .connect("b", "sum")
+            .connect("c", "sum")
+            .output("sum", "total")
+            .output("a", "log")
+            .run()?;
+        assert_eq!(report.outputs["log"], vec![2]);
+        assert_eq!(report.outputs["total"], vec![6]);
+        assert_eq!(report.errors().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ring() -> Result<(), Error> {
+        let mut network = Network::new();
+        network.machine("a", &INCREMENT_TWICE[..], &[0]).machine("b", &INCREMENT_TWICE[..], &[]);
+        network.connect("a", "b").connect("b", "a").output("b", "out");
+        let report = network.run()?;
+        assert_eq!(report.outputs["out"], vec![2, 4]);
+        assert_eq!(report.last("out"), Some(4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors() {
+        let report = Network::new()
+            .machine("bad", &[77], &[])
+            .machine("starved", &SUM[..], &[1])
+            .machine("x", &SUM[..], &[])
+            .machine("y", &SUM[..], &[])
+            .connect("x", "y")
+            .connect("y", "x")
+            .run()
+            .unwrap();
+        let status = report.errors().map(|(name, status)| match status {
+            Status::Failed(err) => format!("{} Failed({:?})", name, err.cause()),
+            status => format!("{} {:?}", name, status)
+        }).collect::<Vec<_>>();
+        assert_eq!(status, vec!["bad Failed(InvalidOpcode(77))", "starved Starved", "x Deadlocked", "y Deadlocked"]);
+
+        assert_eq!(Network::new().machine("a", &[99], &[]).connect("a", "b").run().err(),
+            Some(Error::UnknownMachine("b".to_string())));
+        assert_eq!(Network::new().machine("a", &[99], &[]).machine("a", &[99], &[]).run().err(),
+            Some(Error::DuplicateMachine("a".to_string())));
+        assert_eq!(Network::new().machine("a", &[99], &[]).output("a", "a").run().err(),
+            Some(Error::DuplicatePort("a".to_string())));
+    }
+}
@@ -0,0 +1,150 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use crate::disassembler::{self, Line};
+use crate::intcode::Opcode;
+
+const HOT_INSTRUCTIONS: usize = 20;
+
+/// Execution counts gathered while a `Vm` runs with profiling turned on.
+/// Counts keep adding up across resets, so a profile can cover many runs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    /// How often the instruction at each address was executed.
+    pub counts: Vec<usize>,
+    /// How often each backward jump was taken, by `(from, to)`. Each of
+    /// these closes a loop over `to..=from`.
+    pub loops: BTreeMap<(usize, usize), usize>,
+    opcodes: [usize; 10]
+}
+
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 { 0.0 } else { 100.0 * part as f64 / total as f64 }
+}
+
+impl Profile {
+    pub fn record(&mut self, pc: usize, opcode: Opcode, next: usize) {
+        if pc >= self.counts.len() {
+            self.counts.resize(pc + 1, 0);
+        }
+        self.counts[pc] += 1;
+        self.opcodes[Opcode::ALL.iter().position(|&o| o == opcode).unwrap()] += 1;
+        if matches!(opcode, Opcode::Jnz | Opcode::Jz) && next <= pc {
+            *self.loops.entry((pc, next)).or_insert(0) += 1;
+        }
+    }
+
+    pub fn count(&self, addr: usize) -> usize {
+        self.counts.get(addr).cloned().unwrap_or(0)
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    pub fn opcodes(&self) -> impl Iterator<Item = (Opcode, usize)> + '_ {
+        Opcode::ALL.iter().cloned().zip(self.opcodes.iter().cloned())
+    }
+
+    /// Instructions reachable in `program` that have been executed, out of
+    /// all of them.
+    pub fn coverage(&self, program: &[isize]) -> (usize, usize) {
+        let code = disassembler::reachable(program);
+        let executed = code.iter().filter(|&&addr| self.count(addr) > 0).count();
+        (executed, code.len())
+    }
+
+    /// Tables of the hottest instructions, the opcode mix and the hottest
+    /// loops, sorted by count.
+    pub fn report(&self, program: &[isize]) -> String {
+        let total = self.total();
+        let (executed, reachable) = self.coverage(program);
+        let mut out = String::new();
+        writeln!(out, "steps: {}", total).unwrap();
+        writeln!(out, "coverage: {} of {} instructions ({:.1}%)",
+            executed, reachable, percent(executed, reachable)).unwrap();
+
+        let lines = disassembler::disassemble(program).into_iter()
+            .filter_map(|line| match line {
+                Line::Code(addr, i) => Some((addr, i.to_string())),
+                Line::Data(..) => None
+            })
+            .collect::<BTreeMap<_, _>>();
+        // anything not in the static listing was jumped to dynamically
+        let text = |addr: usize| lines.get(&addr).cloned().unwrap_or_else(|| "?".to_string());
+
+        let mut hot = self.counts.iter().cloned().enumerate()
+            .filter(|&(_, count)| count > 0)
+            .collect::<Vec<_>>();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        writeln!(out, "\nhot instructions\n{:>10} {:>6}  instruction", "count", "%").unwrap();
+        for (addr, count) in hot.into_iter().take(HOT_INSTRUCTIONS) {
+            writeln!(out, "{:>10} {:>5.1}%  {:04}: {}", count, percent(count, total), addr, text(addr)).unwrap();
+        }
+
+        let mut opcodes = self.opcodes().filter(|&(_, count)| count > 0).collect::<Vec<_>>();
+        opcodes.sort_by_key(|&(_, count)| Reverse(count));
+        writeln!(out, "\nopcodes\n{:>10} {:>6}  opcode", "count", "%").unwrap();
+        for (opcode, count) in opcodes {
+            writeln!(out, "{:>10} {:>5.1}%  {}", count, percent(count, total), opcode.mnemonic()).unwrap();
+        }
+
+        let mut loops = self.loops.iter().collect::<Vec<_>>();
+        loops.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(out, "\nhot loops\n{:>10}  range", "count").unwrap();
+        for ((from, to), count) in loops {
+            writeln!(out, "{:>10}  {:04}..={:04}", count, to, from).unwrap();
+        }
+        out
+    }
+
+    /// The program listing with each instruction's count in front of it,
+    /// and `-` for instructions that never ran. Data that was jumped to and
+    /// executed gets its count as well.
+    pub fn annotate(&self, program: &[isize]) -> String {
+        disassembler::disassemble(program).into_iter().map(|line| {
+            let count = match (&line, self.count(line.addr())) {
+                (Line::Code(..), 0) => "-".to_string(),
+                (Line::Data(..), 0) => String::new(),
+                (_, count) => count.to_string()
+            };
+            format!("{:>10}  {}\n", count, line)
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::intcode::{Callbacks, Vm};
+
+    #[test]
+    fn test_countdown() {
+        let program = assemble("
+            loop:
+                add [n], #-1 -> [n]
+                jnz [n], #loop
+                hlt
+                out [n]
+            n: db 3
+        ").unwrap();
+        let mut vm = Vm::new(&program);
+        vm.set_profiling(true);
+        vm.run(&mut Callbacks::new(|| None, |_| ())).unwrap();
+
+        let profile = vm.profile().unwrap();
+        assert_eq!(profile.total(), 7);
+        assert_eq!(profile.loops, vec![((4, 0), 2)].into_iter().collect());
+        assert_eq!(profile.opcodes().find(|&(o, _)| o == Opcode::Jnz), Some((Opcode::Jnz, 3)));
+        assert_eq!(profile.coverage(&program), (3, 3));
+        // the output after the halt is never reached, so it shows up as data
+        assert_eq!(profile.annotate(&program), "         3  0000: ADD [10], #-1 -> [10]
+         3  0004: JNZ [10], #0
+         1  0007: HLT
+            0008: DB 4
+            0009: DB 10
+            0010: DB 3
+");
+    }
+}
@@ -0,0 +1,303 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use crate::intcode::{Error, Io, Step, Vm};
+
+struct State {
+    queue: VecDeque<isize>,
+    senders: usize,
+    waker: Option<Waker>
+}
+
+/// Creates an unbounded channel whose receiving end can be awaited. It
+/// only relies on `Waker`, so it works with any executor.
+pub fn channel() -> (Sender, Receiver) {
+    let state = Arc::new(Mutex::new(State { queue: VecDeque::new(), senders: 1, waker: None }));
+    (Sender { state: state.clone(), closed: false }, Receiver { state })
+}
+
+pub struct Sender {
+    state: Arc<Mutex<State>>,
+    closed: bool
+}
+
+impl Sender {
+    pub fn send(&self, value: isize) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.push_back(value);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Closes this end. Once every sender is closed or dropped, receiving
+    /// from an empty channel gives `None`.
+    pub fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        let mut state = self.state.lock().unwrap();
+        state.senders -= 1;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Sender {
+        if !self.closed {
+            self.state.lock().unwrap().senders += 1;
+        }
+        Sender { state: self.state.clone(), closed: self.closed }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+pub struct Receiver {
+    state: Arc<Mutex<State>>
+}
+
+impl Receiver {
+    pub fn try_recv(&self) -> Option<isize> {
+        self.state.lock().unwrap().queue.pop_front()
+    }
+
+    /// Waits for the next value, or `None` once the channel is closed and
+    /// empty.
+    pub fn recv(&self) -> Recv<'_> {
+        Recv(self)
+    }
+}
+
+pub struct Recv<'a>(&'a Receiver);
+
+impl Future for Recv<'_> {
+    type Output = Option<isize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<isize>> {
+        let mut state = self.0.state.lock().unwrap();
+        if let Some(value) = state.queue.pop_front() {
+            return Poll::Ready(Some(value));
+        }
+        if state.senders == 0 {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+// feeds the core from the channels, with a value that had to be awaited first
+struct ChannelIo<'a> {
+    awaited: Option<isize>,
+    input: &'a Receiver,
+    output: &'a mut Sender
+}
+
+impl Io for ChannelIo<'_> {
+    fn input(&mut self) -> Result<Option<isize>, Error> {
+        Ok(self.awaited.take().or_else(|| self.input.try_recv()))
+    }
+
+    fn output(&mut self, value: isize) -> Result<(), Error> {
+        self.output.send(value);
+        Ok(())
+    }
+
+    fn halt(&mut self) -> Result<(), Error> {
+        self.output.close();
+        Ok(())
+    }
+}
+
+/// Front-end whose input is awaited rather than blocked on, so that many
+/// machines can share a single thread.
+pub struct Machine {
+    vm: Vm,
+    input: Receiver,
+    output: Sender
+}
+
+impl Machine {
+    pub fn new(program: &[isize], input: Receiver, output: Sender) -> Machine {
+        Machine { vm: Vm::new(program), input, output }
+    }
+
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
+
+    pub fn input(&self) -> &Receiver {
+        &self.input
+    }
+
+    /// Runs until the machine halts, returning `Step::Halted`, or until
+    /// it needs input from a channel that has been closed, returning
+    /// `Step::ExpectingInput`.
+    pub async fn run(&mut self) -> Result<Step, Error> {
+        let mut awaited = None;
+        loop {
+            let mut io = ChannelIo { awaited: awaited.take(), input: &self.input, output: &mut self.output };
+            match self.vm.run(&mut io)? {
+                Step::ExpectingInput => match self.input.recv().await {
+                    Some(value) => awaited = Some(value),
+                    None => return Ok(Step::ExpectingInput)
+                },
+                step => return Ok(step)
+            }
+        }
+    }
+}
+
+struct TaskWaker {
+    id: usize,
+    ready: Arc<Mutex<VecDeque<usize>>>
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.ready.lock().unwrap().push_back(self.id);
+    }
+}
+
+/// The result of a spawned task, available once the executor has run it
+/// to completion.
+pub struct Handle<T>(Rc<RefCell<Option<T>>>);
+
+impl<T> Handle<T> {
+    pub fn take(&self) -> Option<T> {
+        self.0.borrow_mut().take()
+    }
+}
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+/// A minimal single-threaded executor, so the async front-end can be used
+/// without pulling in a runtime.
+#[derive(Default)]
+pub struct Executor {
+    tasks: Vec<Option<Task>>,
+    ready: Arc<Mutex<VecDeque<usize>>>
+}
+
+impl Executor {
+    pub fn new() -> Executor {
+        Executor::default()
+    }
+
+    pub fn spawn<F: Future + 'static>(&mut self, future: F) -> Handle<F::Output> {
+        let result = Rc::new(RefCell::new(None));
+        let slot = result.clone();
+        self.ready.lock().unwrap().push_back(self.tasks.len());
+        self.tasks.push(Some(Box::pin(async move {
+            *slot.borrow_mut() = Some(future.await);
+        })));
+        Handle(result)
+    }
+
+    /// Polls tasks until none of them can make progress. Tasks still
+    /// waiting at that point are deadlocked, and are left in place.
+    pub fn run(&mut self) {
+        loop {
+            let id = match self.ready.lock().unwrap().pop_front() {
+                Some(id) => id,
+                None => return
+            };
+            let task = match &mut self.tasks[id] {
+                Some(task) => task,
+                // woken after it finished
+                None => continue
+            };
+            let waker = Waker::from(Arc::new(TaskWaker { id, ready: self.ready.clone() }));
+            if task.as_mut().poll(&mut Context::from_waker(&waker)).is_ready() {
+                self.tasks[id] = None;
+            }
+        }
+    }
+}
+
+/// Runs `future` to completion on a fresh executor, or returns `None` if it
+/// deadlocks.
+pub fn block_on<F: Future + 'static>(future: F) -> Option<F::Output> {
+    let mut executor = Executor::new();
+    let handle = executor.spawn(future);
+    executor.run();
+    handle.take()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use itertools::Itertools;
+    use crate::intcode::read_program;
+
+    #[test]
+    fn test_chain_on_one_thread() {
+        // reads a number and outputs it plus one
+        let increment = [3,0,1001,0,1,0,4,0,99];
+        let mut executor = Executor::new();
+        let (first, mut input) = channel();
+        let mut handles = vec![];
+        for _ in 0..300 {
+            let (sender, receiver) = channel();
+            let mut machine = Machine::new(&increment, input, sender);
+            handles.push(executor.spawn(async move { machine.run().await }));
+            input = receiver;
+        }
+        first.send(0);
+        executor.run();
+        assert!(handles.iter().all(|h| matches!(h.take(), Some(Ok(Step::Halted)))));
+        assert_eq!(input.try_recv(), Some(300));
+    }
+
+    // day 7's amplifiers, with the output of the last fed back into the first
+    fn amplify(program: &[isize], phases: &[isize]) -> isize {
+        let (senders, receivers): (Vec<_>, Vec<_>) = phases.iter().map(|&phase| {
+            let (sender, receiver) = channel();
+            sender.send(phase);
+            (sender, receiver)
+        }).unzip();
+        senders[0].send(0);
+
+        let mut executor = Executor::new();
+        let mut handles = receivers.into_iter().enumerate().map(|(i, input)| {
+            let mut machine = Machine::new(program, input, senders[(i + 1) % senders.len()].clone());
+            executor.spawn(async move {
+                machine.run().await.unwrap();
+                machine
+            })
+        }).collect::<Vec<_>>();
+        drop(senders);
+        executor.run();
+
+        // the last amplifier's final output is left in the first one's input
+        let first = handles.remove(0).take().unwrap();
+        std::iter::from_fn(|| first.input().try_recv()).last().unwrap()
+    }
+
+    #[test]
+    fn test_day_07_feedback_loop() -> Result<(), Error> {
+        let program = read_program("day_07_input.txt")?;
+        let best = |phases: std::ops::Range<isize>| phases.permutations(5)
+            .map(|phases| amplify(&program, &phases))
+            .max();
+        assert_eq!(best(0..5), Some(38500));
+        assert_eq!(best(5..10), Some(33660560));
+        Ok(())
+    }
+}
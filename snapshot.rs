@@ -0,0 +1,238 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use crate::intcode::{Error, Signal};
+
+const MAGIC: &str = "intcode-snapshot";
+
+/// Everything needed to rebuild a machine, including I/O that was queued
+/// but not yet consumed.
+///
+/// The file format is line based text, starting with a version header:
+///
+/// ```text
+/// intcode-snapshot 2
+/// pc 12
+/// relbase 0
+/// program 1,2,3
+/// mem 1,2,3
+/// page 3 0,0,7
+/// input 4,exit
+/// output
+/// ```
+///
+/// There is a `page` line for each page of memory written beyond `mem`,
+/// without its trailing zeros. Version 1 files have no pages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub pc: usize,
+    pub relbase: isize,
+    pub mem: Vec<isize>,
+    /// Memory written beyond `mem`, as runs of words by first address.
+    pub pages: Vec<(usize, Vec<isize>)>,
+    pub program: Vec<isize>,
+    pub input: Vec<Signal>,
+    pub output: Vec<Signal>
+}
+
+fn format_words(words: &[isize]) -> String {
+    words.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn format_signals(signals: &[Signal]) -> String {
+    signals.iter().map(|s| match s {
+        Signal::Value(v) => v.to_string(),
+        Signal::Exiting => "exit".to_string()
+    }).collect::<Vec<_>>().join(",")
+}
+
+fn split(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|v| !v.is_empty())
+}
+
+fn parse_words(value: &str) -> Result<Vec<isize>, Error> {
+    split(value).map(|v| Ok(v.parse()?)).collect()
+}
+
+fn parse_signals(value: &str) -> Result<Vec<Signal>, Error> {
+    split(value).map(|v| match v {
+        "exit" => Ok(Signal::Exiting),
+        v => Ok(Signal::Value(v.parse()?))
+    }).collect()
+}
+
+impl Snapshot {
+    pub const VERSION: u32 = 2;
+
+    pub fn write<W: Write>(&self, mut w: W) -> Result<(), Error> {
+        writeln!(w, "{} {}", MAGIC, Snapshot::VERSION)?;
+        for (key, value) in &[
+            ("pc", self.pc.to_string()),
+            ("relbase", self.relbase.to_string()),
+            ("program", format_words(&self.program)),
+            ("mem", format_words(&self.mem))
+        ] {
+            writeln!(w, "{}", format!("{} {}", key, value).trim_end())?;
+        }
+        for (addr, words) in &self.pages {
+            let len = words.iter().rposition(|&v| v != 0).map_or(0, |last| last + 1);
+            if len > 0 {
+                writeln!(w, "page {} {}", addr, format_words(&words[..len]))?;
+            }
+        }
+        for (key, value) in &[
+            ("input", format_signals(&self.input)),
+            ("output", format_signals(&self.output))
+        ] {
+            writeln!(w, "{}", format!("{} {}", key, value).trim_end())?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: R) -> Result<Snapshot, Error> {
+        let mut lines = BufReader::new(r).lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [MAGIC, version] if matches!(version.parse(), Ok(1..=Snapshot::VERSION)) => (),
+            [MAGIC, version] => return Err(Error::InvalidSnapshot(format!("unsupported version {}", version))),
+            _ => return Err(Error::InvalidSnapshot("not a snapshot".to_string()))
+        }
+
+        let (mut pc, mut relbase, mut program, mut mem, mut input, mut output) = (None, None, None, None, None, None);
+        let mut pages = vec![];
+        for line in lines {
+            let line = line?;
+            let mut parts = line.splitn(2, ' ');
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("");
+            match key {
+                "pc" => pc = Some(value.trim().parse()?),
+                "relbase" => relbase = Some(value.trim().parse()?),
+                "program" => program = Some(parse_words(value)?),
+                "mem" => mem = Some(parse_words(value)?),
+                "page" => {
+                    let mut parts = value.splitn(2, ' ');
+                    let addr = parts.next().unwrap_or("").parse()?;
+                    pages.push((addr, parse_words(parts.next().unwrap_or(""))?));
+                },
+                "input" => input = Some(parse_signals(value)?),
+                "output" => output = Some(parse_signals(value)?),
+                "" => (),
+                key => return Err(Error::InvalidSnapshot(format!("unknown field {}", key)))
+            }
+        }
+        let missing = |field: &str| Error::InvalidSnapshot(format!("missing {}", field));
+        Ok(Snapshot {
+            pc: pc.ok_or_else(|| missing("pc"))?,
+            relbase: relbase.ok_or_else(|| missing("relbase"))?,
+            program: program.ok_or_else(|| missing("program"))?,
+            mem: mem.ok_or_else(|| missing("mem"))?,
+            pages,
+            input: input.ok_or_else(|| missing("input"))?,
+            output: output.ok_or_else(|| missing("output"))?
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        self.write(fs::File::create(path)?)
+    }
+
+    pub fn load(path: &str) -> Result<Snapshot, Error> {
+        Snapshot::read(fs::File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{intcode, intcode2};
+    use crate::assembler::assemble;
+
+    // adds pairs of inputs until it reads a 0
+    fn adder() -> Vec<isize> {
+        assemble("
+            loop:
+                in -> [a]
+                jz [a], #end
+                in -> [b]
+                add [a], [b] -> [b]
+                out [b]
+                jz #0, #loop
+            end:
+                hlt
+            a: db 0
+            b: db 0
+        ").unwrap()
+    }
+
+    #[test]
+    fn test_file_format() -> Result<(), Error> {
+        let snapshot = Snapshot {
+            pc: 2,
+            relbase: -4,
+            mem: vec![3,0,99,5],
+            pages: vec![(1004, vec![0,8])],
+            program: vec![3,0,99],
+            input: vec![Signal::Value(7), Signal::Exiting],
+            output: vec![]
+        };
+        let mut buffer = vec![];
+        snapshot.write(&mut buffer)?;
+        assert_eq!(String::from_utf8(buffer.clone()).unwrap(), "\
+intcode-snapshot 2
+pc 2
+relbase -4
+program 3,0,99
+mem 3,0,99,5
+page 1004 0,8
+input 7,exit
+output
+");
+        assert_eq!(Snapshot::read(&buffer[..])?, snapshot);
+
+        match Snapshot::read("intcode-snapshot 3\n".as_bytes()) {
+            Err(Error::InvalidSnapshot(msg)) => assert_eq!(msg, "unsupported version 3"),
+            _ => panic!("expected a version error")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_polling_restore() -> Result<(), Error> {
+        let mut comp = intcode2::IntcodeComputer::new(&adder());
+        [1, 2, 3, 4].iter().for_each(|&v| comp.send(v));
+        assert_eq!(comp.run()?.output()?, 3);
+
+        let mut buffer = vec![];
+        comp.snapshot().write(&mut buffer)?;
+        let mut restored = intcode2::IntcodeComputer::restore(&Snapshot::read(&buffer[..])?);
+
+        for comp in [&mut comp, &mut restored].iter_mut() {
+            assert_eq!(comp.run()?.output()?, 7);
+            comp.send(0);
+            assert!(matches!(comp.run()?, intcode2::Signal::Exiting));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_channel_restore_keeps_queues() -> Result<(), Error> {
+        let mut comp = intcode::IntcodeComputer::new(&adder());
+        [1, 2, 3, 4].iter().for_each(|&v| comp.send(Signal::Value(v)).unwrap());
+        // run only until the first output
+        while comp.vm().pc() != 13 {
+            comp.next().unwrap()?;
+        }
+
+        let snapshot = comp.snapshot()?;
+        assert_eq!(snapshot.input, vec![Signal::Value(3), Signal::Value(4)]);
+        assert_eq!(snapshot.output, vec![Signal::Value(3)]);
+
+        let mut restored = intcode::IntcodeComputer::restore(&snapshot)?;
+        for comp in [&mut comp, &mut restored].iter_mut() {
+            comp.send(Signal::Value(0))?;
+            comp.run()?;
+            assert_eq!(comp.output_iter().collect::<Vec<_>>(), vec![3, 7]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,736 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+use crate::intcode::{Mode, Opcode};
+
+/// `constant + sum(coefficient * variable)`, the only kind of value the
+/// symbolic machine can track. Terms are kept sorted by variable, without
+/// zero coefficients.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Linear {
+    pub constant: isize,
+    pub terms: Vec<(usize, isize)>
+}
+
+impl From<isize> for Linear {
+    fn from(constant: isize) -> Linear {
+        Linear { constant, terms: vec![] }
+    }
+}
+
+impl Linear {
+    pub fn var(var: usize) -> Linear {
+        Linear { constant: 0, terms: vec![(var, 1)] }
+    }
+
+    pub fn as_constant(&self) -> Option<isize> {
+        self.terms.is_empty().then_some(self.constant)
+    }
+
+    /// `None` if a coefficient or the constant overflows.
+    pub fn add(&self, other: &Linear) -> Option<Linear> {
+        let mut terms = self.terms.iter().cloned().collect::<BTreeMap<_, _>>();
+        for &(var, coefficient) in &other.terms {
+            let sum = terms.get(&var).unwrap_or(&0).checked_add(coefficient)?;
+            terms.insert(var, sum);
+        }
+        Some(Linear {
+            constant: self.constant.checked_add(other.constant)?,
+            terms: terms.into_iter().filter(|&(_, c)| c != 0).collect()
+        })
+    }
+
+    pub fn scale(&self, factor: isize) -> Option<Linear> {
+        Some(Linear {
+            constant: self.constant.checked_mul(factor)?,
+            terms: self.terms.iter()
+                .map(|&(var, c)| Some((var, c.checked_mul(factor)?)))
+                .collect::<Option<Vec<_>>>()?
+                .into_iter().filter(|&(_, c)| c != 0).collect()
+        })
+    }
+
+    pub fn sub(&self, other: &Linear) -> Option<Linear> {
+        self.add(&other.scale(-1)?)
+    }
+
+    /// The value with every variable replaced by `values[var]`.
+    pub fn eval(&self, values: &[isize]) -> i128 {
+        self.terms.iter().fold(self.constant as i128, |sum, &(var, c)| sum + c as i128 * values[var] as i128)
+    }
+}
+
+impl fmt::Display for Linear {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        for (i, &(var, c)) in self.terms.iter().enumerate() {
+            match (i, c) {
+                (0, 1) => write!(f, "x{}", var)?,
+                (0, -1) => write!(f, "-x{}", var)?,
+                (0, c) => write!(f, "{}*x{}", c, var)?,
+                (_, 1) => write!(f, " + x{}", var)?,
+                (_, -1) => write!(f, " - x{}", var)?,
+                (_, c) if c < 0 => write!(f, " - {}*x{}", c.unsigned_abs(), var)?,
+                (_, c) => write!(f, " + {}*x{}", c, var)?
+            }
+        }
+        match (self.terms.is_empty(), self.constant) {
+            (true, c) => write!(f, "{}", c),
+            (false, 0) => Ok(()),
+            (false, c) if c < 0 => write!(f, " - {}", c.unsigned_abs()),
+            (false, c) => write!(f, " + {}", c)
+        }
+    }
+}
+
+/// How an expression compares to zero.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rel {
+    Eq,
+    Ne,
+    Lt,
+    Ge
+}
+
+/// `expr <rel> 0`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraint {
+    pub expr: Linear,
+    pub rel: Rel
+}
+
+impl Constraint {
+    pub fn holds(&self, values: &[isize]) -> bool {
+        let value = self.expr.eval(values);
+        match self.rel {
+            Rel::Eq => value == 0,
+            Rel::Ne => value != 0,
+            Rel::Lt => value < 0,
+            Rel::Ge => value >= 0
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let rel = match self.rel {
+            Rel::Eq => "==",
+            Rel::Ne => "!=",
+            Rel::Lt => "<",
+            Rel::Ge => ">="
+        };
+        write!(f, "{} {} 0", self.expr, rel)
+    }
+}
+
+// domains split before the solver gives up
+const SOLVER_BUDGET: usize = 100_000;
+
+enum Search {
+    Found(Vec<isize>),
+    Unsatisfiable,
+    GaveUp
+}
+
+fn div_floor(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    if (a % b != 0) && ((a < 0) != (b < 0)) { q - 1 } else { q }
+}
+
+fn div_ceil(a: i128, b: i128) -> i128 {
+    -div_floor(-a, b)
+}
+
+// the range of `expr` over the domains, leaving out term `skip`. Saturates
+// rather than overflowing, which only matters for absurdly large domains.
+fn bounds(expr: &Linear, domains: &[(i128, i128)], skip: Option<usize>) -> (i128, i128) {
+    expr.terms.iter().enumerate()
+        .filter(|&(i, _)| Some(i) != skip)
+        .fold((expr.constant as i128, expr.constant as i128), |(low, high), (_, &(var, c))| {
+            let (a, b) = ((c as i128).saturating_mul(domains[var].0), (c as i128).saturating_mul(domains[var].1));
+            (low.saturating_add(a.min(b)), high.saturating_add(a.max(b)))
+        })
+}
+
+// narrows the domains to what the constraints allow, or returns false if
+// one of them is left empty
+fn propagate(constraints: &[Constraint], domains: &mut [(i128, i128)]) -> bool {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for constraint in constraints {
+            let (expr, terms) = (&constraint.expr, &constraint.expr.terms);
+            let (low, high) = bounds(expr, domains, None);
+            let (target_low, target_high) = match constraint.rel {
+                Rel::Eq => (0, 0),
+                Rel::Lt => (i128::MIN / 4, -1),
+                Rel::Ge => (0, i128::MAX / 4),
+                Rel::Ne => {
+                    if low == high && low == 0 {
+                        return false;
+                    }
+                    let unfixed = terms.iter().enumerate()
+                        .filter(|(_, &(var, _))| domains[var].0 != domains[var].1)
+                        .collect::<Vec<_>>();
+                    // only a single variable can have a value ruled out
+                    if let [(i, &(var, c))] = unfixed[..] {
+                        let (rest, _) = bounds(expr, domains, Some(i));
+                        if rest % c as i128 == 0 {
+                            let excluded = -rest / c as i128;
+                            let domain = &mut domains[var];
+                            if domain.0 == excluded {
+                                domain.0 += 1;
+                                changed = true;
+                            } else if domain.1 == excluded {
+                                domain.1 -= 1;
+                                changed = true;
+                            }
+                        }
+                    }
+                    continue;
+                }
+            };
+            if high < target_low || low > target_high {
+                return false;
+            }
+            for (i, &(var, c)) in terms.iter().enumerate() {
+                let (rest_low, rest_high) = bounds(expr, domains, Some(i));
+                let c = c as i128;
+                // c * x has to be in [target_low - rest_high, target_high - rest_low]
+                let (from, to) = (target_low.saturating_sub(rest_high), target_high.saturating_sub(rest_low));
+                let (low, high) = if c > 0 {
+                    (div_ceil(from, c), div_floor(to, c))
+                } else {
+                    (div_ceil(to, c), div_floor(from, c))
+                };
+                let domain = &mut domains[var];
+                if low > domain.0 || high < domain.1 {
+                    *domain = (domain.0.max(low), domain.1.min(high));
+                    if domain.0 > domain.1 {
+                        return false;
+                    }
+                    changed = true;
+                }
+            }
+        }
+    }
+    true
+}
+
+fn search(constraints: &[Constraint], domains: &mut [(i128, i128)], budget: &mut usize) -> Search {
+    if !propagate(constraints, domains) {
+        return Search::Unsatisfiable;
+    }
+    let var = (0..domains.len())
+        .filter(|&var| domains[var].0 < domains[var].1)
+        .min_by_key(|&var| domains[var].1 - domains[var].0);
+    let var = match var {
+        Some(var) => var,
+        None => {
+            let values = domains.iter().map(|&(value, _)| value as isize).collect::<Vec<_>>();
+            return match constraints.iter().all(|c| c.holds(&values)) {
+                true => Search::Found(values),
+                false => Search::Unsatisfiable
+            };
+        }
+    };
+    if *budget == 0 {
+        return Search::GaveUp;
+    }
+    *budget -= 1;
+
+    let (low, high) = domains[var];
+    let mid = div_floor(low + high, 2);
+    let mut gave_up = false;
+    for half in [(low, mid), (mid + 1, high)] {
+        let mut domains = domains.to_vec();
+        domains[var] = half;
+        match search(constraints, &mut domains, budget) {
+            Search::Found(values) => return Search::Found(values),
+            Search::GaveUp => gave_up = true,
+            Search::Unsatisfiable => ()
+        }
+    }
+    if gave_up { Search::GaveUp } else { Search::Unsatisfiable }
+}
+
+/// Finds values for variables `0..domains.len()`, each within its inclusive
+/// domain, that satisfy every constraint. `None` if there aren't any, or
+/// they couldn't be found in a reasonable time.
+pub fn solve(constraints: &[Constraint], domains: &[(isize, isize)]) -> Option<Vec<isize>> {
+    let mut domains = domains.iter().map(|&(low, high)| (low as i128, high as i128)).collect::<Vec<_>>();
+    match search(constraints, &mut domains, &mut { SOLVER_BUDGET }) {
+        Search::Found(values) => Some(values),
+        _ => None
+    }
+}
+
+// like `solve`, but a search that gives up counts as satisfiable, so paths
+// are only pruned when they are certainly impossible
+fn feasible(constraints: &[Constraint], domains: &[(isize, isize)]) -> bool {
+    let mut domains = domains.iter().map(|&(low, high)| (low as i128, high as i128)).collect::<Vec<_>>();
+    !matches!(search(constraints, &mut domains, &mut { SOLVER_BUDGET }), Search::Unsatisfiable)
+}
+
+/// Why a path stopped being explored.
+#[derive(Debug, Clone, PartialEq)]
+pub enum End {
+    Halted,
+    StepLimit,
+    /// An invalid opcode, mode or address.
+    Invalid { pc: usize },
+    /// Something the symbolic machine can't follow, such as multiplying two
+    /// inputs or jumping to an address computed from one.
+    Unsupported { pc: usize, reason: &'static str },
+    /// The goal was met on this path.
+    Goal
+}
+
+/// One way through the program, along with what has to hold for the inputs
+/// to take it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    pub constraints: Vec<Constraint>,
+    pub outputs: Vec<Linear>,
+    /// Number of `in` instructions executed.
+    pub inputs: usize,
+    pub steps: usize,
+    pub end: End
+}
+
+/// What inputs are wanted for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Goal {
+    /// Executing the instruction at an address.
+    Reach(usize),
+    /// Some `out` instruction printing a value.
+    Output(isize),
+    /// A memory cell holding a value when the program halts.
+    Memory { addr: usize, value: isize }
+}
+
+/// Concrete values that meet a goal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solution {
+    /// Values for the `in` instructions, in order.
+    pub inputs: Vec<isize>,
+    /// Values for the memory cells made symbolic.
+    pub memory: Vec<(usize, isize)>,
+    pub path: Path
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    /// Instructions executed on any one path.
+    pub steps: usize,
+    /// Paths explored in total.
+    pub paths: usize,
+    /// Words of memory on any one path.
+    pub memory: usize
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits { steps: 10_000, paths: 1000, memory: 1 << 20 }
+    }
+}
+
+#[derive(Clone)]
+struct State {
+    pc: usize,
+    relbase: isize,
+    mem: Vec<isize>,
+    // cells holding something other than a constant, which shadow `mem`.
+    // `None` is a value that can't be known, such as one read through an
+    // address computed from an input.
+    symbolic: BTreeMap<usize, Option<Linear>>,
+    constraints: Vec<Constraint>,
+    outputs: Vec<Linear>,
+    inputs: usize,
+    steps: usize
+}
+
+impl State {
+    fn read(&self, addr: usize) -> Option<Linear> {
+        match self.symbolic.get(&addr) {
+            Some(value) => value.clone(),
+            None => Some(Linear::from(self.mem.get(addr).cloned().unwrap_or(0)))
+        }
+    }
+
+    fn write(&mut self, addr: usize, value: Option<Linear>, limit: usize) -> Result<(), End> {
+        if addr >= limit {
+            return Err(End::Unsupported { pc: self.pc, reason: "memory limit" });
+        }
+        match value.as_ref().and_then(Linear::as_constant) {
+            Some(value) => {
+                self.symbolic.remove(&addr);
+                if addr >= self.mem.len() {
+                    self.mem.resize(addr + 1, 0);
+                }
+                self.mem[addr] = value;
+            },
+            None => {
+                self.symbolic.insert(addr, value);
+            }
+        }
+        Ok(())
+    }
+
+    // a word that has to be concrete, such as an instruction or parameter
+    fn word(&self, addr: usize) -> Result<isize, End> {
+        self.read(addr).and_then(|value| value.as_constant())
+            .ok_or(End::Unsupported { pc: self.pc, reason: "symbolic code" })
+    }
+
+    fn known(&self, value: Option<Linear>) -> Result<Linear, End> {
+        value.ok_or(End::Unsupported { pc: self.pc, reason: "unknown value" })
+    }
+
+    fn addr(&self, addr: isize) -> Result<usize, End> {
+        addr.try_into().map_err(|_| End::Invalid { pc: self.pc })
+    }
+
+    fn pos(&self, code: isize, pos: usize) -> Result<usize, End> {
+        let param = self.word(self.pc + pos + 1)?;
+        match Mode::decode(code, pos).map_err(|_| End::Invalid { pc: self.pc })? {
+            Mode::Position => self.addr(param),
+            Mode::Immediate => Err(End::Invalid { pc: self.pc }),
+            Mode::Relative => self.addr(self.relbase.checked_add(param).ok_or(End::Invalid { pc: self.pc })?)
+        }
+    }
+
+    // a parameter computed from an input only makes the value unknown, since
+    // it may never be used
+    fn param(&self, code: isize, pos: usize) -> Result<Option<Linear>, End> {
+        let param = self.read(self.pc + pos + 1);
+        match Mode::decode(code, pos) {
+            Ok(Mode::Immediate) => Ok(param),
+            _ if param.as_ref().and_then(Linear::as_constant).is_none() => Ok(None),
+            _ => Ok(self.read(self.pos(code, pos)?))
+        }
+    }
+
+    fn constrained(&self, expr: Linear, rel: Rel) -> State {
+        let mut state = self.clone();
+        state.constraints.push(Constraint { expr, rel });
+        state
+    }
+
+    fn overflow(&self) -> End {
+        End::Unsupported { pc: self.pc, reason: "overflow" }
+    }
+
+    // executes an instruction, returning the other side of a branch that
+    // depends on the inputs
+    fn execute(&mut self, limits: &Limits, vars: usize) -> Result<Option<State>, End> {
+        let code = self.word(self.pc)?;
+        let opcode = Opcode::decode(code).map_err(|_| End::Invalid { pc: self.pc })?;
+        match opcode {
+            Opcode::Add | Opcode::Mul => {
+                let value = match (opcode, self.param(code, 0)?, self.param(code, 1)?) {
+                    (Opcode::Add, Some(a), Some(b)) => Some(a.add(&b).ok_or_else(|| self.overflow())?),
+                    (_, Some(a), Some(b)) => {
+                        let product = match (a.as_constant(), b.as_constant()) {
+                            (Some(a), _) => b.scale(a),
+                            (_, Some(b)) => a.scale(b),
+                            _ => return Err(End::Unsupported { pc: self.pc, reason: "nonlinear" })
+                        };
+                        Some(product.ok_or_else(|| self.overflow())?)
+                    },
+                    _ => None
+                };
+                let out = self.pos(code, 2)?;
+                self.write(out, value, limits.memory)?;
+                self.pc += 4;
+            },
+            Opcode::In => {
+                let out = self.pos(code, 0)?;
+                self.write(out, Some(Linear::var(vars + self.inputs)), limits.memory)?;
+                self.inputs += 1;
+                self.pc += 2;
+            },
+            Opcode::Out => {
+                let value = self.param(code, 0)?;
+                self.outputs.push(self.known(value)?);
+                self.pc += 2;
+            },
+            Opcode::Jnz | Opcode::Jz => {
+                let cond = self.known(self.param(code, 0)?)?;
+                let target = self.known(self.param(code, 1)?)?.as_constant()
+                    .ok_or(End::Unsupported { pc: self.pc, reason: "symbolic jump" })?;
+                let target = target.try_into().map_err(|_| End::Invalid { pc: self.pc })?;
+                let taken = match (opcode, cond.as_constant()) {
+                    (Opcode::Jnz, Some(cond)) => cond != 0,
+                    (_, Some(cond)) => cond == 0,
+                    (_, None) => {
+                        let (taken, not_taken) = if opcode == Opcode::Jnz { (Rel::Ne, Rel::Eq) } else { (Rel::Eq, Rel::Ne) };
+                        let mut other = self.constrained(cond.clone(), not_taken);
+                        other.pc += 3;
+                        self.constraints.push(Constraint { expr: cond, rel: taken });
+                        self.pc = target;
+                        return Ok(Some(other));
+                    }
+                };
+                if taken {
+                    self.pc = target;
+                } else {
+                    self.pc += 3;
+                }
+            },
+            Opcode::Lt | Opcode::Eq => {
+                let (a, b) = (self.known(self.param(code, 0)?)?, self.known(self.param(code, 1)?)?);
+                let diff = a.sub(&b).ok_or_else(|| self.overflow())?;
+                let out = self.pos(code, 2)?;
+                self.pc += 4;
+                let value = match diff.as_constant() {
+                    Some(diff) if opcode == Opcode::Lt => (diff < 0) as isize,
+                    Some(diff) => (diff == 0) as isize,
+                    None => {
+                        let (yes, no) = if opcode == Opcode::Lt { (Rel::Lt, Rel::Ge) } else { (Rel::Eq, Rel::Ne) };
+                        let mut other = self.constrained(diff.clone(), no);
+                        other.write(out, Some(Linear::from(0)), limits.memory)?;
+                        self.constraints.push(Constraint { expr: diff, rel: yes });
+                        self.write(out, Some(Linear::from(1)), limits.memory)?;
+                        return Ok(Some(other));
+                    }
+                };
+                self.write(out, Some(Linear::from(value)), limits.memory)?;
+            },
+            Opcode::Arb => {
+                let offset = self.known(self.param(code, 0)?)?.as_constant()
+                    .ok_or(End::Unsupported { pc: self.pc, reason: "symbolic relative base" })?;
+                self.relbase = self.relbase.checked_add(offset).ok_or_else(|| self.overflow())?;
+                self.pc += 2;
+            },
+            Opcode::Hlt => return Err(End::Halted)
+        }
+        Ok(None)
+    }
+}
+
+/// Runs a program on symbolic inputs, following both sides of every branch
+/// that depends on them.
+pub struct Explorer {
+    program: Vec<isize>,
+    // memory cells replaced by variables, with their domains
+    cells: Vec<(usize, (isize, isize))>,
+    input_domain: (isize, isize),
+    limits: Limits
+}
+
+impl Explorer {
+    pub fn new(program: &[isize]) -> Explorer {
+        Explorer {
+            program: program.to_vec(),
+            cells: vec![],
+            input_domain: (-1_000_000, 1_000_000),
+            limits: Limits::default()
+        }
+    }
+
+    /// Makes the cell at `addr` a variable between `low` and `high`, as with
+    /// day 2's noun and verb.
+    pub fn symbolic_cell(&mut self, addr: usize, low: isize, high: isize) -> &mut Explorer {
+        self.cells.push((addr, (low, high)));
+        self
+    }
+
+    /// The range input values are looked for in.
+    pub fn input_domain(&mut self, low: isize, high: isize) -> &mut Explorer {
+        self.input_domain = (low, high);
+        self
+    }
+
+    pub fn limits(&mut self, limits: Limits) -> &mut Explorer {
+        self.limits = limits;
+        self
+    }
+
+    /// Variables `0..n` are the symbolic cells in the order they were
+    /// added, followed by the inputs in the order they are read.
+    fn domains(&self, inputs: usize) -> Vec<(isize, isize)> {
+        self.cells.iter().map(|&(_, domain)| domain)
+            .chain(std::iter::repeat_n(self.input_domain, inputs))
+            .collect()
+    }
+
+    fn solution(&self, state: &State, extra: Option<Constraint>, end: End) -> Option<Solution> {
+        let mut constraints = state.constraints.clone();
+        constraints.extend(extra);
+        let values = solve(&constraints, &self.domains(state.inputs))?;
+        Some(Solution {
+            inputs: values[self.cells.len()..].to_vec(),
+            memory: self.cells.iter().zip(&values).map(|(&(addr, _), &value)| (addr, value)).collect(),
+            path: Path {
+                constraints,
+                outputs: state.outputs.clone(),
+                inputs: state.inputs,
+                steps: state.steps,
+                end
+            }
+        })
+    }
+
+    // the goal being met at the current point of `state`, checked before
+    // each instruction is executed
+    fn check(&self, state: &State, goal: Goal) -> Option<Solution> {
+        let code = state.read(state.pc)?.as_constant()?;
+        let opcode = Opcode::decode(code).ok()?;
+        let equals = |value: Linear, wanted: isize| Some(Constraint { expr: value.sub(&Linear::from(wanted))?, rel: Rel::Eq });
+        match goal {
+            Goal::Reach(addr) if addr == state.pc => self.solution(state, None, End::Goal),
+            Goal::Output(wanted) if opcode == Opcode::Out => {
+                let value = state.param(code, 0).ok()??;
+                self.solution(state, Some(equals(value, wanted)?), End::Goal)
+            },
+            Goal::Memory { addr, value } if opcode == Opcode::Hlt => {
+                self.solution(state, Some(equals(state.read(addr)?, value)?), End::Goal)
+            },
+            _ => None
+        }
+    }
+
+    fn run(&self, goal: Option<Goal>) -> (Vec<Path>, Option<Solution>) {
+        let mut initial = State {
+            pc: 0,
+            relbase: 0,
+            mem: self.program.clone(),
+            symbolic: BTreeMap::new(),
+            constraints: vec![],
+            outputs: vec![],
+            inputs: 0,
+            steps: 0
+        };
+        for (var, &(addr, _)) in self.cells.iter().enumerate() {
+            initial.symbolic.insert(addr, Some(Linear::var(var)));
+        }
+
+        let mut paths = vec![];
+        // depth first, so a path is finished before the next is started
+        let mut pending = vec![initial];
+        'paths: while let Some(mut state) = pending.pop() {
+            if paths.len() >= self.limits.paths {
+                break;
+            }
+            let end = loop {
+                if let Some(solution) = goal.and_then(|goal| self.check(&state, goal)) {
+                    return (paths, Some(solution));
+                }
+                if state.steps >= self.limits.steps {
+                    break End::StepLimit;
+                }
+                let feasible = |s: &State| feasible(&s.constraints, &self.domains(s.inputs));
+                match state.execute(&self.limits, self.cells.len()) {
+                    Ok(None) => state.steps += 1,
+                    Ok(Some(mut other)) => {
+                        // sides of a branch the inputs can't take are dropped
+                        // rather than counted as paths
+                        other.steps += 1;
+                        if feasible(&other) {
+                            pending.push(other);
+                        }
+                        state.steps += 1;
+                        if !feasible(&state) {
+                            continue 'paths;
+                        }
+                    },
+                    Err(end) => break end
+                }
+            };
+            paths.push(Path {
+                constraints: state.constraints,
+                outputs: state.outputs,
+                inputs: state.inputs,
+                steps: state.steps,
+                end
+            });
+        }
+        (paths, None)
+    }
+
+    /// Every path through the program, up to the limits.
+    pub fn paths(&self) -> Vec<Path> {
+        self.run(None).0
+    }
+
+    /// Inputs that meet `goal`, from the first path found where it can be.
+    pub fn solve(&self, goal: Goal) -> Option<Solution> {
+        self.run(Some(goal)).1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::intcode::{Callbacks, Vm};
+
+    fn run(program: &[isize], inputs: &[isize]) -> Vec<isize> {
+        let mut inputs = inputs.iter().cloned();
+        let mut outputs = vec![];
+        Vm::new(program).run(&mut Callbacks::new(|| inputs.next(), |v| outputs.push(v))).unwrap();
+        outputs
+    }
+
+    #[test]
+    fn test_solver() {
+        // 3x + 2y == 12, x < y, y != 3
+        let constraints = [
+            Constraint { expr: Linear { constant: -12, terms: vec![(0, 3), (1, 2)] }, rel: Rel::Eq },
+            Constraint { expr: Linear { constant: 0, terms: vec![(0, 1), (1, -1)] }, rel: Rel::Lt },
+            Constraint { expr: Linear { constant: -3, terms: vec![(1, 1)] }, rel: Rel::Ne }
+        ];
+        assert_eq!(constraints[0].to_string(), "3*x0 + 2*x1 - 12 == 0");
+        let values = solve(&constraints, &[(-100, 100), (-100, 100)]).unwrap();
+        assert!(constraints.iter().all(|c| c.holds(&values)), "{:?}", values);
+        assert_eq!(solve(&constraints, &[(0, 100), (0, 5)]), None);
+    }
+
+    #[test]
+    fn test_goals() {
+        // prints 1 only if 3 * a + b == 20 and a < b, and 2 otherwise
+        let program = assemble("
+                in -> [a]
+                in -> [b]
+                mul [a], #3 -> [t]
+                add [t], [b] -> [t]
+                eq [t], #20 -> [t]
+                jz [t], #no
+                lt [a], [b] -> [t]
+                jz [t], #no
+            yes:
+                out #1
+                hlt
+            no:
+                out #2
+                hlt
+            a: db 0
+            b: db 0
+            t: db 0
+        ").unwrap();
+
+        let mut explorer = Explorer::new(&program);
+        explorer.input_domain(0, 100);
+        let paths = explorer.paths();
+        assert_eq!(paths.len(), 3);
+        assert!(paths.iter().all(|path| path.end == End::Halted && path.inputs == 2));
+
+        let solution = explorer.solve(Goal::Output(1)).unwrap();
+        assert_eq!(run(&program, &solution.inputs), vec![1]);
+        // `no`
+        let solution = explorer.solve(Goal::Reach(29)).unwrap();
+        assert_eq!(run(&program, &solution.inputs), vec![2]);
+        assert_eq!(explorer.solve(Goal::Output(3)), None);
+    }
+
+    #[test]
+    fn test_symbolic_cells() {
+        // day 2 style: the noun and verb are addresses at first, then
+        // [0] = [1] * 1000 + [2] * 3
+        let program = [1,0,0,3,1002,1,1000,3,1002,2,3,17,1,3,17,0,99,0];
+        let mut explorer = Explorer::new(&program);
+        explorer.symbolic_cell(1, 0, 99).symbolic_cell(2, 0, 99);
+        let solution = explorer.solve(Goal::Memory { addr: 0, value: 42_012 }).unwrap();
+        assert_eq!(solution.memory, vec![(1, 42), (2, 4)]);
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::io::Write;
+use crate::extension::Spec;
+use crate::intcode::Opcode;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write
+}
+
+/// The instruction that made an access, either built in or added by an
+/// extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessOp {
+    Builtin(Opcode),
+    Extension(Spec)
+}
+
+impl AccessOp {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            AccessOp::Builtin(opcode) => opcode.mnemonic(),
+            AccessOp::Extension(spec) => spec.mnemonic
+        }
+    }
+}
+
+/// A single read or write of a watched address. For reads `old` and `new`
+/// are the same value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub addr: usize,
+    pub old: isize,
+    pub new: isize,
+    pub pc: usize,
+    pub opcode: AccessOp
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self.kind {
+            AccessKind::Read => write!(f, "read  [{:04}] {}", self.addr, self.new)?,
+            AccessKind::Write => write!(f, "write [{:04}] {} -> {}", self.addr, self.old, self.new)?
+        }
+        write!(f, " at {:04} ({})", self.pc, self.opcode.mnemonic().to_uppercase())
+    }
+}
+
+/// Receives every access to a watched address.
+pub trait TraceSink {
+    fn record(&mut self, access: &Access);
+}
+
+impl TraceSink for Vec<Access> {
+    fn record(&mut self, access: &Access) {
+        self.push(access.clone());
+    }
+}
+
+/// Writes each access as a line of text.
+pub struct TraceWriter<W: Write>(pub W);
+
+impl<W: Write> TraceSink for TraceWriter<W> {
+    fn record(&mut self, access: &Access) {
+        // tracing is best effort, a failing writer shouldn't stop the machine
+        let _ = writeln!(self.0, "{}", access);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::intcode::{Callbacks, Vm};
+
+    #[test]
+    fn test_watchpoints() {
+        // [9] = [9] + [10], then outputs [9]
+        let mut vm = Vm::new(&[1,9,10,9,4,9,99,0,0,3,4]);
+        let sink = Arc::new(Mutex::new(vec![]));
+        vm.set_trace_sink(sink.clone());
+        vm.watch(9..=9);
+        vm.run(&mut Callbacks::new(|| None, |_| ())).unwrap();
+
+        let trace = sink.lock().unwrap();
+        assert_eq!(trace.iter().map(|a| a.to_string()).collect::<Vec<_>>(), vec![
+            "read  [0009] 3 at 0000 (ADD)",
+            "write [0009] 3 -> 7 at 0000 (ADD)",
+            "read  [0009] 7 at 0004 (OUT)"
+        ]);
+    }
+
+    #[test]
+    fn test_writer_sink() {
+        let mut vm = Vm::new(&[3,5,99]);
+        let sink = Arc::new(Mutex::new(TraceWriter(vec![])));
+        vm.set_trace_sink(sink.clone());
+        vm.watch(0..=100);
+        let mut input = Some(12);
+        vm.run(&mut Callbacks::new(|| input.take(), |_| ())).unwrap();
+        assert_eq!(String::from_utf8(sink.lock().unwrap().0.clone()).unwrap(), "write [0005] 0 -> 12 at 0000 (IN)\n");
+    }
+}
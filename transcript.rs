@@ -0,0 +1,236 @@
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use crate::intcode::{self, Callbacks, Limits, Vm};
+
+const MAGIC: &str = "intcode-transcript";
+
+#[derive(Debug)]
+pub enum Error {
+    Intcode(intcode::Error),
+    InvalidTranscript(String),
+    Diverged(Divergence)
+}
+
+impl From<intcode::Error> for Error {
+    fn from(err: intcode::Error) -> Error {
+        Error::Intcode(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Intcode(err.into())
+    }
+}
+
+/// A value crossing the machine's I/O boundary, with the number of steps
+/// executed before it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Entry {
+    Input { step: usize, value: isize },
+    Output { step: usize, value: isize },
+    Halt { step: usize }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Entry::Input { step, value } => write!(f, "in {} {}", step, value),
+            Entry::Output { step, value } => write!(f, "out {} {}", step, value),
+            Entry::Halt { step } => write!(f, "halt {}", step)
+        }
+    }
+}
+
+/// The first point where a replay did something other than the recording.
+/// `None` means that side had stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub index: usize,
+    pub expected: Option<Entry>,
+    pub actual: Option<Entry>
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let show = |entry: Option<Entry>| entry.map_or("nothing".to_string(), |e| e.to_string());
+        write!(f, "entry {}: expected {}, got {}", self.index, show(self.expected), show(self.actual))
+    }
+}
+
+/// Everything a machine read and wrote, kept by a `Vm` with recording turned
+/// on. Saved as text, one entry per line after a version header, and the
+/// step count the recording ended at:
+///
+/// ```text
+/// intcode-transcript 1
+/// in 0 5
+/// out 3 10
+/// halt 4
+/// end 4
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transcript {
+    pub entries: Vec<Entry>,
+    /// Steps executed when the recording ended.
+    pub steps: usize
+}
+
+impl Transcript {
+    pub const VERSION: u32 = 1;
+
+    pub fn inputs(&self) -> impl Iterator<Item = isize> + '_ {
+        self.entries.iter().filter_map(|entry| match entry {
+            Entry::Input { value, .. } => Some(*value),
+            _ => None
+        })
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = isize> + '_ {
+        self.entries.iter().filter_map(|entry| match entry {
+            Entry::Output { value, .. } => Some(*value),
+            _ => None
+        })
+    }
+
+    /// Runs `program` on the recorded inputs, up to where the recording
+    /// ended, and checks that it does exactly the same I/O at the same steps.
+    pub fn replay(&self, program: &[isize]) -> Result<(), Error> {
+        let mut vm = Vm::new(program);
+        vm.set_recording(true);
+        // a step past the end, so that waiting for input there still counts
+        vm.set_limits(Limits { steps: Some(self.steps + 1), ..Limits::default() });
+        let mut inputs = self.inputs().collect::<Vec<_>>();
+        inputs.reverse();
+        match vm.run(&mut Callbacks::new(|| inputs.pop(), |_| ())) {
+            Ok(_) | Err(intcode::Error::StepLimitExceeded { .. }) => (),
+            Err(err) => return Err(err.into())
+        }
+
+        let actual = &vm.transcript().unwrap().entries;
+        let end = self.entries.len().max(actual.len());
+        let index = match (0..end).find(|&i| self.entries.get(i) != actual.get(i)) {
+            Some(index) => index,
+            None => return Ok(())
+        };
+        Err(Error::Diverged(Divergence {
+            index,
+            expected: self.entries.get(index).cloned(),
+            actual: actual.get(index).cloned()
+        }))
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> Result<(), Error> {
+        writeln!(w, "{} {}", MAGIC, Transcript::VERSION)?;
+        for entry in &self.entries {
+            writeln!(w, "{}", entry)?;
+        }
+        writeln!(w, "end {}", self.steps)?;
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: R) -> Result<Transcript, Error> {
+        let invalid = |msg: String| Error::InvalidTranscript(msg);
+        let mut lines = BufReader::new(r).lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [MAGIC, version] if version.parse() == Ok(Transcript::VERSION) => (),
+            [MAGIC, version] => return Err(invalid(format!("unsupported version {}", version))),
+            _ => return Err(invalid("not a transcript".to_string()))
+        }
+
+        let mut transcript = Transcript::default();
+        let mut end = None;
+        for line in lines {
+            let line = line?;
+            let parts = line.split_whitespace()
+                .map(|part| part.parse::<isize>().ok())
+                .collect::<Vec<_>>();
+            let number = |i: usize| parts.get(i).cloned().flatten()
+                .ok_or_else(|| invalid(format!("bad line: {}", line)));
+            let step = || number(1).map(|step| step as usize);
+            match line.split_whitespace().next() {
+                Some("in") => transcript.entries.push(Entry::Input { step: step()?, value: number(2)? }),
+                Some("out") => transcript.entries.push(Entry::Output { step: step()?, value: number(2)? }),
+                Some("halt") => transcript.entries.push(Entry::Halt { step: step()? }),
+                Some("end") => end = Some(step()?),
+                None => (),
+                Some(_) => return Err(invalid(format!("bad line: {}", line)))
+            }
+        }
+        transcript.steps = end.ok_or_else(|| invalid("missing end".to_string()))?;
+        Ok(transcript)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        self.write(fs::File::create(path)?)
+    }
+
+    pub fn load(path: &str) -> Result<Transcript, Error> {
+        Transcript::read(fs::File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::intcode2::{IntcodeComputer, Signal};
+
+    // outputs each input times two until it reads a 0
+    const DOUBLER: [isize; 16] = [3,15,1006,15,14,1002,15,2,15,4,15,1105,1,0,99,0];
+
+    fn record(program: &[isize], inputs: &[isize]) -> Transcript {
+        let mut comp = IntcodeComputer::new(program);
+        comp.vm_mut().set_recording(true);
+        inputs.iter().for_each(|&v| comp.send(v));
+        while let Ok(Signal::Output(_)) = comp.run() {}
+        comp.vm().transcript().unwrap().clone()
+    }
+
+    #[test]
+    fn test_record_and_replay() -> Result<(), Error> {
+        let transcript = record(&DOUBLER, &[5, 7, 0]);
+        let mut buffer = vec![];
+        transcript.write(&mut buffer)?;
+        assert_eq!(String::from_utf8(buffer.clone()).unwrap(), "\
+intcode-transcript 1
+in 0 5
+out 3 10
+in 5 7
+out 8 14
+in 10 0
+halt 12
+end 12
+");
+        assert_eq!(Transcript::read(&buffer[..])?, transcript);
+        transcript.replay(&DOUBLER)?;
+
+        // doubling became tripling
+        let mut changed = DOUBLER;
+        changed[7] = 3;
+        match transcript.replay(&changed) {
+            Err(Error::Diverged(divergence)) => assert_eq!(divergence.to_string(), "entry 1: expected out 3 10, got out 3 15"),
+            other => panic!("expected a divergence, got {:?}", other)
+        }
+
+        // stops where the original was still waiting for input
+        let transcript = record(&DOUBLER, &[5]);
+        assert_eq!(transcript.steps, 5);
+        transcript.replay(&DOUBLER)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_halt() -> Result<(), Error> {
+        let transcript = record(&[104,1,99], &[]);
+        assert_eq!(transcript.entries, vec![Entry::Output { step: 0, value: 1 }, Entry::Halt { step: 1 }]);
+        assert_eq!(transcript.steps, 1);
+        transcript.replay(&[104,1,99])?;
+        match transcript.replay(&[104,1,104,2,99]) {
+            Err(Error::Diverged(divergence)) => assert_eq!(divergence.index, 1),
+            other => panic!("expected a divergence, got {:?}", other)
+        }
+        Ok(())
+    }
+}
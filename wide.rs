@@ -0,0 +1,450 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::num::ParseIntError;
+use std::ops::{Add, Mul};
+use crate::intcode::{Error, Mode, Opcode, Step};
+
+/// A word of a `WideVm`'s memory.
+pub trait Word: Clone + PartialEq + PartialOrd + fmt::Debug + fmt::Display {
+    fn from_isize(value: isize) -> Self;
+    fn to_isize(&self) -> Option<isize>;
+    /// `None` if the sum doesn't fit in a word.
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    /// `None` if the product doesn't fit in a word.
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+    fn parse(s: &str) -> Result<Self, ParseIntError>;
+}
+
+impl Word for i128 {
+    fn from_isize(value: isize) -> i128 {
+        value as i128
+    }
+
+    fn to_isize(&self) -> Option<isize> {
+        isize::try_from(*self).ok()
+    }
+
+    fn checked_add(&self, other: &i128) -> Option<i128> {
+        i128::checked_add(*self, *other)
+    }
+
+    fn checked_mul(&self, other: &i128) -> Option<i128> {
+        i128::checked_mul(*self, *other)
+    }
+
+    fn parse(s: &str) -> Result<i128, ParseIntError> {
+        s.parse()
+    }
+}
+
+/// An integer of any size, with only what Intcode needs: adding,
+/// multiplying and comparing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    // base 2^32, least significant first, without leading zeros
+    digits: Vec<u32>
+}
+
+impl BigInt {
+    fn new(negative: bool, mut digits: Vec<u32>) -> BigInt {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        BigInt { negative: negative && !digits.is_empty(), digits }
+    }
+
+    pub fn from_i128(value: i128) -> BigInt {
+        let mut magnitude = value.unsigned_abs();
+        let mut digits = vec![];
+        while magnitude > 0 {
+            digits.push(magnitude as u32);
+            magnitude >>= 32;
+        }
+        BigInt::new(value < 0, digits)
+    }
+
+    pub fn to_i128(&self) -> Option<i128> {
+        if self.digits.len() > 4 {
+            return None;
+        }
+        let magnitude = self.digits.iter().rev().fold(0_u128, |acc, &d| acc << 32 | d as u128);
+        if self.negative {
+            0_i128.checked_sub_unsigned(magnitude)
+        } else {
+            i128::try_from(magnitude).ok()
+        }
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0_u64;
+    for i in 0..a.len().max(b.len()) {
+        let total = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        sum.push(total as u32);
+        carry = total >> 32;
+    }
+    sum.push(carry as u32);
+    sum
+}
+
+// `a - b`, where `a` is at least `b`
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0_i64;
+    for (i, &d) in a.iter().enumerate() {
+        let mut total = d as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = (total < 0) as i64;
+        total += borrow << 32;
+        difference.push(total as u32);
+    }
+    difference
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_magnitude(&self.digits, &other.digits));
+        }
+        match cmp_magnitude(&self.digits, &other.digits) {
+            Ordering::Less => BigInt::new(other.negative, sub_magnitude(&other.digits, &self.digits)),
+            _ => BigInt::new(self.negative, sub_magnitude(&self.digits, &other.digits))
+        }
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut product = vec![0_u32; self.digits.len() + other.digits.len()];
+        for (i, &a) in self.digits.iter().enumerate() {
+            let mut carry = 0_u64;
+            for (j, &b) in other.digits.iter().enumerate() {
+                let total = product[i + j] as u64 + a as u64 * b as u64 + carry;
+                product[i + j] = total as u32;
+                carry = total >> 32;
+            }
+            product[i + other.digits.len()] = carry as u32;
+        }
+        BigInt::new(self.negative != other.negative, product)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.digits, &other.digits),
+            (true, true) => cmp_magnitude(&other.digits, &self.digits)
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// the largest power of ten in a digit
+const DECIMAL: u64 = 1_000_000_000;
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        // nine decimal digits at a time, least significant first
+        let mut chunks = vec![];
+        let mut digits = self.digits.clone();
+        while !digits.is_empty() {
+            let mut remainder = 0;
+            for d in digits.iter_mut().rev() {
+                let current = remainder << 32 | *d as u64;
+                *d = (current / DECIMAL) as u32;
+                remainder = current % DECIMAL;
+            }
+            chunks.push(remainder);
+            while digits.last() == Some(&0) {
+                digits.pop();
+            }
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap_or(0))?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Word for BigInt {
+    fn from_isize(value: isize) -> BigInt {
+        BigInt::from_i128(value as i128)
+    }
+
+    fn to_isize(&self) -> Option<isize> {
+        self.to_i128().and_then(|value| isize::try_from(value).ok())
+    }
+
+    fn checked_add(&self, other: &BigInt) -> Option<BigInt> {
+        Some(self + other)
+    }
+
+    fn checked_mul(&self, other: &BigInt) -> Option<BigInt> {
+        Some(self * other)
+    }
+
+    fn parse(s: &str) -> Result<BigInt, ParseIntError> {
+        let (negative, magnitude) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s))
+        };
+        if magnitude.is_empty() || !magnitude.bytes().all(|b| b.is_ascii_digit()) {
+            // the same error parsing any other integer would give
+            return Err(s.parse::<u8>().unwrap_err());
+        }
+        let mut value = BigInt::new(false, vec![]);
+        for chunk in magnitude.as_bytes().chunks(9) {
+            let chunk = std::str::from_utf8(chunk).unwrap();
+            let scale = BigInt::from_i128(10_i128.pow(chunk.len() as u32));
+            value = &(&value * &scale) + &BigInt::from_i128(chunk.parse()?);
+        }
+        Ok(BigInt::new(negative, value.digits))
+    }
+}
+
+pub fn read_program<W: Word>(program: &str) -> Result<Vec<W>, Error> {
+    fs::read_to_string(program)?.split(',').map(|x| Ok(W::parse(x.trim())?)).collect()
+}
+
+// a word as an `isize` for an error, going as far as it can if it's bigger
+fn saturate<W: Word>(word: &W) -> isize {
+    word.to_isize().unwrap_or(if *word < W::from_isize(0) { isize::MIN } else { isize::MAX })
+}
+
+/// Runs the built-in instruction set with words wider than an `isize`, such
+/// as `i128` or `BigInt`, for programs whose values outgrow 64 bits. Adding
+/// or multiplying past what a word holds is an `ArithmeticOverflow`.
+/// Extensions, limits, tracing and the rest of what `Vm` can do aren't
+/// supported.
+#[derive(Debug, Clone)]
+pub struct WideVm<W> {
+    mem: Vec<W>,
+    // words written past the program
+    far: HashMap<usize, W>,
+    pc: usize,
+    relbase: W,
+    steps: usize
+}
+
+impl<W: Word> WideVm<W> {
+    pub fn new(program: &[W]) -> WideVm<W> {
+        WideVm { mem: program.to_vec(), far: HashMap::new(), pc: 0, relbase: W::from_isize(0), steps: 0 }
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn read(&self, addr: usize) -> W {
+        self.mem.get(addr).or_else(|| self.far.get(&addr)).cloned().unwrap_or_else(|| W::from_isize(0))
+    }
+
+    fn write(&mut self, addr: usize, value: W) {
+        match self.mem.get_mut(addr) {
+            Some(word) => *word = value,
+            None => {
+                self.far.insert(addr, value);
+            }
+        }
+    }
+
+    fn addr(&self, addr: &W) -> Result<usize, Error> {
+        addr.to_isize().and_then(|a| usize::try_from(a).ok())
+            .ok_or(Error::IndexOutOfBounds { pc: self.pc, addr: saturate(addr) })
+    }
+
+    fn relative(&self, offset: &W) -> Result<usize, Error> {
+        self.addr(&self.relbase.checked_add(offset).ok_or(Error::ArithmeticOverflow { pc: self.pc })?)
+    }
+
+    fn pos(&self, code: isize, pos: usize) -> Result<usize, Error> {
+        let param = self.read(self.pc + pos + 1);
+        match Mode::decode(code, pos)? {
+            Mode::Position => self.addr(&param),
+            Mode::Immediate => Err(Error::InvalidParameterMode(1)),
+            Mode::Relative => self.relative(&param)
+        }
+    }
+
+    fn param(&self, code: isize, pos: usize) -> Result<W, Error> {
+        let param = self.read(self.pc + pos + 1);
+        match Mode::decode(code, pos)? {
+            Mode::Position => Ok(self.read(self.addr(&param)?)),
+            Mode::Immediate => Ok(param),
+            Mode::Relative => Ok(self.read(self.relative(&param)?))
+        }
+    }
+
+    fn jump_target(&self, code: isize) -> Result<usize, Error> {
+        self.param(code, 1)?.to_isize().and_then(|a| usize::try_from(a).ok())
+            .ok_or(Error::BadValueAtPosition(self.pc + 2))
+    }
+
+    // an arithmetic or comparison instruction writing `f` of its two inputs
+    fn apply<F: Fn(&W, &W) -> Option<W>>(&mut self, code: isize, f: F) -> Result<(), Error> {
+        let out_pos = self.pos(code, 2)?;
+        let value = f(&self.param(code, 0)?, &self.param(code, 1)?).ok_or(Error::ArithmeticOverflow { pc: self.pc })?;
+        self.write(out_pos, value);
+        self.pc += 4;
+        Ok(())
+    }
+
+    /// Executes a single instruction, returning `None` if the machine can
+    /// carry on.
+    pub fn step<I, O>(&mut self, input: &mut I, output: &mut O) -> Result<Option<Step>, Error>
+    where I: FnMut() -> Option<W>,
+          O: FnMut(W)
+    {
+        let word = self.read(self.pc);
+        let code = word.to_isize().ok_or(Error::InvalidOpcode(saturate(&word)))?;
+        let flag = |b: bool| Some(W::from_isize(b as isize));
+        match Opcode::decode(code)? {
+            Opcode::Add => self.apply(code, |a, b| a.checked_add(b))?,
+            Opcode::Mul => self.apply(code, |a, b| a.checked_mul(b))?,
+            Opcode::Lt => self.apply(code, |a, b| flag(a < b))?,
+            Opcode::Eq => self.apply(code, |a, b| flag(a == b))?,
+            Opcode::In => {
+                let out_pos = self.pos(code, 0)?;
+                match input() {
+                    Some(value) => self.write(out_pos, value),
+                    None => return Ok(Some(Step::ExpectingInput))
+                }
+                self.pc += 2;
+            },
+            Opcode::Out => {
+                output(self.param(code, 0)?);
+                self.pc += 2;
+            },
+            Opcode::Jnz => {
+                if self.param(code, 0)? != W::from_isize(0) {
+                    self.pc = self.jump_target(code)?;
+                } else {
+                    self.pc += 3;
+                }
+            },
+            Opcode::Jz => {
+                if self.param(code, 0)? == W::from_isize(0) {
+                    self.pc = self.jump_target(code)?;
+                } else {
+                    self.pc += 3;
+                }
+            },
+            Opcode::Arb => {
+                self.relbase = self.relbase.checked_add(&self.param(code, 0)?)
+                    .ok_or(Error::ArithmeticOverflow { pc: self.pc })?;
+                self.pc += 2;
+            },
+            Opcode::Hlt => return Ok(Some(Step::Halted))
+        }
+        self.steps += 1;
+        Ok(None)
+    }
+
+    /// Runs until the program halts or `input` returns `None`.
+    pub fn run<I, O>(&mut self, mut input: I, mut output: O) -> Result<Step, Error>
+    where I: FnMut() -> Option<W>,
+          O: FnMut(W)
+    {
+        loop {
+            if let Some(step) = self.step(&mut input, &mut output)? {
+                return Ok(step);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::intcode::{self, Callbacks, Vm};
+
+    #[test]
+    fn test_big_int() {
+        let values = [0, 1, -1, 7, -12, 1 << 32, -(1 << 40) + 3, i64::MAX as i128, i128::MAX, i128::MIN, 999_999_999_999];
+        for &a in &values {
+            let big = BigInt::from_i128(a);
+            assert_eq!(big.to_i128(), Some(a));
+            assert_eq!(big.to_string(), a.to_string());
+            assert_eq!(BigInt::parse(&a.to_string()).unwrap(), big);
+            for &b in &values {
+                let other = BigInt::from_i128(b);
+                assert_eq!(big.cmp(&other), a.cmp(&b), "{} <=> {}", a, b);
+                if let Some(sum) = a.checked_add(b) {
+                    assert_eq!((&big + &other).to_i128(), Some(sum), "{} + {}", a, b);
+                }
+                if let Some(product) = a.checked_mul(b) {
+                    assert_eq!((&big * &other).to_i128(), Some(product), "{} * {}", a, b);
+                }
+            }
+        }
+
+        let big = BigInt::parse("-340282366920938463463374607431768211456000").unwrap();
+        assert_eq!(big.to_i128(), None);
+        assert_eq!(big.to_string(), "-340282366920938463463374607431768211456000");
+        assert_eq!(&big * &BigInt::from_i128(-1), BigInt::parse("340282366920938463463374607431768211456000").unwrap());
+        assert!(BigInt::parse("12a").is_err());
+        assert!(BigInt::parse("-").is_err());
+    }
+
+    fn squares<W: Word>(n: isize) -> Result<Vec<W>, Error> {
+        // outputs the input squared, then that squared
+        let program = [3,20,2,20,20,20,4,20,1001,21,1,21,1008,21,2,22,1006,22,2,99,0,0,0]
+            .iter().map(|&w| W::from_isize(w)).collect::<Vec<_>>();
+        let mut input = Some(W::from_isize(n));
+        let mut outputs = vec![];
+        WideVm::new(&program).run(|| input.take(), |v| outputs.push(v))?;
+        Ok(outputs)
+    }
+
+    fn boost<W: Word>() -> Result<Vec<String>, Error> {
+        let mut outputs = vec![];
+        let step = WideVm::new(&read_program::<W>("day_09_input.txt")?).run(|| Some(W::from_isize(1)), |v| outputs.push(v.to_string()))?;
+        assert_eq!(step, Step::Halted);
+        Ok(outputs)
+    }
+
+    #[test]
+    fn test_words() -> Result<(), Error> {
+        // day 9 runs the same however wide the words are
+        let mut outputs = vec![];
+        Vm::new(&intcode::read_program("day_09_input.txt")?).run(&mut Callbacks::new(|| Some(1), |v| outputs.push(v.to_string())))?;
+        assert_eq!(boost::<i128>()?, outputs);
+        assert_eq!(boost::<BigInt>()?, outputs);
+
+        // 2^20 squared fits in an isize, 2^80 in an i128, and 2^160 only in a
+        // BigInt
+        assert_eq!(squares::<i128>(1 << 20)?, vec![1 << 40, 1 << 80]);
+        assert!(matches!(squares::<i128>(1 << 40), Err(Error::ArithmeticOverflow { pc: 2 })));
+        let big = squares::<BigInt>(1 << 40)?;
+        assert_eq!(big.iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+            vec![(1_i128 << 80).to_string(), "1461501637330902918203684832716283019655932542976".to_string()]);
+        Ok(())
+    }
+}